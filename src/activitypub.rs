@@ -0,0 +1,486 @@
+//! ActivityPub outbox export
+//!
+//! Produces an `outbox.json` shaped like the one in a Mastodon account
+//! archive: an `OrderedCollection` of `Create` activities wrapping `Note`
+//! objects.  Posts become top-level notes and the owner's comments become
+//! notes that reply to them, so fediverse import tools can rebuild the
+//! threads.  Other people's comments are quoted in the post's note, since
+//! the outbox can't publish activities in their name.
+
+use serde::Serialize;
+
+use crate::links::parse_link;
+use crate::markdown::transform_image_path;
+use crate::models::{Comment, PostData, Visibility};
+use crate::utils::to_pretty_json;
+
+const ACTIVITYSTREAMS_CONTEXT: &str = "https://www.w3.org/ns/activitystreams";
+const PUBLIC_AUDIENCE: &str = "https://www.w3.org/ns/activitystreams#Public";
+
+/// The whole outbox collection
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Outbox {
+    #[serde(rename = "@context")]
+    pub context: String,
+    pub id: String,
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub total_items: usize,
+    pub ordered_items: Vec<Activity>,
+}
+
+/// A `Create` activity for one note
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Activity {
+    pub id: String,
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub actor: String,
+    pub published: String,
+    pub to: Vec<String>,
+    pub cc: Vec<String>,
+    pub object: Note,
+}
+
+/// A post or comment as an ActivityPub `Note`
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Note {
+    pub id: String,
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub summary: Option<String>,
+    pub in_reply_to: Option<String>,
+    pub published: String,
+    pub url: Option<String>,
+    pub attributed_to: String,
+    pub to: Vec<String>,
+    pub cc: Vec<String>,
+    pub sensitive: bool,
+    pub content: String,
+    pub attachment: Vec<Attachment>,
+    pub tag: Vec<Tag>,
+}
+
+/// An image or other media attached to a note
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Attachment {
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub media_type: String,
+    pub url: String,
+    pub name: Option<String>,
+}
+
+/// A `Hashtag` or `Mention` on a note
+#[derive(Debug, Serialize)]
+pub struct Tag {
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub href: String,
+    pub name: String,
+}
+
+/// Build the activities for one post and its comments
+///
+/// # Arguments
+/// * `post_data` - The post to convert
+/// * `actor` - Actor URL owning the outbox
+/// * `site_url` - Base URL of the site the images are published on
+/// * `slug` - Output filename without extension, used when there is no canonical URL
+/// * `date_prefix` - The date prefix (YYYY-MM-DD) for image paths
+pub fn post_activities(
+    post_data: &PostData,
+    actor: &str,
+    site_url: &str,
+    slug: &str,
+    date_prefix: &str,
+) -> Vec<Activity> {
+    let status_id = post_data
+        .canonical_url
+        .rsplit('/')
+        .find(|segment| !segment.is_empty())
+        .unwrap_or(slug);
+    let note_id = format!("{}/statuses/{}", actor.trim_end_matches('/'), status_id);
+
+    // Public posts go to everyone, the rest only to followers
+    let followers = format!("{}/followers", actor.trim_end_matches('/'));
+    let (to, cc) = if post_data.visibility == Visibility::Public {
+        (vec![PUBLIC_AUDIENCE.to_string()], vec![followers])
+    } else {
+        (vec![followers], Vec::new())
+    };
+
+    let mut content = post_data.body.to_html();
+    if let Some(reshare_author) = &post_data.reshare_author {
+        content.push_str(&format!(
+            "<p>Originally shared by {}</p>\n",
+            html_escape::encode_text(reshare_author)
+        ));
+        content.push_str(&post_data.reshare_body.to_html());
+    }
+
+    // Other people's comments are quoted, only the owner's are replies
+    let is_owner = |comment: &Comment| {
+        if comment.author_url.is_empty() {
+            comment.author == post_data.author
+        } else {
+            comment.author_url == post_data.author_url
+        }
+    };
+    let (own_comments, other_comments): (Vec<&Comment>, Vec<&Comment>) = post_data
+        .comments
+        .iter()
+        .partition(|comment| is_owner(comment));
+    for comment in other_comments {
+        content.push_str(&format!(
+            "<blockquote>\n<p>{} commented:</p>\n{}</blockquote>\n",
+            html_escape::encode_text(&comment.author),
+            comment.body.to_html()
+        ));
+    }
+
+    let mut activities = vec![create_activity(Note {
+        id: note_id.clone(),
+        kind: String::from("Note"),
+        summary: None,
+        in_reply_to: None,
+        published: post_data.date.clone(),
+        url: non_empty(&post_data.canonical_url),
+        attributed_to: actor.to_string(),
+        to: to.clone(),
+        cc: cc.clone(),
+        sensitive: false,
+        content,
+        attachment: attachments(
+            post_data
+                .images
+                .iter()
+                .map(|image| (image.src.as_str(), image.description())),
+            site_url,
+            date_prefix,
+        ),
+        tag: tags(actor, &post_data.hashtags, &post_data.mentions),
+    })];
+
+    for (index, comment) in own_comments.into_iter().enumerate() {
+        activities.push(create_activity(Note {
+            id: format!("{}/replies/{}", note_id, index + 1),
+            kind: String::from("Note"),
+            summary: None,
+            in_reply_to: Some(note_id.clone()),
+            published: comment.date.clone(),
+            url: None,
+            attributed_to: actor.to_string(),
+            to: to.clone(),
+            cc: cc.clone(),
+            sensitive: false,
+            content: comment.body.to_html(),
+            attachment: attachments(
+                comment.images.iter().map(|image| (image.as_str(), None)),
+                site_url,
+                date_prefix,
+            ),
+            tag: tags(actor, &comment.hashtags, &comment.mentions),
+        }));
+    }
+
+    activities
+}
+
+/// Wrap activities into an outbox and serialize it
+pub fn outbox_to_json(activities: Vec<Activity>) -> String {
+    let outbox = Outbox {
+        context: ACTIVITYSTREAMS_CONTEXT.to_string(),
+        id: String::from("outbox.json"),
+        kind: String::from("OrderedCollection"),
+        total_items: activities.len(),
+        ordered_items: activities,
+    };
+    to_pretty_json(&outbox)
+}
+
+fn create_activity(note: Note) -> Activity {
+    Activity {
+        id: format!("{}/activity", note.id),
+        kind: String::from("Create"),
+        actor: note.attributed_to.clone(),
+        published: note.published.clone(),
+        to: note.to.clone(),
+        cc: note.cc.clone(),
+        object: note,
+    }
+}
+
+/// Attachments for `(source, description)` pairs of images, at absolute
+/// URLs on `site_url`
+///
+/// Remote images keep their URL, only files copied with the post are on
+/// the site.
+fn attachments<'a>(
+    images: impl Iterator<Item = (&'a str, Option<&'a str>)>,
+    site_url: &str,
+    date_prefix: &str,
+) -> Vec<Attachment> {
+    images
+        .map(|(image, description)| Attachment {
+            kind: String::from("Document"),
+            media_type: media_type(image).to_string(),
+            url: match parse_link(image) {
+                Some(_) => image.to_string(),
+                None => format!(
+                    "{}{}",
+                    site_url.trim_end_matches('/'),
+                    transform_image_path(image, date_prefix)
+                ),
+            },
+            name: description.map(str::to_string),
+        })
+        .collect()
+}
+
+fn tags(actor: &str, hashtags: &[String], mentions: &[(String, String)]) -> Vec<Tag> {
+    let origin = url_origin(actor);
+    let hashtags = hashtags.iter().map(|hashtag| Tag {
+        kind: String::from("Hashtag"),
+        href: format!("{}/tags/{}", origin, hashtag),
+        name: format!("#{}", hashtag),
+    });
+    let mentions = mentions.iter().map(|(href, name)| Tag {
+        kind: String::from("Mention"),
+        href: href.clone(),
+        name: format!("@{}", name),
+    });
+    hashtags.chain(mentions).collect()
+}
+
+/// Guess a MIME type from a file extension
+fn media_type(path: &str) -> &'static str {
+    let extension = path.rsplit('.').next().unwrap_or("").to_lowercase();
+    match extension.as_str() {
+        "jpg" | "jpeg" => "image/jpeg",
+        "png" => "image/png",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "mp4" => "video/mp4",
+        _ => "application/octet-stream",
+    }
+}
+
+/// "https://example.social/users/me" -> "https://example.social"
+fn url_origin(url: &str) -> &str {
+    let host_start = url.find("://").map(|pos| pos + 3).unwrap_or(0);
+    match url[host_start..].find('/') {
+        Some(pos) => &url[..host_start + pos],
+        None => url,
+    }
+}
+
+fn non_empty(s: &str) -> Option<String> {
+    if s.is_empty() {
+        None
+    } else {
+        Some(s.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{Comment, Image};
+    use crate::test_helpers::{link, paragraph, text, text_body};
+
+    const ACTOR: &str = "https://example.social/users/chicks";
+    const SITE: &str = "https://chicks.example.com/";
+
+    fn sample_post() -> PostData {
+        PostData {
+            author: String::from("Christopher Hicks"),
+            author_url: String::from("https://plus.google.com/+ChristopherHicksFINI"),
+            date: String::from("2012-11-01T22:04:01Z"),
+            canonical_url: String::from(
+                "https://plus.google.com/+ChristopherHicksFINI/posts/avkomKeocvq",
+            ),
+            body: paragraph(vec![text("Hello "), link("http://example.com", "world")]),
+            images: vec![Image::new("../Photos/Photos%20from%20posts/x/a.jpg")],
+            hashtags: vec![String::from("sour_apples")],
+            mentions: vec![(
+                String::from("https://plus.google.com/114270651108785324698"),
+                String::from("MythBusters"),
+            )],
+            visibility: Visibility::Public,
+            comments: vec![
+                Comment {
+                    author: String::from("Paul"),
+                    author_url: String::from("https://plus.google.com/+PaulFernandez"),
+                    date: String::from("2012-11-02T01:00:00Z"),
+                    body: text_body("Nice"),
+                    ..Default::default()
+                },
+                Comment {
+                    author: String::from("Christopher Hicks"),
+                    author_url: String::from("https://plus.google.com/+ChristopherHicksFINI"),
+                    date: String::from("2012-11-02T02:00:00Z"),
+                    body: text_body("Thanks"),
+                    ..Default::default()
+                },
+            ],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_post_note() {
+        let activities = post_activities(&sample_post(), ACTOR, SITE, "slug", "2012-11-01");
+        let post = &activities[0];
+        assert_eq!(post.kind, "Create");
+        assert_eq!(post.actor, ACTOR);
+        assert_eq!(
+            post.object.id,
+            "https://example.social/users/chicks/statuses/avkomKeocvq"
+        );
+        assert_eq!(post.id, format!("{}/activity", post.object.id));
+        assert_eq!(post.object.published, "2012-11-01T22:04:01Z");
+        assert!(post
+            .object
+            .content
+            .starts_with("<p>Hello <a href=\"http://example.com\">world</a></p>\n<blockquote>"));
+        assert_eq!(post.object.to, vec![PUBLIC_AUDIENCE]);
+        assert_eq!(post.object.in_reply_to, None);
+    }
+
+    #[test]
+    fn test_post_attachments() {
+        let activities = post_activities(&sample_post(), ACTOR, SITE, "slug", "2012-11-01");
+        let attachment = &activities[0].object.attachment[0];
+        assert_eq!(attachment.kind, "Document");
+        assert_eq!(attachment.media_type, "image/jpeg");
+        assert_eq!(
+            attachment.url,
+            "https://chicks.example.com/posts/2012-11-01-a.jpg"
+        );
+    }
+
+    #[test]
+    fn test_remote_attachments_keep_their_url() {
+        let remote = "https://lh3.googleusercontent.com/-x/AAuE7m=s64-c";
+        let mut post = sample_post();
+        post.comments[1].images = vec![remote.to_string()];
+        let activities = post_activities(&post, ACTOR, SITE, "slug", "2012-11-01");
+        assert_eq!(activities[1].object.attachment[0].url, remote);
+
+        // Downloaded by --fetch-images, copied with the post
+        post.comments[1].images = vec![String::from("/cache/images/5e0c.jpg")];
+        let activities = post_activities(&post, ACTOR, SITE, "slug", "2012-11-01");
+        assert_eq!(
+            activities[1].object.attachment[0].url,
+            "https://chicks.example.com/posts/2012-11-01-5e0c.jpg"
+        );
+    }
+
+    #[test]
+    fn test_post_tags() {
+        let activities = post_activities(&sample_post(), ACTOR, SITE, "slug", "2012-11-01");
+        let tag = &activities[0].object.tag;
+        assert_eq!(tag[0].kind, "Hashtag");
+        assert_eq!(tag[0].name, "#sour_apples");
+        assert_eq!(tag[0].href, "https://example.social/tags/sour_apples");
+        assert_eq!(tag[1].kind, "Mention");
+        assert_eq!(tag[1].name, "@MythBusters");
+    }
+
+    #[test]
+    fn test_own_comments_reply_to_post() {
+        let activities = post_activities(&sample_post(), ACTOR, SITE, "slug", "2012-11-01");
+        assert_eq!(activities.len(), 2);
+
+        let post_id = activities[0].object.id.clone();
+        let reply = &activities[1];
+        assert_eq!(reply.object.in_reply_to, Some(post_id.clone()));
+        assert_eq!(reply.object.id, format!("{}/replies/1", post_id));
+        assert_eq!(reply.object.content, "<p>Thanks</p>\n");
+        assert_eq!(reply.actor, ACTOR);
+    }
+
+    #[test]
+    fn test_other_comments_are_quoted() {
+        let mut post = sample_post();
+        // Renamed by the privacy settings, without a profile link
+        post.comments[0].author = String::from("P. <F.>");
+        post.comments[0].author_url.clear();
+        let activities = post_activities(&post, ACTOR, SITE, "slug", "2012-11-01");
+        assert!(activities.iter().all(|activity| activity.actor == ACTOR));
+        assert!(activities[0].object.content.ends_with(
+            "<blockquote>\n<p>P. &lt;F.&gt; commented:</p>\n<p>Nice</p>\n</blockquote>\n"
+        ));
+    }
+
+    #[test]
+    fn test_status_id_falls_back_to_slug() {
+        let mut post = sample_post();
+        post.canonical_url = String::new();
+        let activities = post_activities(&post, ACTOR, SITE, "2012-11-01-Post", "2012-11-01");
+        assert_eq!(
+            activities[0].object.id,
+            "https://example.social/users/chicks/statuses/2012-11-01-Post"
+        );
+        assert_eq!(activities[0].object.url, None);
+    }
+
+    #[test]
+    fn test_non_public_post_goes_to_followers() {
+        let mut post = sample_post();
+        post.visibility = Visibility::Circles;
+        let activities = post_activities(&post, ACTOR, SITE, "slug", "2012-11-01");
+        assert_eq!(
+            activities[0].to,
+            vec!["https://example.social/users/chicks/followers"]
+        );
+        assert!(activities[0].cc.is_empty());
+    }
+
+    #[test]
+    fn test_reshare_content() {
+        let mut post = sample_post();
+        post.reshare_author = Some(String::from("Jake <Gross>"));
+        post.reshare_body = text_body("For the noobs.");
+        let activities = post_activities(&post, ACTOR, SITE, "slug", "2012-11-01");
+        assert!(activities[0]
+            .object
+            .content
+            .contains("<p>Originally shared by Jake &lt;Gross&gt;</p>\n<p>For the noobs.</p>\n"));
+    }
+
+    #[test]
+    fn test_outbox_to_json_shape() {
+        let activities = post_activities(&sample_post(), ACTOR, SITE, "slug", "2012-11-01");
+        let parsed: serde_json::Value = serde_json::from_str(&outbox_to_json(activities)).unwrap();
+        assert_eq!(parsed["@context"], ACTIVITYSTREAMS_CONTEXT);
+        assert_eq!(parsed["type"], "OrderedCollection");
+        assert_eq!(parsed["totalItems"], 2);
+        assert_eq!(
+            parsed["orderedItems"][1]["object"]["inReplyTo"],
+            parsed["orderedItems"][0]["object"]["id"]
+        );
+        assert_eq!(parsed["orderedItems"][0]["object"]["attributedTo"], ACTOR);
+    }
+
+    #[test]
+    fn test_url_origin() {
+        assert_eq!(url_origin(ACTOR), "https://example.social");
+        assert_eq!(
+            url_origin("https://example.social"),
+            "https://example.social"
+        );
+    }
+
+    #[test]
+    fn test_media_type() {
+        assert_eq!(media_type("a.JPG"), "image/jpeg");
+        assert_eq!(media_type("clip.mp4"), "video/mp4");
+        assert_eq!(media_type("noext"), "application/octet-stream");
+    }
+}
@@ -0,0 +1,275 @@
+//! Comment export formats
+//!
+//! These let a site render comments itself instead of having them inlined
+//! under "## Comments" by `generate_markdown`, either from Hugo data files
+//! or by seeding a self-hosted comment system (Staticman, Isso).
+
+use chrono::DateTime;
+use serde::Serialize;
+
+use crate::models::Comment;
+use crate::utils::{to_pretty_json, urlize};
+
+/// Site path of a generated post, used to key comment threads
+///
+/// Hugo urlizes paths by default, so `2011-08-14-Hello world!` under
+/// `/posts/` is served from `/posts/2011-08-14-hello-world/`.
+pub fn post_path(url_prefix: &str, slug: &str) -> String {
+    format!("/{}/{}/", url_prefix.trim_matches('/'), urlize(slug)).replace("//", "/")
+}
+
+/// Parse a comment's UTC date into a Unix timestamp, if it converted cleanly
+fn comment_timestamp(comment: &Comment) -> Option<i64> {
+    DateTime::parse_from_rfc3339(&comment.date)
+        .ok()
+        .map(|date| date.timestamp())
+}
+
+/// Serialize a post's comments as a JSON array for a Hugo data file
+///
+/// Each entry carries the author, profile URL, UTC date, Markdown content
+/// and any attached images and links.  Hugo exposes the file as
+/// `.Site.Data.comments.<slug>` when written to `data/comments/<slug>.json`.
+pub fn comments_to_json(comments: &[Comment]) -> String {
+    to_pretty_json(comments)
+}
+
+/// Render a post's comments as Staticman entry files
+///
+/// Staticman stores one YAML file per comment under a directory named after
+/// the post (`data/comments/<slug>/` in the usual Hugo setup), urlized like
+/// `post_path()` so it matches the slug Hugo serves.  Returns `(path,
+/// contents)` pairs with paths relative to the comments directory; filenames
+/// follow Staticman's `entry{@timestamp}` pattern with a sequence number so
+/// comments made in the same second don't collide.
+pub fn comments_to_staticman(slug: &str, comments: &[Comment]) -> Vec<(String, String)> {
+    let slug = urlize(slug);
+    comments
+        .iter()
+        .enumerate()
+        .map(|(index, comment)| {
+            let timestamp = comment_timestamp(comment).unwrap_or(0);
+            let path = format!("{}/entry{}-{}.yml", slug, timestamp, index);
+
+            let mut yaml = String::new();
+            yaml.push_str(&format!(
+                "_id: {}\n",
+                yaml_string(&format!("{}-{}", slug, index))
+            ));
+            yaml.push_str(&format!("name: {}\n", yaml_string(&comment.author)));
+            yaml.push_str(&format!("url: {}\n", yaml_string(&comment.author_url)));
+            yaml.push_str(&format!("message: {}\n", yaml_string(&comment.content())));
+            yaml.push_str(&format!("date: {}\n", timestamp));
+
+            (path, yaml)
+        })
+        .collect()
+}
+
+/// Quote a string for YAML
+///
+/// A JSON string is a valid YAML double-quoted scalar, so this borrows
+/// serde_json's escaping rather than reimplementing it.
+fn yaml_string(s: &str) -> String {
+    serde_json::to_string(s).expect("Strings always serialize")
+}
+
+/// One thread in Isso's generic JSON import format
+#[derive(Debug, Serialize)]
+pub struct IssoThread {
+    /// Site path of the post, as Isso sees it in `data-isso-id`/the URL
+    pub id: String,
+    pub title: String,
+    pub comments: Vec<IssoComment>,
+}
+
+/// One comment in Isso's generic JSON import format
+#[derive(Debug, Serialize)]
+pub struct IssoComment {
+    pub id: usize,
+    pub author: String,
+    pub email: String,
+    pub website: String,
+    pub remote_addr: String,
+    /// Local "YYYY-MM-DD HH:MM:SS" time as expected by `isso import`
+    pub created: String,
+    pub text: String,
+}
+
+/// Build an Isso thread for a post's comments
+pub fn comments_to_isso(post_path: &str, title: &str, comments: &[Comment]) -> IssoThread {
+    let comments = comments
+        .iter()
+        .enumerate()
+        .map(|(index, comment)| IssoComment {
+            id: index + 1,
+            author: comment.author.clone(),
+            email: String::new(),
+            website: comment.author_url.clone(),
+            remote_addr: String::from("0.0.0.0"),
+            created: DateTime::parse_from_rfc3339(&comment.date)
+                .map(|date| date.format("%Y-%m-%d %H:%M:%S").to_string())
+                .unwrap_or_else(|_| comment.date.clone()),
+            text: comment.content(),
+        })
+        .collect();
+
+    IssoThread {
+        id: post_path.to_string(),
+        title: title.to_string(),
+        comments,
+    }
+}
+
+/// Serialize threads for `isso import --type generic`
+pub fn isso_threads_to_json(threads: &[IssoThread]) -> String {
+    to_pretty_json(threads)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_helpers::{paragraphs, text, text_body};
+
+    fn comment(author: &str, content: &str) -> Comment {
+        Comment {
+            author: author.to_string(),
+            author_url: format!("https://plus.google.com/+{}", author),
+            date: String::from("2011-11-21T16:06:25Z"),
+            body: text_body(content),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_comments_to_json_empty() {
+        assert_eq!(comments_to_json(&[]), "[]\n");
+    }
+
+    #[test]
+    fn test_comments_to_json_fields() {
+        let mut with_image = comment("Wing", "True true.");
+        with_image.images.push(String::from("../Photos/a.jpg"));
+        with_image
+            .links
+            .push((String::from("http://example.com"), String::from("Example")));
+
+        let parsed: serde_json::Value =
+            serde_json::from_str(&comments_to_json(&[comment("Paul", "Nice"), with_image]))
+                .unwrap();
+
+        assert_eq!(parsed[0]["author"], "Paul");
+        assert_eq!(parsed[0]["author_url"], "https://plus.google.com/+Paul");
+        assert_eq!(parsed[0]["date"], "2011-11-21T16:06:25Z");
+        assert_eq!(parsed[0]["content"], "Nice");
+        assert_eq!(parsed[1]["images"][0], "../Photos/a.jpg");
+        assert_eq!(parsed[1]["links"][0][0], "http://example.com");
+        assert_eq!(parsed[1]["links"][0][1], "Example");
+    }
+
+    #[test]
+    fn test_comments_to_json_escapes_content() {
+        let json = comments_to_json(&[comment("Paul", "He said \"hi\"\nbye")]);
+        assert!(json.contains(r#""content": "He said \"hi\"\nbye""#));
+    }
+
+    // Tests for post_path()
+    #[test]
+    fn test_post_path_lowercases_slug() {
+        assert_eq!(
+            post_path("/posts/", "2011-08-14-Today_is_my_first_day"),
+            "/posts/2011-08-14-today_is_my_first_day/"
+        );
+    }
+
+    #[test]
+    fn test_post_path_urlizes_takeout_name() {
+        assert_eq!(
+            post_path("/posts/", "2014-03-12-Penn _ Teller rock!"),
+            "/posts/2014-03-12-penn-_-teller-rock/"
+        );
+    }
+
+    #[test]
+    fn test_post_path_prefix_without_slashes() {
+        assert_eq!(post_path("blog", "post"), "/blog/post/");
+    }
+
+    #[test]
+    fn test_post_path_root_prefix() {
+        assert_eq!(post_path("/", "post"), "/post/");
+    }
+
+    // Tests for comments_to_staticman()
+    #[test]
+    fn test_comments_to_staticman_files() {
+        let entries = comments_to_staticman(
+            "2011-11-09-Android",
+            &[comment("Paul", "Nice"), comment("Wing", "Yes")],
+        );
+
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].0, "2011-11-09-android/entry1321891585-0.yml");
+        assert_eq!(entries[1].0, "2011-11-09-android/entry1321891585-1.yml");
+        assert_eq!(
+            entries[0].1,
+            "_id: \"2011-11-09-android-0\"\n\
+             name: \"Paul\"\n\
+             url: \"https://plus.google.com/+Paul\"\n\
+             message: \"Nice\"\n\
+             date: 1321891585\n"
+        );
+    }
+
+    #[test]
+    fn test_comments_to_staticman_escapes_yaml() {
+        let mut comment = comment("Paul", "");
+        comment.body = paragraphs(vec![vec![text("Line: one")], vec![text("two \"quoted\"")]]);
+        let entries = comments_to_staticman("post", &[comment]);
+        assert!(entries[0]
+            .1
+            .contains("message: \"Line: one\\n\\ntwo \\\"quoted\\\"\"\n"));
+    }
+
+    #[test]
+    fn test_comments_to_staticman_unparsed_date() {
+        let mut undated = comment("Paul", "Nice");
+        undated.date = String::from("yesterday");
+        let entries = comments_to_staticman("post", &[undated]);
+        assert_eq!(entries[0].0, "post/entry0-0.yml");
+        assert!(entries[0].1.ends_with("date: 0\n"));
+    }
+
+    #[test]
+    fn test_comments_to_staticman_urlizes_takeout_name() {
+        let entries =
+            comments_to_staticman("2014-03-12-Penn _ Teller rock!", &[comment("Paul", "Nice")]);
+        assert_eq!(
+            entries[0].0,
+            "2014-03-12-penn-_-teller-rock/entry1321891585-0.yml"
+        );
+    }
+
+    // Tests for comments_to_isso()
+    #[test]
+    fn test_comments_to_isso_thread() {
+        let thread = comments_to_isso("/posts/post/", "A post", &[comment("Paul", "Nice")]);
+        assert_eq!(thread.id, "/posts/post/");
+        assert_eq!(thread.title, "A post");
+        assert_eq!(thread.comments[0].id, 1);
+        assert_eq!(thread.comments[0].author, "Paul");
+        assert_eq!(thread.comments[0].website, "https://plus.google.com/+Paul");
+        assert_eq!(thread.comments[0].created, "2011-11-21 16:06:25");
+        assert_eq!(thread.comments[0].text, "Nice");
+    }
+
+    #[test]
+    fn test_isso_threads_to_json_shape() {
+        let thread = comments_to_isso("/posts/post/", "A post", &[comment("Paul", "Nice")]);
+        let parsed: serde_json::Value =
+            serde_json::from_str(&isso_threads_to_json(&[thread])).unwrap();
+        assert_eq!(parsed[0]["id"], "/posts/post/");
+        assert_eq!(parsed[0]["comments"][0]["remote_addr"], "0.0.0.0");
+        assert_eq!(parsed[0]["comments"][0]["email"], "");
+    }
+}
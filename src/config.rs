@@ -0,0 +1,389 @@
+//! Settings file
+//!
+//! Settings that would make for an unwieldy command line are read from a
+//! TOML file given with `--config`.  Every setting is optional:
+//!
+//! ```toml
+//! [cover]
+//! pick = "largest"
+//! hidden = false
+//!
+//! [images]
+//! widths = [480, 960]
+//! ```
+
+use std::path::Path;
+
+use serde::Deserialize;
+
+use crate::models::VariantFormat;
+
+/// `sizes` attribute of responsive images when none is configured
+pub const DEFAULT_SIZES: &str = "100vw";
+
+/// Everything a settings file can set
+#[derive(Debug, Default, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub cover: CoverConfig,
+    /// Resized copies of photos, made only when this section is present
+    pub images: Option<ImagesConfig>,
+    pub fetch: FetchConfig,
+    /// Reading photo EXIF data, only when this section is present
+    pub exif: Option<ExifConfig>,
+    pub filter: FilterConfig,
+    /// Redacting private posts and other people, only when this section is
+    /// present
+    pub privacy: Option<PrivacyConfig>,
+}
+
+/// How posts get their cover image
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CoverConfig {
+    /// Which photo becomes the cover
+    pub pick: CoverPick,
+    /// Written as `cover.hidden`, so the theme shows the cover only in
+    /// lists and not above the post
+    pub hidden: bool,
+}
+
+impl Default for CoverConfig {
+    fn default() -> Self {
+        CoverConfig {
+            pick: CoverPick::default(),
+            hidden: true,
+        }
+    }
+}
+
+/// Which of a post's photos is its cover
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CoverPick {
+    /// The first photo, or the link preview for posts without photos
+    #[default]
+    First,
+    /// The photo with the most pixels, the first one when sizes are unknown
+    Largest,
+}
+
+/// How resized copies of photos are made and shown
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ImagesConfig {
+    /// Widths in pixels to make copies at, photos are never enlarged
+    pub widths: Vec<u32>,
+    /// Formats to make each width in
+    pub formats: Vec<VariantFormat>,
+    /// JPEG quality from 1 to 100
+    pub quality: u8,
+    /// Whether copies keep the photo's EXIF data
+    pub exif: ExifMode,
+    /// `sizes` attribute telling browsers how wide the photos are shown
+    pub sizes: String,
+}
+
+impl Default for ImagesConfig {
+    fn default() -> Self {
+        ImagesConfig {
+            widths: vec![480, 960, 1600],
+            formats: vec![VariantFormat::Jpeg],
+            quality: 82,
+            exif: ExifMode::default(),
+            sizes: String::from(DEFAULT_SIZES),
+        }
+    }
+}
+
+/// What happens to EXIF data in copied photos and their resized copies
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ExifMode {
+    /// Drop it, along with the camera details and location in it
+    #[default]
+    Strip,
+    /// Copy it over
+    Preserve,
+}
+
+/// How remote images are downloaded with `--fetch-images`
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FetchConfig {
+    /// Extra attempts after a failed download
+    pub retries: u32,
+    /// Seconds to wait between requests, doubled for each retry
+    pub interval: f64,
+}
+
+impl Default for FetchConfig {
+    fn default() -> Self {
+        FetchConfig {
+            retries: 3,
+            interval: 1.0,
+        }
+    }
+}
+
+/// How EXIF data of local photos fills in missing post metadata
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ExifConfig {
+    /// Days a photo may be taken before or after its post without the
+    /// post being flagged
+    pub date_tolerance: u32,
+}
+
+impl Default for ExifConfig {
+    fn default() -> Self {
+        ExifConfig { date_tolerance: 3 }
+    }
+}
+
+/// Which posts are published, every post by default
+///
+/// Also set from the command line, see `FilterConfig::overridden_by()`.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FilterConfig {
+    /// First year, month or day to publish, like `2013` or `2013-06-01`
+    pub since: Option<String>,
+    /// Last year, month or day to publish
+    pub until: Option<String>,
+    /// Audiences a post must be shared with one of: `public`, `circles`,
+    /// `extended_circles`, `specific`, `community`, or the name of a
+    /// community, circle or person
+    pub visibility: Vec<String>,
+    /// Names or profile URLs of the authors whose posts are published
+    pub authors: Vec<String>,
+    /// Whether posts resharing someone else's post are published
+    pub reshares: bool,
+    /// Publish only posts with photos
+    pub with_images: bool,
+    /// Text a post must contain, ignoring case
+    pub matching: Option<String>,
+    /// Text a post must not contain, ignoring case
+    pub excluding: Option<String>,
+}
+
+impl Default for FilterConfig {
+    fn default() -> Self {
+        FilterConfig {
+            since: None,
+            until: None,
+            visibility: Vec::new(),
+            authors: Vec::new(),
+            reshares: true,
+            with_images: false,
+            matching: None,
+            excluding: None,
+        }
+    }
+}
+
+impl FilterConfig {
+    /// These settings with those given in `overrides` replacing them
+    ///
+    /// Lists are replaced rather than extended, and the switches can only
+    /// narrow the selection.
+    pub fn overridden_by(self, overrides: FilterConfig) -> Self {
+        let list = |setting: Vec<String>, replacement: Vec<String>| {
+            if replacement.is_empty() {
+                setting
+            } else {
+                replacement
+            }
+        };
+        FilterConfig {
+            since: overrides.since.or(self.since),
+            until: overrides.until.or(self.until),
+            visibility: list(self.visibility, overrides.visibility),
+            authors: list(self.authors, overrides.authors),
+            reshares: self.reshares && overrides.reshares,
+            with_images: self.with_images || overrides.with_images,
+            matching: overrides.matching.or(self.matching),
+            excluding: overrides.excluding.or(self.excluding),
+        }
+    }
+}
+
+/// What is kept of posts that weren't public and of other people
+#[derive(Debug, Default, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PrivacyConfig {
+    pub non_public: NonPublicMode,
+    pub names: NameMode,
+    /// Names or profile URLs of people who agreed to be named
+    pub allow: Vec<String>,
+}
+
+/// What happens to posts not shared with `Public`
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NonPublicMode {
+    /// Publish them like public posts
+    Keep,
+    /// Leave them out
+    #[default]
+    Skip,
+    /// Publish only their date and audience, with a note in place of the
+    /// content
+    Redact,
+}
+
+/// How commenters, +1ers and mentioned people are named
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NameMode {
+    /// By their names, as in the Takeout
+    Keep,
+    /// By their initials, like `P. F.`
+    #[default]
+    Initials,
+    /// As `Person 1`, `Person 2` and so on, numbered across the archive
+    Pseudonyms,
+}
+
+impl Config {
+    /// Load settings from a TOML file
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let source = std::fs::read_to_string(path).map_err(|why| why.to_string())?;
+        Self::from_toml(&source)
+    }
+
+    /// Parse settings, rejecting unknown keys so typos don't go unnoticed
+    pub fn from_toml(source: &str) -> Result<Self, String> {
+        toml::from_str(source).map_err(|why| why.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_defaults() {
+        let config = Config::from_toml("").unwrap();
+        assert_eq!(config, Config::default());
+        assert_eq!(config.cover.pick, CoverPick::First);
+        assert!(config.cover.hidden);
+    }
+
+    #[test]
+    fn test_cover_settings() {
+        let config = Config::from_toml("[cover]\npick = \"largest\"\nhidden = false\n").unwrap();
+        assert_eq!(
+            config.cover,
+            CoverConfig {
+                pick: CoverPick::Largest,
+                hidden: false,
+            }
+        );
+    }
+
+    #[test]
+    fn test_images_settings() {
+        assert_eq!(Config::default().images, None);
+
+        let config = Config::from_toml("[images]\n").unwrap();
+        assert_eq!(config.images, Some(ImagesConfig::default()));
+
+        let config = Config::from_toml(
+            "[images]\nwidths = [320, 640]\nformats = [\"webp\", \"jpeg\"]\nexif = \"preserve\"\n",
+        )
+        .unwrap();
+        assert_eq!(
+            config.images,
+            Some(ImagesConfig {
+                widths: vec![320, 640],
+                formats: vec![VariantFormat::Webp, VariantFormat::Jpeg],
+                exif: ExifMode::Preserve,
+                ..Default::default()
+            })
+        );
+    }
+
+    #[test]
+    fn test_fetch_settings() {
+        let config = Config::from_toml("[fetch]\ninterval = 0.25\n").unwrap();
+        assert_eq!(
+            config.fetch,
+            FetchConfig {
+                retries: 3,
+                interval: 0.25,
+            }
+        );
+    }
+
+    #[test]
+    fn test_exif_settings() {
+        assert_eq!(Config::default().exif, None);
+        assert_eq!(
+            Config::from_toml("[exif]\n").unwrap().exif,
+            Some(ExifConfig { date_tolerance: 3 })
+        );
+        assert_eq!(
+            Config::from_toml("[exif]\ndate_tolerance = 30\n")
+                .unwrap()
+                .exif,
+            Some(ExifConfig { date_tolerance: 30 })
+        );
+    }
+
+    #[test]
+    fn test_filter_settings() {
+        let config = Config::from_toml(
+            "[filter]\nsince = \"2013\"\nvisibility = [\"Public\"]\nreshares = false\n",
+        )
+        .unwrap();
+        let overrides = FilterConfig {
+            until: Some(String::from("2016")),
+            visibility: vec![String::from("Your circles")],
+            with_images: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            config.filter.overridden_by(overrides),
+            FilterConfig {
+                since: Some(String::from("2013")),
+                until: Some(String::from("2016")),
+                visibility: vec![String::from("Your circles")],
+                reshares: false,
+                with_images: true,
+                ..Default::default()
+            }
+        );
+    }
+
+    #[test]
+    fn test_privacy_settings() {
+        assert_eq!(Config::default().privacy, None);
+        assert_eq!(
+            Config::from_toml("[privacy]\n").unwrap().privacy,
+            Some(PrivacyConfig {
+                non_public: NonPublicMode::Skip,
+                names: NameMode::Initials,
+                allow: Vec::new(),
+            })
+        );
+        let config = Config::from_toml(
+            "[privacy]\nnon_public = \"redact\"\nnames = \"pseudonyms\"\nallow = [\"Paul Fernandez\"]\n",
+        )
+        .unwrap();
+        assert_eq!(
+            config.privacy,
+            Some(PrivacyConfig {
+                non_public: NonPublicMode::Redact,
+                names: NameMode::Pseudonyms,
+                allow: vec![String::from("Paul Fernandez")],
+            })
+        );
+    }
+
+    #[test]
+    fn test_unknown_settings_rejected() {
+        assert!(Config::from_toml("[cover]\nhiden = false\n").is_err());
+        assert!(Config::from_toml("[cover]\npick = \"last\"\n").is_err());
+    }
+}
@@ -0,0 +1,1246 @@
+//! Rich text content of posts, reshares and comments
+//!
+//! The parser turns HTML bodies into a small tree of blocks and inline
+//! elements.  Each output format renders that tree its own way instead of
+//! re-parsing Markdown.
+
+use serde::Serialize;
+
+use crate::dom::{format_markdown_link, markdown_link_destination};
+use crate::utils::{escape_markdown, escape_markdown_block_start, escape_markdown_inline};
+
+/// Parsed body text
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct RichText {
+    pub blocks: Vec<Block>,
+}
+
+/// A block of text, separated from its neighbours by a blank line
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Block {
+    /// Lines of text, joined by hard line breaks
+    Paragraph { inlines: Vec<Inline> },
+    /// A list typed by hand, numbered from `start` or bulleted when `None`
+    List {
+        start: Option<u32>,
+        items: Vec<Vec<Inline>>,
+    },
+    /// Lines typed with a leading `>`
+    Quote { inlines: Vec<Inline> },
+}
+
+/// Inline text and markup within a block
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Inline {
+    Text {
+        text: String,
+    },
+    LineBreak,
+    Bold {
+        inlines: Vec<Inline>,
+    },
+    Italic {
+        inlines: Vec<Inline>,
+    },
+    Strike {
+        inlines: Vec<Inline>,
+    },
+    Link {
+        href: String,
+        text: String,
+    },
+    /// A +mention of a profile, `name` without the leading '+'
+    Mention {
+        href: String,
+        name: String,
+    },
+    /// A #hashtag, `tag` without the leading '#'
+    Hashtag {
+        href: String,
+        tag: String,
+    },
+}
+
+impl RichText {
+    /// Is there any text at all?
+    pub fn is_empty(&self) -> bool {
+        self.to_markdown().is_empty()
+    }
+
+    /// Every inline element, depth first
+    pub fn inlines(&self) -> Vec<&Inline> {
+        fn walk<'a>(inlines: &'a [Inline], found: &mut Vec<&'a Inline>) {
+            for inline in inlines {
+                found.push(inline);
+                if let Inline::Bold { inlines }
+                | Inline::Italic { inlines }
+                | Inline::Strike { inlines } = inline
+                {
+                    walk(inlines, found);
+                }
+            }
+        }
+
+        let mut found = Vec::new();
+        for block in &self.blocks {
+            match block {
+                Block::Paragraph { inlines } | Block::Quote { inlines } => {
+                    walk(inlines, &mut found)
+                }
+                Block::List { items, .. } => {
+                    for item in items {
+                        walk(item, &mut found);
+                    }
+                }
+            }
+        }
+        found
+    }
+
+    /// Every link, mention and hashtag URL, depth first
+    pub fn hrefs(&self) -> Vec<&str> {
+        self.inlines()
+            .into_iter()
+            .filter_map(|inline| match inline {
+                Inline::Link { href, .. }
+                | Inline::Mention { href, .. }
+                | Inline::Hashtag { href, .. } => Some(href.as_str()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Replace link, mention and hashtag URLs
+    ///
+    /// `rewrite` returns the new URL, or `None` to keep it.  Bare links
+    /// showing their URL as text get the new URL as text too.
+    pub fn rewrite_hrefs(&mut self, rewrite: &mut impl FnMut(&str) -> Option<String>) {
+        fn walk(inlines: &mut [Inline], rewrite: &mut impl FnMut(&str) -> Option<String>) {
+            for inline in inlines {
+                match inline {
+                    Inline::Bold { inlines }
+                    | Inline::Italic { inlines }
+                    | Inline::Strike { inlines } => walk(inlines, rewrite),
+                    Inline::Link { href, text } => {
+                        if let Some(new_href) = rewrite(href) {
+                            if text == href {
+                                *text = new_href.clone();
+                            }
+                            *href = new_href;
+                        }
+                    }
+                    Inline::Mention { href, .. } | Inline::Hashtag { href, .. } => {
+                        if let Some(new_href) = rewrite(href) {
+                            *href = new_href;
+                        }
+                    }
+                    Inline::Text { .. } | Inline::LineBreak => {}
+                }
+            }
+        }
+
+        for block in &mut self.blocks {
+            match block {
+                Block::Paragraph { inlines } | Block::Quote { inlines } => walk(inlines, rewrite),
+                Block::List { items, .. } => {
+                    for item in items {
+                        walk(item, rewrite);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Replace links with other inline elements
+    ///
+    /// `replace` is called with each link's `href` and `text` and returns
+    /// the elements to put in its place, or `None` to keep the link.
+    pub fn replace_links(&mut self, replace: &mut impl FnMut(&str, &str) -> Option<Vec<Inline>>) {
+        self.replace_inlines(&mut |inline| match inline {
+            Inline::Link { href, text } => replace(href, text),
+            _ => None,
+        });
+    }
+
+    /// Replace +mentions with other inline elements
+    ///
+    /// `replace` is called with each mention's `href` and `name` and
+    /// returns the elements to put in its place, or `None` to keep it.
+    pub fn replace_mentions(
+        &mut self,
+        replace: &mut impl FnMut(&str, &str) -> Option<Vec<Inline>>,
+    ) {
+        self.replace_inlines(&mut |inline| match inline {
+            Inline::Mention { href, name } => replace(href, name),
+            _ => None,
+        });
+    }
+
+    /// Replace inline elements, looking inside emphasis but not inside
+    /// the replacements
+    fn replace_inlines(&mut self, replace: &mut impl FnMut(&Inline) -> Option<Vec<Inline>>) {
+        fn walk(
+            inlines: &mut Vec<Inline>,
+            replace: &mut impl FnMut(&Inline) -> Option<Vec<Inline>>,
+        ) {
+            let mut index = 0;
+            while index < inlines.len() {
+                if let Inline::Bold { inlines }
+                | Inline::Italic { inlines }
+                | Inline::Strike { inlines } = &mut inlines[index]
+                {
+                    walk(inlines, replace);
+                } else if let Some(replacement) = replace(&inlines[index]) {
+                    let count = replacement.len();
+                    inlines.splice(index..=index, replacement);
+                    index += count;
+                    continue;
+                }
+                index += 1;
+            }
+        }
+
+        for block in &mut self.blocks {
+            match block {
+                Block::Paragraph { inlines } | Block::Quote { inlines } => walk(inlines, replace),
+                Block::List { items, .. } => {
+                    for item in items {
+                        walk(item, replace);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Render as Markdown, blocks separated by blank lines
+    pub fn to_markdown(&self) -> String {
+        let blocks: Vec<String> = self
+            .blocks
+            .iter()
+            .map(|block| match block {
+                Block::Paragraph { inlines } => inlines_to_markdown(inlines),
+                Block::List { start, items } => list_lines(*start, items, inlines_to_markdown),
+                Block::Quote { inlines } => inlines_to_markdown(inlines)
+                    .lines()
+                    .map(|line| format!("> {}", line).trim_end().to_string())
+                    .collect::<Vec<_>>()
+                    .join("\n"),
+            })
+            .filter(|block| !block.is_empty())
+            .collect();
+        blocks.join("\n\n")
+    }
+
+    /// Render as HTML, one element per block
+    pub fn to_html(&self) -> String {
+        let mut html = String::new();
+        for block in &self.blocks {
+            match block {
+                Block::Paragraph { inlines } => {
+                    let paragraph = inlines_to_html(inlines);
+                    if !paragraph.is_empty() {
+                        html.push_str(&format!("<p>{}</p>\n", paragraph));
+                    }
+                }
+                Block::List { start, items } => {
+                    html.push_str(&match start {
+                        Some(1) => String::from("<ol>\n"),
+                        Some(start) => format!("<ol start=\"{}\">\n", start),
+                        None => String::from("<ul>\n"),
+                    });
+                    for item in items {
+                        html.push_str(&format!("<li>{}</li>\n", inlines_to_html(item)));
+                    }
+                    html.push_str(if start.is_some() {
+                        "</ol>\n"
+                    } else {
+                        "</ul>\n"
+                    });
+                }
+                Block::Quote { inlines } => {
+                    html.push_str(&format!(
+                        "<blockquote><p>{}</p></blockquote>\n",
+                        inlines_to_html(inlines)
+                    ));
+                }
+            }
+        }
+        html
+    }
+
+    /// Render as plain text, turning links into `text [n]` references
+    ///
+    /// Links whose text is the URL itself stay inline.  Referenced URLs are
+    /// appended to `references`, reusing the number of a URL seen before.
+    /// Lists and quotes keep the markers they were typed with.
+    pub fn to_plain_text(&self, references: &mut Vec<String>) -> String {
+        self.render_plain_text(Some(references))
+    }
+
+    /// Render as plain text with link text but no URLs, for titles and
+    /// descriptions
+    pub fn to_text(&self) -> String {
+        self.render_plain_text(None)
+    }
+
+    fn render_plain_text(&self, mut references: Option<&mut Vec<String>>) -> String {
+        let mut plain_text = |inlines: &[Inline]| {
+            let mut text = String::new();
+            push_plain_text(&mut text, inlines, references.as_deref_mut());
+            trim_lines(&text)
+        };
+        let blocks: Vec<String> = self
+            .blocks
+            .iter()
+            .map(|block| match block {
+                Block::Paragraph { inlines } => plain_text(inlines),
+                Block::List { start, items } => list_lines(*start, items, &mut plain_text),
+                Block::Quote { inlines } => plain_text(inlines)
+                    .lines()
+                    .map(|line| format!("> {}", line).trim_end().to_string())
+                    .collect::<Vec<_>>()
+                    .join("\n"),
+            })
+            .filter(|block| !block.is_empty())
+            .collect();
+        blocks.join("\n\n")
+    }
+}
+
+/// Split inline text into blocks at blank lines
+///
+/// Takeout bodies are a single run of text with `<br>` line breaks.  Two or
+/// more breaks in a row end a paragraph, a single one is a line break within
+/// it.  Runs of lines starting with list markers (`-`, `*`, `•`, `1.`,
+/// `1)`) or `>` become lists and quotes when there are at least two of them
+/// or they make up the whole paragraph.
+pub fn split_blocks(inlines: Vec<Inline>) -> Vec<Block> {
+    // Split into lines, then into chunks of lines between blank lines
+    let mut chunks: Vec<Vec<Vec<Inline>>> = vec![Vec::new()];
+    let mut line = Vec::new();
+    for inline in inlines.into_iter().chain([Inline::LineBreak]) {
+        if inline != Inline::LineBreak {
+            line.push(inline);
+            continue;
+        }
+        let line = trim_inlines(std::mem::take(&mut line));
+        let chunk = chunks.last_mut().expect("there is always a chunk");
+        if !line.is_empty() {
+            chunk.push(line);
+        } else if !chunk.is_empty() {
+            chunks.push(Vec::new());
+        }
+    }
+
+    let mut blocks = Vec::new();
+    for chunk in chunks.into_iter().filter(|chunk| !chunk.is_empty()) {
+        let kinds: Vec<LineKind> = chunk.iter().map(|line| line_kind(line)).collect();
+        let whole_chunk = kinds.windows(2).all(|pair| pair[0].same_kind(&pair[1]));
+        let mut lines = chunk.into_iter().zip(kinds).peekable();
+        let mut paragraph = Vec::new();
+
+        while let Some((line, kind)) = lines.next() {
+            let mut run = vec![line];
+            while let Some((_, next_kind)) = lines.peek() {
+                if !next_kind.same_kind(&kind) {
+                    break;
+                }
+                run.push(lines.next().expect("peeked").0);
+            }
+
+            // Lone marker lines in the middle of text stay text
+            if kind == LineKind::Text || (run.len() == 1 && !whole_chunk) {
+                let text = std::mem::take(&mut paragraph);
+                paragraph = join_lines(std::iter::once(text).chain(run));
+                continue;
+            }
+
+            if !paragraph.is_empty() {
+                blocks.push(Block::Paragraph {
+                    inlines: std::mem::take(&mut paragraph),
+                });
+            }
+            let items = run.into_iter().map(|line| strip_marker(line, &kind));
+            blocks.push(match kind {
+                LineKind::Quote => Block::Quote {
+                    inlines: join_lines(items),
+                },
+                LineKind::Ordered(start) => Block::List {
+                    start: Some(start),
+                    items: items.collect(),
+                },
+                _ => Block::List {
+                    start: None,
+                    items: items.collect(),
+                },
+            });
+        }
+
+        if !paragraph.is_empty() {
+            blocks.push(Block::Paragraph { inlines: paragraph });
+        }
+    }
+    blocks
+}
+
+/// How a line of hand-typed text starts
+#[derive(Debug, Clone, PartialEq)]
+enum LineKind {
+    Text,
+    Bullet,
+    Ordered(u32),
+    Quote,
+}
+
+impl LineKind {
+    /// Numbered items of any number belong to the same list
+    fn same_kind(&self, other: &LineKind) -> bool {
+        std::mem::discriminant(self) == std::mem::discriminant(other)
+    }
+}
+
+fn line_kind(line: &[Inline]) -> LineKind {
+    let text = match line.first() {
+        Some(Inline::Text { text }) => text.trim_start(),
+        _ => return LineKind::Text,
+    };
+    if text.starts_with('>') {
+        return LineKind::Quote;
+    }
+    if let Some(rest) = text.strip_prefix(['-', '*', '•']) {
+        if rest.starts_with(' ') {
+            return LineKind::Bullet;
+        }
+    }
+    let digits = text.len() - text.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+    let rest = &text[digits..];
+    if (1..=9).contains(&digits) && rest.starts_with(['.', ')']) && rest[1..].starts_with(' ') {
+        if let Ok(number) = text[..digits].parse() {
+            return LineKind::Ordered(number);
+        }
+    }
+    LineKind::Text
+}
+
+/// Remove the list or quote marker from the start of a line
+fn strip_marker(mut line: Vec<Inline>, kind: &LineKind) -> Vec<Inline> {
+    if let Some(Inline::Text { text }) = line.first_mut() {
+        let trimmed = text.trim_start();
+        let rest = match kind {
+            LineKind::Text => trimmed,
+            LineKind::Quote => &trimmed[1..],
+            LineKind::Bullet => &trimmed[trimmed.chars().next().map_or(0, char::len_utf8)..],
+            LineKind::Ordered(_) => {
+                trimmed.trim_start_matches(|c: char| c.is_ascii_digit())[1..].as_ref()
+            }
+        };
+        *text = rest.trim_start().to_string();
+    }
+    trim_inlines(line)
+}
+
+/// Join lines with line breaks
+fn join_lines(lines: impl Iterator<Item = Vec<Inline>>) -> Vec<Inline> {
+    let mut inlines = Vec::new();
+    for line in lines.filter(|line| !line.is_empty()) {
+        if !inlines.is_empty() {
+            inlines.push(Inline::LineBreak);
+        }
+        inlines.extend(line);
+    }
+    inlines
+}
+
+/// Drop whitespace and line breaks from both ends
+fn trim_inlines(mut inlines: Vec<Inline>) -> Vec<Inline> {
+    let blank = |inline: &Inline| match inline {
+        Inline::Text { text } => text.trim().is_empty(),
+        Inline::LineBreak => true,
+        _ => false,
+    };
+    while inlines.last().is_some_and(blank) {
+        inlines.pop();
+    }
+    let leading = inlines.iter().take_while(|inline| blank(inline)).count();
+    inlines.drain(..leading);
+    inlines
+}
+
+/// Render list items one per line with their markers
+fn list_lines(
+    start: Option<u32>,
+    items: &[Vec<Inline>],
+    mut render: impl FnMut(&[Inline]) -> String,
+) -> String {
+    items
+        .iter()
+        .enumerate()
+        .map(|(index, item)| match start {
+            Some(start) => format!("{}. {}", start as usize + index, render(item)),
+            None => format!("- {}", render(item)),
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn inlines_to_markdown(inlines: &[Inline]) -> String {
+    let mut text = String::new();
+    push_markdown(&mut text, inlines, false);
+    text.trim().to_string()
+}
+
+fn inlines_to_html(inlines: &[Inline]) -> String {
+    let mut html = String::new();
+    push_html(&mut html, inlines);
+    html.trim().to_string()
+}
+
+/// Plain text of inline elements, with link text but no URLs
+pub fn inlines_to_text(inlines: &[Inline]) -> String {
+    let mut text = String::new();
+    push_plain_text(&mut text, inlines, None);
+    trim_lines(&text)
+}
+
+/// Append text, merging it into a preceding text element
+pub fn push_text(inlines: &mut Vec<Inline>, new_text: &str) {
+    if let Some(Inline::Text { text }) = inlines.last_mut() {
+        text.push_str(new_text);
+    } else {
+        inlines.push(Inline::Text {
+            text: new_text.to_string(),
+        });
+    }
+}
+
+/// Append inlines as Markdown
+///
+/// `nested` is set inside emphasis, where text never starts a line and
+/// line breaks are left for `push_markdown_emphasis()` to mark.
+fn push_markdown(text: &mut String, inlines: &[Inline], nested: bool) {
+    for (index, inline) in inlines.iter().enumerate() {
+        match inline {
+            Inline::Text { text: inline_text } => {
+                let at_line_start = !nested && (text.is_empty() || text.ends_with('\n'));
+                text.push_str(&escape_markdown(inline_text, at_line_start))
+            }
+            Inline::LineBreak => {
+                // A hard break between two lines of text, a plain newline
+                // at either end or next to another break
+                let between_text = index > 0
+                    && index + 1 < inlines.len()
+                    && inlines[index - 1] != Inline::LineBreak
+                    && inlines[index + 1] != Inline::LineBreak;
+                if between_text && !nested {
+                    text.push('\\');
+                }
+                text.push('\n');
+            }
+            Inline::Bold { inlines: children } => {
+                push_markdown_emphasis(text, "**", children, &inlines[index + 1..], nested)
+            }
+            Inline::Italic { inlines: children } => {
+                push_markdown_emphasis(text, "*", children, &inlines[index + 1..], nested)
+            }
+            Inline::Strike { inlines: children } => {
+                push_markdown_emphasis(text, "~~", children, &inlines[index + 1..], nested)
+            }
+            Inline::Link {
+                href,
+                text: link_text,
+            } => format_markdown_link(text, href, link_text),
+            Inline::Mention { href, name } => {
+                text.push_str(&format!(
+                    "+[{}]({})",
+                    escape_markdown_inline(name),
+                    markdown_link_destination(href)
+                ));
+            }
+            Inline::Hashtag { href, tag } => format_markdown_link(text, href, &format!("#{}", tag)),
+        }
+    }
+}
+
+/// Wrap emphasized text in Markdown delimiters
+///
+/// Whitespace at either end is moved outside the delimiters, which only
+/// count as emphasis when they hug the text.  Each line is wrapped on its
+/// own since emphasis cannot span a blank line.  Where CommonMark would not
+/// see the delimiters as emphasis (punctuation inside with a letter outside,
+/// as in `a**"b"**c`), the text is left plain.
+fn push_markdown_emphasis(
+    text: &mut String,
+    delimiter: &str,
+    children: &[Inline],
+    following: &[Inline],
+    nested: bool,
+) {
+    let mut content = String::new();
+    push_markdown(&mut content, children, true);
+
+    let lines: Vec<&str> = content.split('\n').collect();
+    for (index, line) in lines.iter().enumerate() {
+        if index > 0 {
+            // Hard breaks between lines of text, as in push_markdown()
+            if !nested && !lines[index - 1].trim().is_empty() && !line.trim().is_empty() {
+                text.push('\\');
+            }
+            text.push('\n');
+        }
+        let core = line.trim();
+        if core.is_empty() {
+            text.push_str(line);
+            continue;
+        }
+
+        let leading = &line[..line.len() - line.trim_start().len()];
+        let trailing = &line[line.trim_end().len()..];
+        text.push_str(leading);
+
+        let before = text.chars().last();
+        let after = if !trailing.is_empty() || index + 1 < lines.len() {
+            Some(' ')
+        } else {
+            first_markdown_char(following)
+        };
+        let opens = !starts_with_punctuation(core) || !is_word_char(before);
+        let closes = !ends_with_punctuation(core) || !is_word_char(after);
+        if opens && closes {
+            text.push_str(delimiter);
+            text.push_str(core);
+            text.push_str(delimiter);
+        } else if !nested && (text.is_empty() || text.ends_with('\n')) {
+            text.push_str(&escape_markdown_block_start(core));
+        } else {
+            text.push_str(core);
+        }
+        text.push_str(trailing);
+    }
+}
+
+/// The first character Markdown for these inlines would start with
+fn first_markdown_char(inlines: &[Inline]) -> Option<char> {
+    match inlines.first()? {
+        Inline::Text { text } if text.is_empty() => first_markdown_char(&inlines[1..]),
+        Inline::Text { text } => text.chars().next(),
+        // Links get a space in front after emphasis
+        Inline::LineBreak | Inline::Link { .. } | Inline::Hashtag { .. } => Some(' '),
+        Inline::Mention { .. } => Some('+'),
+        Inline::Bold { .. } | Inline::Italic { .. } => Some('*'),
+        Inline::Strike { .. } => Some('~'),
+    }
+}
+
+fn starts_with_punctuation(text: &str) -> bool {
+    text.chars()
+        .next()
+        .is_some_and(|c| c.is_ascii_punctuation())
+}
+
+fn ends_with_punctuation(text: &str) -> bool {
+    text.chars()
+        .last()
+        .is_some_and(|c| c.is_ascii_punctuation())
+}
+
+/// Letters and digits next to a delimiter stop punctuation inside it from
+/// counting as emphasis
+fn is_word_char(c: Option<char>) -> bool {
+    c.is_some_and(|c| !c.is_whitespace() && !c.is_ascii_punctuation())
+}
+
+fn push_html(html: &mut String, inlines: &[Inline]) {
+    for inline in inlines {
+        match inline {
+            Inline::Text { text } => html.push_str(&html_escape::encode_text(text)),
+            Inline::LineBreak => html.push_str("<br>\n"),
+            Inline::Bold { inlines } => push_html_element(html, "strong", inlines),
+            Inline::Italic { inlines } => push_html_element(html, "em", inlines),
+            Inline::Strike { inlines } => push_html_element(html, "del", inlines),
+            Inline::Link { href, text } => {
+                add_separator(html);
+                let text = if text.is_empty() { href } else { text };
+                html.push_str(&format!(
+                    "<a href=\"{}\">{}</a>",
+                    html_escape::encode_double_quoted_attribute(href),
+                    html_escape::encode_text(text)
+                ));
+            }
+            // Markup Mastodon uses for mentions and hashtags
+            Inline::Mention { href, name } => {
+                html.push_str(&format!(
+                    "<span class=\"h-card\"><a href=\"{}\" class=\"u-url mention\">+{}</a></span>",
+                    html_escape::encode_double_quoted_attribute(href),
+                    html_escape::encode_text(name)
+                ));
+            }
+            Inline::Hashtag { href, tag } => {
+                add_separator(html);
+                html.push_str(&format!(
+                    "<a href=\"{}\" class=\"mention hashtag\" rel=\"tag\">#{}</a>",
+                    html_escape::encode_double_quoted_attribute(href),
+                    html_escape::encode_text(tag)
+                ));
+            }
+        }
+    }
+}
+
+fn push_html_element(html: &mut String, tag: &str, inlines: &[Inline]) {
+    html.push_str(&format!("<{}>", tag));
+    push_html(html, inlines);
+    html.push_str(&format!("</{}>", tag));
+}
+
+fn push_plain_text(
+    text: &mut String,
+    inlines: &[Inline],
+    mut references: Option<&mut Vec<String>>,
+) {
+    for inline in inlines {
+        match inline {
+            Inline::Text { text: inline_text } => text.push_str(inline_text),
+            Inline::LineBreak => text.push('\n'),
+            Inline::Bold { inlines } | Inline::Italic { inlines } | Inline::Strike { inlines } => {
+                push_plain_text(text, inlines, references.as_deref_mut())
+            }
+            Inline::Link {
+                href,
+                text: link_text,
+            } => {
+                add_separator(text);
+                if link_text.is_empty() || link_text == href {
+                    text.push_str(href);
+                    continue;
+                }
+                text.push_str(link_text.trim());
+                if let Some(references) = references.as_deref_mut() {
+                    let number = match references.iter().position(|url| url == href) {
+                        Some(index) => index + 1,
+                        None => {
+                            references.push(href.clone());
+                            references.len()
+                        }
+                    };
+                    text.push_str(&format!(" [{}]", number));
+                }
+            }
+            Inline::Mention { name, .. } => {
+                text.push('+');
+                text.push_str(name);
+            }
+            Inline::Hashtag { tag, .. } => {
+                add_separator(text);
+                text.push('#');
+                text.push_str(tag);
+            }
+        }
+    }
+}
+
+/// Add a space before a link unless the text already ends in whitespace or
+/// an opening bracket
+fn add_separator(text: &mut String) {
+    if !text.is_empty() && !text.ends_with(|c: char| c.is_whitespace() || c == '(' || c == '[') {
+        text.push(' ');
+    }
+}
+
+/// Trim text and collapse runs of blank lines left by consecutive breaks
+fn trim_lines(text: &str) -> String {
+    let mut lines: Vec<&str> = Vec::new();
+    for line in text.trim().lines() {
+        let line = line.trim_end();
+        if line.is_empty() && lines.last().is_some_and(|last| last.is_empty()) {
+            continue;
+        }
+        lines.push(line);
+    }
+    lines.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_helpers::{link, mention, paragraph, paragraphs, text};
+    use pulldown_cmark::{Event, Options, Parser, Tag};
+
+    fn sample() -> RichText {
+        paragraphs(vec![
+            vec![
+                text("Thanks to "),
+                mention("https://plus.google.com/1", "Todd Stein"),
+                text(" for "),
+                Inline::Bold {
+                    inlines: vec![text("this")],
+                },
+                Inline::LineBreak,
+                Inline::Hashtag {
+                    href: String::from("https://plus.google.com/s/%23Linux/posts"),
+                    tag: String::from("Linux"),
+                },
+            ],
+            vec![text("Read "), link("http://example.com/?a=1&b=2", "more")],
+        ])
+    }
+
+    #[test]
+    fn test_to_markdown() {
+        assert_eq!(
+            sample().to_markdown(),
+            "Thanks to +[Todd Stein](https://plus.google.com/1) for **this**\\\n\
+             [#Linux](https://plus.google.com/s/%23Linux/posts)\n\n\
+             Read [more](http://example.com/?a=1&b=2)"
+        );
+    }
+
+    fn markdown(inlines: Vec<Inline>) -> String {
+        paragraph(inlines).to_markdown()
+    }
+
+    fn bold(inlines: Vec<Inline>) -> Inline {
+        Inline::Bold { inlines }
+    }
+
+    fn italic(inlines: Vec<Inline>) -> Inline {
+        Inline::Italic { inlines }
+    }
+
+    #[test]
+    fn test_to_markdown_emphasis() {
+        assert_eq!(
+            markdown(vec![
+                bold(vec![text("bold")]),
+                text(", "),
+                italic(vec![text("italic")]),
+                text(" and "),
+                Inline::Strike {
+                    inlines: vec![text("gone")]
+                },
+            ]),
+            "**bold**, *italic* and ~~gone~~"
+        );
+    }
+
+    #[test]
+    fn test_to_markdown_emphasis_moves_whitespace_out() {
+        assert_eq!(
+            markdown(vec![text("a"), bold(vec![text(" b ")]), text("c")]),
+            "a **b** c"
+        );
+        assert_eq!(
+            markdown(vec![text("a "), italic(vec![text("  ")]), text("b")]),
+            "a   b"
+        );
+    }
+
+    #[test]
+    fn test_to_markdown_emphasis_nested() {
+        assert_eq!(
+            markdown(vec![bold(vec![text("so "), italic(vec![text("very")])])]),
+            "**so *very***"
+        );
+    }
+
+    #[test]
+    fn test_to_markdown_emphasis_per_line() {
+        assert_eq!(
+            markdown(vec![bold(vec![
+                text("one"),
+                Inline::LineBreak,
+                Inline::LineBreak,
+                text("two"),
+            ])]),
+            "**one**\n\n**two**"
+        );
+    }
+
+    #[test]
+    fn test_to_markdown_emphasis_escapes_delimiters() {
+        assert_eq!(
+            markdown(vec![bold(vec![text("2*3 = snake_case")])]),
+            "**2\\*3 = snake_case**"
+        );
+    }
+
+    #[test]
+    fn test_to_markdown_emphasis_punctuation_next_to_word() {
+        // CommonMark would not read these delimiters as emphasis
+        assert_eq!(
+            markdown(vec![text("say"), italic(vec![text("\"hi\"")]), text("now")]),
+            "say\"hi\"now"
+        );
+        assert_eq!(
+            markdown(vec![text("say "), italic(vec![text("\"hi\"")]), text(".")]),
+            "say *\"hi\"*."
+        );
+    }
+
+    /// Render Markdown and return its text, paragraphs separated by blank
+    /// lines, along with the emphasis and link markup it used
+    fn rendered(markdown: &str) -> (String, Vec<String>) {
+        let mut text = String::new();
+        let mut markup = Vec::new();
+        for event in Parser::new_ext(markdown, Options::ENABLE_STRIKETHROUGH) {
+            match event {
+                Event::Text(t) => text.push_str(&t),
+                Event::SoftBreak | Event::HardBreak => text.push('\n'),
+                Event::Start(Tag::Paragraph) if !text.is_empty() => text.push_str("\n\n"),
+                Event::Start(Tag::Paragraph) | Event::End(_) => {}
+                Event::Start(Tag::Emphasis) => markup.push(String::from("em")),
+                Event::Start(Tag::Strong) => markup.push(String::from("strong")),
+                Event::Start(Tag::Strikethrough) => markup.push(String::from("del")),
+                Event::Start(Tag::Link { dest_url, .. }) => markup.push(dest_url.to_string()),
+                other => panic!("{:?} in {:?}", other, markdown),
+            }
+        }
+        (text, markup)
+    }
+
+    #[test]
+    fn test_to_markdown_round_trip() {
+        let rich_text = paragraphs(vec![
+            vec![
+                text("# 1 * 2 = [2] & <3> "),
+                bold(vec![text("*really*")]),
+                text(" "),
+                link("http://example.com/a_(b)", "a [link] with _under_ scores"),
+                Inline::LineBreak,
+                text("- not a list "),
+                italic(vec![text("`x`")]),
+            ],
+            vec![
+                text("1. nor this, "),
+                mention("https://plus.google.com/1", "Under_Score*"),
+                text(" "),
+                Inline::Strike {
+                    inlines: vec![text("~gone~")],
+                },
+            ],
+        ]);
+
+        let expected: Vec<String> = rich_text
+            .blocks
+            .iter()
+            .filter_map(|block| match block {
+                Block::Paragraph { inlines } => Some(inlines_to_text(inlines)),
+                _ => None,
+            })
+            .collect();
+        let (text, markup) = rendered(&rich_text.to_markdown());
+        assert_eq!(text, expected.join("\n\n"));
+        assert_eq!(
+            markup,
+            vec![
+                "strong",
+                "http://example.com/a_(b)",
+                "em",
+                "https://plus.google.com/1",
+                "del"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_to_markdown_hard_breaks() {
+        assert_eq!(
+            markdown(vec![
+                text("one"),
+                Inline::LineBreak,
+                bold(vec![text("two"), Inline::LineBreak, text("three")]),
+                Inline::LineBreak,
+            ]),
+            "one\\\n**two**\\\n**three**"
+        );
+    }
+
+    fn lines(lines: &[&str]) -> Vec<Inline> {
+        join_lines(lines.iter().map(|line| vec![text(line)]))
+    }
+
+    #[test]
+    fn test_split_blocks_paragraphs() {
+        let mut inlines = lines(&["one", "two", "", "three"]);
+        inlines.extend([Inline::LineBreak, text("  "), Inline::LineBreak]);
+        assert_eq!(
+            split_blocks(inlines),
+            paragraphs(vec![
+                vec![text("one"), Inline::LineBreak, text("two")],
+                vec![text("three")],
+            ])
+            .blocks
+        );
+    }
+
+    #[test]
+    fn test_split_blocks_lists() {
+        assert_eq!(
+            split_blocks(lines(&[
+                "Shopping:",
+                "- eggs",
+                "* milk",
+                "",
+                "3. three",
+                "4) four"
+            ])),
+            vec![
+                Block::Paragraph {
+                    inlines: vec![text("Shopping:")]
+                },
+                Block::List {
+                    start: None,
+                    items: vec![vec![text("eggs")], vec![text("milk")]],
+                },
+                Block::List {
+                    start: Some(3),
+                    items: vec![vec![text("three")], vec![text("four")]],
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_split_blocks_lone_marker_stays_text() {
+        assert_eq!(
+            split_blocks(lines(&["Thanks all", "- Jake"])),
+            paragraph(lines(&["Thanks all", "- Jake"])).blocks
+        );
+        assert_eq!(
+            split_blocks(lines(&["2013. What a year", "-5 degrees"])),
+            paragraph(lines(&["2013. What a year", "-5 degrees"])).blocks
+        );
+        assert_eq!(
+            split_blocks(lines(&["1. Only item"])),
+            vec![Block::List {
+                start: Some(1),
+                items: vec![vec![text("Only item")]],
+            }]
+        );
+    }
+
+    #[test]
+    fn test_split_blocks_quotes() {
+        assert_eq!(
+            split_blocks(lines(&["He said:", "> one", ">two"])),
+            vec![
+                Block::Paragraph {
+                    inlines: vec![text("He said:")]
+                },
+                Block::Quote {
+                    inlines: lines(&["one", "two"]),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_to_markdown_blocks() {
+        let rich_text = RichText {
+            blocks: split_blocks(lines(&[
+                "Steps:",
+                "1. one",
+                "2. 1. two",
+                "",
+                "> quoted",
+                "> # text",
+                "",
+                "- a",
+                "- b",
+            ])),
+        };
+        let markdown = rich_text.to_markdown();
+        assert_eq!(
+            markdown,
+            "Steps:\n\n1. one\n2. 1\\. two\n\n> quoted\\\n> \\# text\n\n- a\n- b"
+        );
+    }
+
+    #[test]
+    fn test_to_html_blocks() {
+        let rich_text = RichText {
+            blocks: split_blocks(lines(&["2. two", "3. three", "", "- a", "- b", "", "> q"])),
+        };
+        assert_eq!(
+            rich_text.to_html(),
+            "<ol start=\"2\">\n<li>two</li>\n<li>three</li>\n</ol>\n\
+             <ul>\n<li>a</li>\n<li>b</li>\n</ul>\n\
+             <blockquote><p>q</p></blockquote>\n"
+        );
+    }
+
+    #[test]
+    fn test_to_plain_text_blocks() {
+        let rich_text = RichText {
+            blocks: split_blocks(lines(&["7) seven", "8) eight", "", "* a", "* b"])),
+        };
+        assert_eq!(
+            rich_text.to_plain_text(&mut Vec::new()),
+            "7. seven\n8. eight\n\n- a\n- b"
+        );
+    }
+
+    #[test]
+    fn test_to_html() {
+        assert_eq!(
+            sample().to_html(),
+            "<p>Thanks to <span class=\"h-card\"><a href=\"https://plus.google.com/1\" \
+             class=\"u-url mention\">+Todd Stein</a></span> for <strong>this</strong><br>\n\
+             <a href=\"https://plus.google.com/s/%23Linux/posts\" class=\"mention hashtag\" \
+             rel=\"tag\">#Linux</a></p>\n\
+             <p>Read <a href=\"http://example.com/?a=1&amp;b=2\">more</a></p>\n"
+        );
+    }
+
+    #[test]
+    fn test_to_html_escapes_text() {
+        let rich_text = paragraph(vec![text("Penn & Teller <3")]);
+        assert_eq!(rich_text.to_html(), "<p>Penn &amp; Teller &lt;3</p>\n");
+    }
+
+    #[test]
+    fn test_to_plain_text() {
+        let mut references = Vec::new();
+        assert_eq!(
+            sample().to_plain_text(&mut references),
+            "Thanks to +Todd Stein for this\n#Linux\n\nRead more [1]"
+        );
+        assert_eq!(references, vec!["http://example.com/?a=1&b=2"]);
+    }
+
+    #[test]
+    fn test_to_text() {
+        assert_eq!(
+            sample().to_text(),
+            "Thanks to +Todd Stein for this\n#Linux\n\nRead more"
+        );
+    }
+
+    #[test]
+    fn test_to_plain_text_reuses_references() {
+        let link = |href: &str, s: &str| Inline::Link {
+            href: href.to_string(),
+            text: s.to_string(),
+        };
+        let rich_text = paragraph(vec![
+            link("http://example.com/a", "this"),
+            text(" and "),
+            link("http://example.com/b", "that"),
+            text(", then "),
+            link("http://example.com/a", "this again"),
+            text(" or "),
+            link("http://example.com/c", "http://example.com/c"),
+        ]);
+        let mut references = Vec::new();
+        assert_eq!(
+            rich_text.to_plain_text(&mut references),
+            "this [1] and that [2], then this again [1] or http://example.com/c"
+        );
+        assert_eq!(
+            references,
+            vec!["http://example.com/a", "http://example.com/b"]
+        );
+    }
+
+    #[test]
+    fn test_collapses_blank_lines() {
+        let rich_text = paragraph(vec![
+            text("one"),
+            Inline::LineBreak,
+            Inline::LineBreak,
+            Inline::LineBreak,
+            text("two"),
+        ]);
+        assert_eq!(rich_text.to_plain_text(&mut Vec::new()), "one\n\ntwo");
+        assert_eq!(inlines_to_text(&[text(" one "), Inline::LineBreak]), "one");
+    }
+
+    #[test]
+    fn test_inlines_walks_emphasis() {
+        let rich_text = sample();
+        let inlines = rich_text.inlines();
+        assert!(inlines.contains(&&text("this")));
+        assert_eq!(inlines.len(), 9);
+    }
+
+    #[test]
+    fn test_hrefs() {
+        assert_eq!(
+            sample().hrefs(),
+            vec![
+                "https://plus.google.com/1",
+                "https://plus.google.com/s/%23Linux/posts",
+                "http://example.com/?a=1&b=2"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_replace_links() {
+        let mut rich_text = paragraph(vec![
+            Inline::Bold {
+                inlines: vec![link("http://a.example/", "a")],
+            },
+            text(" and "),
+            link("http://b.example/", "b"),
+        ]);
+        rich_text.replace_links(&mut |href, link_text| {
+            (link_text == "b").then(|| {
+                vec![
+                    Inline::Link {
+                        href: href.to_string(),
+                        text: link_text.to_string(),
+                    },
+                    text(" (old)"),
+                ]
+            })
+        });
+        assert_eq!(
+            rich_text.to_markdown(),
+            "**[a](http://a.example/)** and [b](http://b.example/) (old)"
+        );
+    }
+
+    #[test]
+    fn test_replace_mentions() {
+        let mention = |name: &str| Inline::Mention {
+            href: format!("https://plus.google.com/+{}", name),
+            name: name.to_string(),
+        };
+        let mut rich_text = paragraph(vec![
+            Inline::Italic {
+                inlines: vec![mention("PaulFernandez")],
+            },
+            text(" and "),
+            mention("WilSchultz"),
+        ]);
+        rich_text.replace_mentions(&mut |href, name| {
+            (name == "PaulFernandez" && href.ends_with(name)).then(|| vec![text("P. F.")])
+        });
+        assert_eq!(
+            rich_text.to_markdown(),
+            "*P. F.* and +[WilSchultz](https://plus.google.com/+WilSchultz)"
+        );
+    }
+
+    #[test]
+    fn test_is_empty() {
+        assert!(RichText::default().is_empty());
+        assert!(paragraph(vec![text("  "), Inline::LineBreak]).is_empty());
+        assert!(!sample().is_empty());
+    }
+
+    #[test]
+    fn test_push_text_merges() {
+        let mut inlines = Vec::new();
+        push_text(&mut inlines, "one ");
+        push_text(&mut inlines, "two");
+        inlines.push(Inline::LineBreak);
+        push_text(&mut inlines, "three");
+        assert_eq!(
+            inlines,
+            vec![text("one two"), Inline::LineBreak, text("three")]
+        );
+    }
+}
@@ -0,0 +1,148 @@
+//! Cover image selection
+//!
+//! Hugo themes like PaperMod show a `cover.image` in post lists and above
+//! the post.  A post's photos are the natural choice, with the preview
+//! image of a shared link for posts without photos.
+
+use serde::Serialize;
+
+use crate::config::CoverPick;
+use crate::markdown::published_image_path;
+use crate::models::{Image, PostData};
+
+/// The cover image of a post
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Cover {
+    /// Site path of the image, see `transform_image_path()`, or the URL
+    /// of a remote image that wasn't downloaded
+    pub image: String,
+    /// Alt text, empty when the photo has none
+    pub alt: String,
+}
+
+/// Pick the cover image of a post, `None` if it has no images
+pub fn post_cover(post_data: &PostData, date_prefix: &str, pick: CoverPick) -> Option<Cover> {
+    let candidates = post_data.images.iter().chain(&post_data.link_preview);
+    let image = match pick {
+        CoverPick::First => candidates.into_iter().next()?,
+        CoverPick::Largest => {
+            candidates.fold(None, |largest: Option<&Image>, image| match largest {
+                Some(largest) if pixels(largest) >= pixels(image) => Some(largest),
+                _ => Some(image),
+            })?
+        }
+    };
+
+    // Remote images are only copied once `--fetch-images` made them local
+    Some(Cover {
+        image: published_image_path(&image.src, date_prefix),
+        alt: image.description().unwrap_or_default().to_string(),
+    })
+}
+
+/// Number of pixels in a photo, 0 when its size is unknown
+fn pixels(image: &Image) -> u64 {
+    image
+        .dimensions()
+        .map_or(0, |(width, height)| u64::from(width) * u64::from(height))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::ImageInfo;
+
+    fn sized(src: &str, width: u32, height: u32) -> Image {
+        Image {
+            info: Some(ImageInfo {
+                width: Some(width),
+                height: Some(height),
+                ..Default::default()
+            }),
+            ..Image::new(src)
+        }
+    }
+
+    #[test]
+    fn test_first_image() {
+        let post = PostData {
+            images: vec![
+                Image {
+                    alt: String::from("Tulips"),
+                    ..Image::new("../Photos/a.jpg")
+                },
+                sized("../Photos/b.jpg", 2048, 1536),
+            ],
+            ..Default::default()
+        };
+        assert_eq!(
+            post_cover(&post, "2015-03-31", CoverPick::First),
+            Some(Cover {
+                image: String::from("/posts/2015-03-31-a.jpg"),
+                alt: String::from("Tulips"),
+            })
+        );
+    }
+
+    #[test]
+    fn test_largest_image() {
+        let post = PostData {
+            images: vec![
+                Image::new("../Photos/a.jpg"),
+                sized("../Photos/b.jpg", 640, 480),
+                sized("../Photos/c.jpg", 2048, 1536),
+                sized("../Photos/d.jpg", 1536, 2048),
+            ],
+            ..Default::default()
+        };
+        assert_eq!(
+            post_cover(&post, "2015-03-31", CoverPick::Largest).map(|cover| cover.image),
+            Some(String::from("/posts/2015-03-31-c.jpg"))
+        );
+    }
+
+    #[test]
+    fn test_largest_without_sizes_is_first() {
+        let post = PostData {
+            images: vec![Image::new("a.jpg"), Image::new("b.jpg")],
+            ..Default::default()
+        };
+        assert_eq!(
+            post_cover(&post, "2015-03-31", CoverPick::Largest).map(|cover| cover.image),
+            Some(String::from("/posts/2015-03-31-a.jpg"))
+        );
+    }
+
+    #[test]
+    fn test_link_preview() {
+        let mut post = PostData {
+            link_preview: Some(Image {
+                alt: String::from("Jan Vormann’s Best Lego Installations"),
+                ..Image::new("http://thereifixedit.files.wordpress.com/2011/08/31ea.jpg")
+            }),
+            ..Default::default()
+        };
+        assert_eq!(
+            post_cover(&post, "2011-09-02", CoverPick::First),
+            Some(Cover {
+                image: String::from("http://thereifixedit.files.wordpress.com/2011/08/31ea.jpg"),
+                alt: String::from("Jan Vormann’s Best Lego Installations"),
+            })
+        );
+
+        // A downloaded copy is published with the post
+        post.link_preview.as_mut().unwrap().src = String::from("/cache/images/5e0c.jpg");
+        assert_eq!(
+            post_cover(&post, "2011-09-02", CoverPick::First).map(|cover| cover.image),
+            Some(String::from("/posts/2011-09-02-5e0c.jpg"))
+        );
+
+        post.images.push(Image::new("../Photos/a.jpg"));
+        assert_eq!(
+            post_cover(&post, "2011-09-02", CoverPick::First).map(|cover| cover.image),
+            Some(String::from("/posts/2011-09-02-a.jpg"))
+        );
+
+        assert_eq!(post_cover(&PostData::default(), "", CoverPick::First), None);
+    }
+}
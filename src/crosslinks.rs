@@ -0,0 +1,257 @@
+//! Links between posts in the same archive
+//!
+//! Posts link to and reshare each other by their
+//! `plus.google.com/<profile>/posts/<id>` URLs, which stopped working with
+//! Google+.  An `ArchiveIndex` maps every archived post to where its copy
+//! is published, so those links can point at the copy instead, and links
+//! to posts that were not archived are reported.
+
+use std::collections::HashMap;
+
+use crate::links::{parse_link, rewrite_post_links, LinkRewrite, Normalization};
+use crate::models::PostData;
+
+/// Site paths of the posts in an archive, keyed by Google+ post ID
+///
+/// The same post is linked as `+Name/posts/<id>`, `<numeric id>/posts/<id>`
+/// or with a `/u/0` account prefix, so only the post ID is compared.
+#[derive(Debug, Default)]
+pub struct ArchiveIndex {
+    paths: HashMap<String, String>,
+}
+
+impl ArchiveIndex {
+    /// Record where the post at `canonical_url` is published
+    ///
+    /// Returns `false`, and records nothing, if `canonical_url` is not a
+    /// Google+ post URL.
+    pub fn insert(&mut self, canonical_url: &str, path: &str) -> bool {
+        match post_id(canonical_url) {
+            Some(id) => {
+                self.paths.insert(id, path.to_string());
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Where the post linked by `href` is published, if it is archived
+    pub fn resolve(&self, href: &str) -> Option<&str> {
+        self.paths.get(&post_id(href)?).map(String::as_str)
+    }
+
+    pub fn len(&self) -> usize {
+        self.paths.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.paths.is_empty()
+    }
+}
+
+/// Links from one post to other Google+ posts
+#[derive(Debug, Default, PartialEq)]
+pub struct PostReferences {
+    /// Links now pointing at archived copies
+    pub linked: Vec<LinkRewrite>,
+    /// Links to posts that are not in the archive, left as they were
+    pub missing: Vec<String>,
+}
+
+/// Point links to archived posts at their copies
+///
+/// Covers the same links as `normalize_post_links()`: bodies, comments,
+/// the embedded links list and the video link.  Each distinct link is
+/// listed once in the result.
+pub fn link_archived_posts(post_data: &mut PostData, index: &ArchiveIndex) -> PostReferences {
+    let mut references = PostReferences::default();
+    rewrite_post_links(post_data, |href| {
+        post_id(href)?;
+        match index.resolve(href) {
+            Some(path) => {
+                if !references.linked.iter().any(|rewrite| rewrite.from == href) {
+                    references.linked.push(LinkRewrite {
+                        from: href.to_string(),
+                        to: path.to_string(),
+                        normalizations: vec![Normalization::ArchivedPost],
+                    });
+                }
+                Some(path.to_string())
+            }
+            None => {
+                if !references.missing.iter().any(|missing| missing == href) {
+                    references.missing.push(href.to_string());
+                }
+                None
+            }
+        }
+    });
+    references
+}
+
+/// The post ID of a `plus.google.com/<profile>/posts/<id>` link
+///
+/// Accepts numeric and `+Name` profiles, a `/u/<n>` account prefix, a
+/// trailing slash, query string or fragment.
+pub fn post_id(href: &str) -> Option<String> {
+    let url = parse_link(href)?;
+    if url.host_str()? != "plus.google.com" {
+        return None;
+    }
+
+    let mut segments: Vec<&str> = url.path_segments()?.filter(|s| !s.is_empty()).collect();
+    if segments.len() >= 2 && segments[0] == "u" && segments[1].parse::<u32>().is_ok() {
+        segments.drain(..2);
+    }
+    match segments.as_slice() {
+        [_profile, "posts", id] => Some(id.to_string()),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::Comment;
+    use crate::test_helpers::{link, paragraph};
+
+    fn index() -> ArchiveIndex {
+        let mut index = ArchiveIndex::default();
+        assert!(index.insert(
+            "https://plus.google.com/+ChristopherHicksFINI/posts/fbNx3GuEAZ1",
+            "/posts/2011-08-14-today_is_my_first_day/",
+        ));
+        index
+    }
+
+    #[test]
+    fn test_post_id() {
+        for href in [
+            "https://plus.google.com/+ChristopherHicksFINI/posts/fbNx3GuEAZ1",
+            "https://plus.google.com/u/0/117011524888311158858/posts/fbNx3GuEAZ1",
+            "http://plus.google.com/117011524888311158858/posts/fbNx3GuEAZ1/?hl=en",
+            "//plus.google.com/+ChristopherHicksFINI/posts/fbNx3GuEAZ1#comments",
+        ] {
+            assert_eq!(post_id(href).as_deref(), Some("fbNx3GuEAZ1"), "{}", href);
+        }
+    }
+
+    #[test]
+    fn test_post_id_rejects_other_links() {
+        for href in [
+            "https://plus.google.com/+ChristopherHicksFINI",
+            "https://plus.google.com/+ChristopherHicksFINI/posts",
+            "https://plus.google.com/communities/103612431617512137342",
+            "https://example.com/+Someone/posts/fbNx3GuEAZ1",
+            "/posts/2011-08-14-today_is_my_first_day/",
+        ] {
+            assert_eq!(post_id(href), None, "{}", href);
+        }
+    }
+
+    #[test]
+    fn test_insert_needs_post_url() {
+        let mut index = ArchiveIndex::default();
+        assert!(!index.insert("", "/posts/2011-08-14-post/"));
+        assert!(index.is_empty());
+    }
+
+    #[test]
+    fn test_resolve_any_profile_form() {
+        let index = index();
+        assert_eq!(index.len(), 1);
+        assert_eq!(
+            index.resolve("https://plus.google.com/u/0/117011524888311158858/posts/fbNx3GuEAZ1"),
+            Some("/posts/2011-08-14-today_is_my_first_day/")
+        );
+        assert_eq!(
+            index.resolve("https://plus.google.com/+ChristopherHicksFINI/posts/XYZ123abc"),
+            None
+        );
+    }
+
+    #[test]
+    fn test_link_archived_posts() {
+        let archived = "https://plus.google.com/117011524888311158858/posts/fbNx3GuEAZ1";
+        let missing = "https://plus.google.com/+deezmaker/posts/3EmZtQZXsVG";
+        let mut post = PostData {
+            body: paragraph(vec![link(archived, "this post")]),
+            links: vec![(archived.to_string(), archived.to_string())],
+            comments: vec![Comment {
+                body: paragraph(vec![link(missing, "this post")]),
+                links: vec![(missing.to_string(), String::from("Deezmaker"))],
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+
+        let references = link_archived_posts(&mut post, &index());
+
+        assert_eq!(
+            post.content(),
+            "[this post](/posts/2011-08-14-today_is_my_first_day/)"
+        );
+        assert_eq!(
+            post.links,
+            vec![(
+                String::from("/posts/2011-08-14-today_is_my_first_day/"),
+                String::from("/posts/2011-08-14-today_is_my_first_day/")
+            )]
+        );
+        assert_eq!(
+            post.comments[0].content(),
+            format!("[this post]({})", missing)
+        );
+        assert_eq!(
+            references,
+            PostReferences {
+                linked: vec![LinkRewrite {
+                    from: archived.to_string(),
+                    to: String::from("/posts/2011-08-14-today_is_my_first_day/"),
+                    normalizations: vec![Normalization::ArchivedPost],
+                }],
+                missing: vec![missing.to_string()],
+            }
+        );
+    }
+
+    #[test]
+    fn test_link_archived_posts_reshare() {
+        let archived = "https://plus.google.com/+ChristopherHicksFINI/posts/fbNx3GuEAZ1";
+        let mut post = PostData {
+            reshare_author: Some(String::from("Christopher Hicks")),
+            reshare_url: Some(archived.to_string()),
+            ..Default::default()
+        };
+        let references = link_archived_posts(&mut post, &index());
+        assert_eq!(
+            post.reshare_url.as_deref(),
+            Some("/posts/2011-08-14-today_is_my_first_day/")
+        );
+        assert_eq!(references.linked[0].from, archived);
+
+        let missing = "https://plus.google.com/+deezmaker/posts/3EmZtQZXsVG";
+        post.reshare_url = Some(missing.to_string());
+        let references = link_archived_posts(&mut post, &index());
+        assert_eq!(post.reshare_url.as_deref(), Some(missing));
+        assert_eq!(references.missing, vec![missing.to_string()]);
+    }
+
+    #[test]
+    fn test_link_archived_posts_leaves_other_links() {
+        let mut post = PostData {
+            author_url: String::from("https://plus.google.com/+ChristopherHicksFINI"),
+            body: paragraph(vec![link("http://example.com/", "this post")]),
+            ..Default::default()
+        };
+
+        let references = link_archived_posts(&mut post, &index());
+
+        assert_eq!(
+            post.author_url,
+            "https://plus.google.com/+ChristopherHicksFINI"
+        );
+        assert_eq!(post.content(), "[this post](http://example.com/)");
+        assert_eq!(references, PostReferences::default());
+    }
+}
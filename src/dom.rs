@@ -3,12 +3,28 @@
 use markup5ever_rcdom as rcdom;
 use rcdom::{Handle, NodeData};
 
+use crate::links::normalize_link;
+use crate::utils::escape_markdown_inline;
+
 pub fn has_class(attrs: &[markup5ever::interface::Attribute], class_name: &str) -> bool {
     attrs
         .iter()
         .any(|attr| attr.name.local.as_ref() == "class" && attr.value.as_ref().contains(class_name))
 }
 
+/// Like `has_class()` but only matches a whole class name, so "comment"
+/// does not match "comments" or "comment-content"
+pub fn has_class_name(attrs: &[markup5ever::interface::Attribute], class_name: &str) -> bool {
+    attrs.iter().any(|attr| {
+        attr.name.local.as_ref() == "class"
+            && attr
+                .value
+                .as_ref()
+                .split_whitespace()
+                .any(|name| name == class_name)
+    })
+}
+
 pub fn has_attr(
     attrs: &[markup5ever::interface::Attribute],
     attr_name: &str,
@@ -49,50 +65,13 @@ pub fn get_text_content(handle: &Handle) -> String {
     text.trim().to_string()
 }
 
-pub fn get_text_content_formatted(handle: &Handle) -> String {
-    let mut text = String::new();
-
-    fn collect_text_formatted(node: &Handle, text: &mut String) {
-        match &node.data {
-            NodeData::Text { ref contents } => {
-                text.push_str(&contents.borrow());
-            }
-            NodeData::Element {
-                ref name,
-                ref attrs,
-                ..
-            } => {
-                let tag_name = name.local.as_ref();
-                if tag_name == "br" {
-                    text.push('\n');
-                } else if tag_name == "a" {
-                    // Handle links within content - convert to Markdown
-                    let attrs = attrs.borrow();
-                    if let Some(href) = get_attr_value(&attrs, "href") {
-                        let link_text = get_text_content(node);
-                        format_markdown_link(text, &href, &link_text);
-                    } else {
-                        // No href attribute, just extract text
-                        for child in node.children.borrow().iter() {
-                            collect_text_formatted(child, text);
-                        }
-                    }
-                } else {
-                    for child in node.children.borrow().iter() {
-                        collect_text_formatted(child, text);
-                    }
-                }
-            }
-            _ => {
-                for child in node.children.borrow().iter() {
-                    collect_text_formatted(child, text);
-                }
-            }
-        }
-    }
-
-    collect_text_formatted(handle, &mut text);
-    text.trim().to_string()
+/// Get a node's parent element, if it still exists
+pub fn get_parent(handle: &Handle) -> Option<Handle> {
+    // Temporarily take parent ref, upgrade it, then restore it
+    let parent_weak_opt = handle.parent.take();
+    let parent = parent_weak_opt.as_ref().and_then(|weak| weak.upgrade());
+    handle.parent.set(parent_weak_opt);
+    parent
 }
 
 pub fn find_parent_href(handle: &Handle) -> Option<String> {
@@ -126,38 +105,176 @@ pub fn find_parent_href(handle: &Handle) -> Option<String> {
     search_parents(handle)
 }
 
+/// The closest enclosing element with tag `tag_name` and class `class_name`
+pub fn find_ancestor(handle: &Handle, tag_name: &str, class_name: &str) -> Option<Handle> {
+    let mut node = get_parent(handle);
+    while let Some(current) = node {
+        if let NodeData::Element {
+            ref name,
+            ref attrs,
+            ..
+        } = current.data
+        {
+            if name.local.as_ref() == tag_name && has_class_name(&attrs.borrow(), class_name) {
+                return Some(current);
+            }
+        }
+        node = get_parent(&current);
+    }
+    None
+}
+
+/// The `href` of the closest enclosing `<a>` with class `class_name`
+///
+/// Unlike `find_parent_href()` this skips unrelated links further up, so
+/// only the link the element belongs to is found.
+pub fn find_parent_link(handle: &Handle, class_name: &str) -> Option<String> {
+    match &find_ancestor(handle, "a", class_name)?.data {
+        NodeData::Element { attrs, .. } => get_attr_value(&attrs.borrow(), "href"),
+        _ => None,
+    }
+}
+
 /// Format a link as Markdown, adding spacing if needed
 /// - If link text equals URL, uses angle bracket syntax: <URL>
 /// - Otherwise uses full Markdown syntax: [text](URL)
-/// - Adds space before link if text buffer doesn't end with whitespace
+/// - Adds space before link if text buffer doesn't end with whitespace or
+///   an opening bracket
+/// - Escapes Markdown syntax in the link text
+/// - Unwraps redirectors and strips tracking parameters from the URL
 pub fn format_markdown_link(text: &mut String, href: &str, link_text: &str) {
     // Add space before link if needed
-    if !text.is_empty() && !text.ends_with(|c: char| c.is_whitespace()) {
+    if !text.is_empty() && !text.ends_with(|c: char| c.is_whitespace() || c == '(' || c == '[') {
         text.push(' ');
     }
-    // If link text is the same as URL, use angle bracket syntax
-    // Otherwise use full Markdown link syntax
-    if link_text == href {
+    // Bare URLs (no text, or the URL as text) become autolinks when
+    // CommonMark allows it, everything else uses full link syntax
+    let normalized = normalize_link(href);
+    let link_text = if link_text.is_empty() || link_text == href {
+        normalized.as_str()
+    } else {
+        link_text
+    };
+    let href = normalized.as_str();
+    if link_text == href && is_autolink(href) {
         text.push('<');
         text.push_str(href);
         text.push('>');
-    } else if !link_text.is_empty() {
+    } else {
         text.push('[');
-        text.push_str(link_text);
+        text.push_str(&escape_markdown_inline(link_text));
         text.push_str("](");
-        text.push_str(href);
+        text.push_str(&markdown_link_destination(href));
         text.push(')');
+    }
+}
+
+/// Link destination for `[text](destination)`, using the `<...>` form when
+/// the URL has spaces or unbalanced parentheses
+pub fn markdown_link_destination(href: &str) -> String {
+    let mut depth = 0i32;
+    let mut balanced = true;
+    for c in href.chars() {
+        match c {
+            '(' => depth += 1,
+            ')' => {
+                depth -= 1;
+                balanced &= depth >= 0;
+            }
+            _ => {}
+        }
+    }
+    balanced &= depth == 0;
+
+    if balanced && !href.contains(|c: char| c.is_whitespace() || c == '<' || c == '>') {
+        href.to_string()
     } else {
-        // No link text, just use the URL in angle brackets
-        text.push('<');
-        text.push_str(href);
-        text.push('>');
+        format!("<{}>", href.replace('<', "%3C").replace('>', "%3E"))
     }
 }
 
+/// Can this URL be written as a CommonMark `<URL>` autolink?
+fn is_autolink(href: &str) -> bool {
+    let scheme = match href.split_once(':') {
+        Some((scheme, _)) => scheme,
+        None => return false,
+    };
+    (2..=32).contains(&scheme.len())
+        && scheme.starts_with(|c: char| c.is_ascii_alphabetic())
+        && scheme
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '+' | '.' | '-'))
+        && !href.contains(|c: char| c.is_whitespace() || c.is_control() || c == '<' || c == '>')
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use markup5ever::interface::{Attribute, QualName};
+    use markup5ever::{ns, LocalName};
+
+    fn class_attr(value: &str) -> Vec<Attribute> {
+        vec![Attribute {
+            name: QualName::new(None, ns!(), LocalName::from("class")),
+            value: value.into(),
+        }]
+    }
+
+    // Tests for has_class_name()
+    #[test]
+    fn test_has_class_name_exact() {
+        assert!(has_class_name(&class_attr("comment"), "comment"));
+    }
+
+    #[test]
+    fn test_has_class_name_among_others() {
+        assert!(has_class_name(
+            &class_attr("ot-anchor bidi_isolate"),
+            "bidi_isolate"
+        ));
+    }
+
+    #[test]
+    fn test_has_class_name_rejects_prefix() {
+        assert!(!has_class_name(&class_attr("comments"), "comment"));
+        assert!(!has_class_name(&class_attr("comment-content"), "comment"));
+    }
+
+    // Tests for find_parent_link()
+    fn find_by_class(handle: &Handle, class_name: &str) -> Option<Handle> {
+        if let NodeData::Element { ref attrs, .. } = handle.data {
+            if has_class_name(&attrs.borrow(), class_name) {
+                return Some(handle.clone());
+            }
+        }
+        handle
+            .children
+            .borrow()
+            .iter()
+            .find_map(|child| find_by_class(child, class_name))
+    }
+
+    #[test]
+    fn test_find_parent_link_skips_other_links() {
+        use html5ever::tendril::TendrilSink;
+
+        let html = r#"<div class="album"><a href="http://youtu.be/u6XAPnuFjJc" class="media-link">
+            <div class="video-placeholder"><span class="play-icon"></span></div></a></div>
+            <a href="https://example.com/outer" class="link-embed"><div class="orphan"></div></a>"#;
+        let dom = html5ever::parse_document(rcdom::RcDom::default(), Default::default()).one(html);
+
+        let placeholder = find_by_class(&dom.document, "play-icon").unwrap();
+        assert_eq!(
+            find_parent_link(&placeholder, "media-link").as_deref(),
+            Some("http://youtu.be/u6XAPnuFjJc")
+        );
+        let orphan = find_by_class(&dom.document, "orphan").unwrap();
+        assert_eq!(find_parent_link(&orphan, "media-link"), None);
+        assert_eq!(
+            find_parent_href(&orphan).as_deref(),
+            Some("https://example.com/outer")
+        );
+    }
 
     // Tests for format_markdown_link()
     #[test]
@@ -202,6 +319,13 @@ mod tests {
         assert_eq!(text, "Check this [link](http://example.com)");
     }
 
+    #[test]
+    fn test_format_markdown_link_after_parenthesis() {
+        let mut text = String::from("(");
+        format_markdown_link(&mut text, "http://example.com", "archived");
+        assert_eq!(text, "([archived](http://example.com)");
+    }
+
     #[test]
     fn test_format_markdown_link_after_newline() {
         let mut text = String::from("Line one\n");
@@ -215,4 +339,49 @@ mod tests {
         format_markdown_link(&mut text, "https://example.com", "https://example.com");
         assert_eq!(text, "Secure: <https://example.com>");
     }
+
+    #[test]
+    fn test_format_markdown_link_escapes_text() {
+        let mut text = String::new();
+        format_markdown_link(&mut text, "http://example.com", "[*not* code]`");
+        assert_eq!(text, r"[\[\*not\* code\]\`](http://example.com)");
+    }
+
+    #[test]
+    fn test_format_markdown_link_relative_url() {
+        let mut text = String::new();
+        format_markdown_link(&mut text, "/posts/a_b/", "/posts/a_b/");
+        assert_eq!(text, "[/posts/a_b/](/posts/a_b/)");
+    }
+
+    #[test]
+    fn test_format_markdown_link_url_with_spaces() {
+        let mut text = String::new();
+        format_markdown_link(&mut text, "http://example.com/a b", "");
+        assert_eq!(text, "[http://example.com/a b](<http://example.com/a b>)");
+    }
+
+    #[test]
+    fn test_format_markdown_link_normalizes_url() {
+        let tracked = "https://www.google.com/url?q=http://example.com/%3Futm_source%3Dgplus";
+        let mut text = String::new();
+        format_markdown_link(&mut text, tracked, tracked);
+        assert_eq!(text, "<http://example.com/>");
+
+        let mut text = String::new();
+        format_markdown_link(&mut text, tracked, "Example");
+        assert_eq!(text, "[Example](http://example.com/)");
+    }
+
+    #[test]
+    fn test_markdown_link_destination_parentheses() {
+        assert_eq!(
+            markdown_link_destination("https://en.wikipedia.org/wiki/Rust_(fungus)"),
+            "https://en.wikipedia.org/wiki/Rust_(fungus)"
+        );
+        assert_eq!(
+            markdown_link_destination("http://example.com/a)"),
+            "<http://example.com/a)>"
+        );
+    }
 }
@@ -0,0 +1,409 @@
+//! Downloading remote images
+//!
+//! Some photos, link previews and profile photos are only linked from
+//! `googleusercontent.com` and will go when those URLs stop working.  An
+//! `ImageFetcher` downloads them once into a cache directory, named by the
+//! SHA-256 of their contents so the same image is stored once, and points
+//! the post at the cached file.  Posts then treat it like a photo from the
+//! Takeout, so `--copy-media` copies it next to the output.
+
+use std::collections::BTreeMap;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use sha2::{Digest, Sha256};
+
+use crate::config::FetchConfig;
+use crate::links::parse_link;
+use crate::models::PostData;
+use crate::utils::to_pretty_json;
+
+/// File in the cache directory mapping URLs to cached files
+const INDEX_FILE: &str = "index.json";
+
+/// Largest image downloaded, anything bigger is cut off and rejected
+const MAX_IMAGE_BYTES: u64 = 64 * 1024 * 1024;
+
+/// Longest wait a `Retry-After` header is honoured for
+const MAX_RETRY_AFTER: Duration = Duration::from_secs(60);
+
+/// Downloads remote images into a content-addressed cache
+pub struct ImageFetcher {
+    cache_dir: PathBuf,
+    /// Cached file names by URL, kept between runs
+    index: BTreeMap<String, String>,
+    agent: ureq::Agent,
+    retries: u32,
+    interval: Duration,
+    last_request: Option<Instant>,
+    /// Which references are downloaded, `is_remote_image()` outside tests
+    is_remote: fn(&str) -> bool,
+    /// Images downloaded and URLs that failed this run
+    pub downloaded: usize,
+    pub failed: usize,
+}
+
+impl ImageFetcher {
+    /// Use `cache_dir`, creating it if needed and reading its index
+    pub fn new(cache_dir: &Path, config: &FetchConfig) -> Result<Self, String> {
+        std::fs::create_dir_all(cache_dir)
+            .map_err(|why| format!("couldn't create {}: {}", cache_dir.display(), why))?;
+        let cache_dir = std::path::absolute(cache_dir).map_err(|why| why.to_string())?;
+        let index = match std::fs::read_to_string(cache_dir.join(INDEX_FILE)) {
+            Ok(json) => serde_json::from_str(&json).map_err(|why| why.to_string())?,
+            Err(why) if why.kind() == std::io::ErrorKind::NotFound => BTreeMap::new(),
+            Err(why) => return Err(why.to_string()),
+        };
+
+        Ok(ImageFetcher {
+            cache_dir,
+            index,
+            agent: ureq::AgentBuilder::new()
+                .timeout(Duration::from_secs(30))
+                .user_agent(concat!(
+                    env!("CARGO_PKG_NAME"),
+                    "/",
+                    env!("CARGO_PKG_VERSION")
+                ))
+                .build(),
+            retries: config.retries,
+            interval: Duration::from_secs_f64(config.interval.max(0.0)),
+            last_request: None,
+            is_remote: is_remote_image,
+            downloaded: 0,
+            failed: 0,
+        })
+    }
+
+    /// Write the URL index so the next run doesn't download again
+    pub fn save(&self) -> Result<(), String> {
+        std::fs::write(self.cache_dir.join(INDEX_FILE), to_pretty_json(&self.index))
+            .map_err(|why| why.to_string())
+    }
+
+    /// Download a post's remote images and point the post at the copies
+    ///
+    /// Covers photos and their full-size links, the link preview, the
+    /// author's profile photo and comment images.  Images that can't be
+    /// downloaded keep their URL and a warning is printed.
+    pub fn localize_images(&mut self, post_data: &mut PostData) {
+        let mut references: Vec<&mut String> = Vec::new();
+        for image in post_data
+            .images
+            .iter_mut()
+            .chain(post_data.link_preview.as_mut())
+        {
+            references.push(&mut image.src);
+            references.extend(image.link.as_mut());
+        }
+        references.extend(post_data.author_photo.as_mut());
+        for comment in &mut post_data.comments {
+            references.extend(comment.images.iter_mut());
+        }
+
+        for reference in references {
+            if !(self.is_remote)(reference) {
+                continue;
+            }
+            match self.fetch(reference) {
+                Ok(path) => *reference = path.display().to_string(),
+                Err(why) => {
+                    self.failed += 1;
+                    eprintln!("Warning: couldn't download {}: {}", reference, why);
+                }
+            }
+        }
+    }
+
+    /// The cached copy of `url`, downloading it if it isn't cached yet
+    pub fn fetch(&mut self, url: &str) -> Result<PathBuf, String> {
+        if let Some(file_name) = self.index.get(url) {
+            let path = self.cache_dir.join(file_name);
+            if path.is_file() {
+                return Ok(path);
+            }
+        }
+
+        let (bytes, content_type) = self.download(url)?;
+        let file_name = cache_file_name(&bytes, content_type.as_deref(), url);
+        let path = self.cache_dir.join(&file_name);
+        if !path.is_file() {
+            std::fs::write(&path, &bytes)
+                .map_err(|why| format!("couldn't write {}: {}", path.display(), why))?;
+        }
+        self.index.insert(url.to_string(), file_name);
+        self.downloaded += 1;
+        Ok(path)
+    }
+
+    /// Body and `Content-Type` of `url`, retrying server errors, `429 Too
+    /// Many Requests` and network failures
+    fn download(&mut self, url: &str) -> Result<(Vec<u8>, Option<String>), String> {
+        let mut attempt = 0;
+        loop {
+            self.wait_turn();
+            let (error, retry_after) = match self.agent.get(url).call() {
+                Ok(response) => {
+                    let content_type = response.header("content-type").map(str::to_string);
+                    let mut bytes = Vec::new();
+                    response
+                        .into_reader()
+                        .take(MAX_IMAGE_BYTES + 1)
+                        .read_to_end(&mut bytes)
+                        .map_err(|why| why.to_string())?;
+                    if bytes.len() as u64 > MAX_IMAGE_BYTES {
+                        return Err(format!("larger than {} bytes", MAX_IMAGE_BYTES));
+                    }
+                    return Ok((bytes, content_type));
+                }
+                Err(ureq::Error::Status(status, response)) => {
+                    if status != 429 && status < 500 {
+                        return Err(format!("status {}", status));
+                    }
+                    let retry_after = response
+                        .header("retry-after")
+                        .and_then(|seconds| seconds.trim().parse::<u64>().ok())
+                        .map(|seconds| Duration::from_secs(seconds).min(MAX_RETRY_AFTER));
+                    (format!("status {}", status), retry_after)
+                }
+                Err(why) => (why.to_string(), None),
+            };
+
+            if attempt >= self.retries {
+                return Err(error);
+            }
+            attempt += 1;
+            thread::sleep(retry_after.unwrap_or(self.interval * 2u32.pow(attempt.min(10))));
+        }
+    }
+
+    /// Sleep until `interval` has passed since the last request
+    fn wait_turn(&mut self) {
+        if let Some(last) = self.last_request {
+            let elapsed = last.elapsed();
+            if elapsed < self.interval {
+                thread::sleep(self.interval - elapsed);
+            }
+        }
+        self.last_request = Some(Instant::now());
+    }
+}
+
+/// Whether `src` is an image on Google's servers
+pub fn is_remote_image(src: &str) -> bool {
+    parse_link(src)
+        .and_then(|url| url.host_str().map(str::to_string))
+        .is_some_and(|host| {
+            ["googleusercontent.com", "ggpht.com"]
+                .iter()
+                .any(|domain| host == *domain || host.ends_with(&format!(".{}", domain)))
+        })
+}
+
+/// `<sha256>.<extension>` for downloaded bytes
+///
+/// The extension comes from the bytes themselves, then the
+/// `Content-Type`, then the URL, as Google's URLs often have none.
+fn cache_file_name(bytes: &[u8], content_type: Option<&str>, url: &str) -> String {
+    let extension = image::guess_format(bytes)
+        .ok()
+        .and_then(|format| format.extensions_str().first().copied())
+        .or_else(|| {
+            let mime = content_type?.split(';').next()?.trim();
+            image::ImageFormat::from_mime_type(mime)?
+                .extensions_str()
+                .first()
+                .copied()
+        })
+        .or_else(|| {
+            let path = parse_link(url)?.path().to_string();
+            let extension = Path::new(&path).extension()?.to_str()?.to_ascii_lowercase();
+            image::ImageFormat::from_extension(&extension)?
+                .extensions_str()
+                .first()
+                .copied()
+        })
+        .unwrap_or("bin");
+    format!("{:x}.{}", Sha256::digest(bytes), extension)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{Comment, Image};
+    use crate::stub_server::{StubResponse, StubServer};
+    use crate::test_helpers::temp_dir;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    const GIF: &str = "GIF89a\x01\x00\x01\x00\x00\x00\x00;";
+
+    fn fast() -> FetchConfig {
+        FetchConfig {
+            retries: 2,
+            interval: 0.0,
+        }
+    }
+
+    fn google_url(server: &StubServer, path: &str) -> String {
+        format!("{}{}", server.url, path)
+    }
+
+    #[test]
+    fn test_is_remote_image() {
+        assert!(is_remote_image(
+            "https://lh3.googleusercontent.com/a-/AAuE7m=s64-c"
+        ));
+        assert!(is_remote_image("//lh5.ggpht.com/x/photo.jpg"));
+        assert!(!is_remote_image(
+            "http://thereifixedit.files.wordpress.com/a.jpg"
+        ));
+        assert!(!is_remote_image("https://notgoogleusercontent.com/a.jpg"));
+        assert!(!is_remote_image("../Photos/a.jpg"));
+    }
+
+    #[test]
+    fn test_cache_file_name() {
+        let hash = "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855";
+        assert_eq!(cache_file_name(b"", None, ""), format!("{}.bin", hash));
+        assert_eq!(
+            cache_file_name(b"", Some("image/png; charset=binary"), ""),
+            format!("{}.png", hash)
+        );
+        assert_eq!(
+            cache_file_name(b"", None, "https://lh3.googleusercontent.com/x/a.JPG"),
+            format!("{}.jpg", hash)
+        );
+        assert!(cache_file_name(GIF.as_bytes(), Some("image/png"), "").ends_with(".gif"));
+    }
+
+    #[test]
+    fn test_fetch_caches_by_content() {
+        let server = StubServer::start(|_| StubResponse::ok(GIF));
+        let dir = temp_dir("fetch-cache");
+        let mut fetcher = ImageFetcher::new(&dir, &fast()).unwrap();
+
+        let first = fetcher.fetch(&google_url(&server, "/a.gif")).unwrap();
+        let second = fetcher.fetch(&google_url(&server, "/b")).unwrap();
+        assert_eq!(first, second);
+        assert_eq!(std::fs::read(&first).unwrap(), GIF.as_bytes());
+        assert!(first
+            .extension()
+            .is_some_and(|extension| extension == "gif"));
+
+        // Known URLs come from the index, even in a new run
+        fetcher.save().unwrap();
+        let mut fetcher = ImageFetcher::new(&dir, &fast()).unwrap();
+        assert_eq!(
+            fetcher.fetch(&google_url(&server, "/a.gif")).unwrap(),
+            first
+        );
+        assert_eq!(server.requests(), vec!["/a.gif", "/b"]);
+        assert_eq!(fetcher.downloaded, 0);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_fetch_retries() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let seen = Arc::clone(&calls);
+        let server = StubServer::start(move |target| match target {
+            "/flaky" if seen.fetch_add(1, Ordering::SeqCst) < 2 => StubResponse::status(503),
+            "/flaky" => StubResponse::ok(GIF),
+            "/busy" => StubResponse {
+                status: 429,
+                headers: vec![("Retry-After", String::from("0"))],
+                body: String::new(),
+            },
+            _ => StubResponse::status(404),
+        });
+        let dir = temp_dir("fetch-retries");
+        let mut fetcher = ImageFetcher::new(&dir, &fast()).unwrap();
+
+        assert!(fetcher.fetch(&google_url(&server, "/flaky")).is_ok());
+        assert_eq!(
+            fetcher.fetch(&google_url(&server, "/busy")),
+            Err(String::from("status 429"))
+        );
+        assert_eq!(
+            fetcher.fetch(&google_url(&server, "/gone")),
+            Err(String::from("status 404"))
+        );
+
+        let requests = server.requests();
+        let count = |path: &str| requests.iter().filter(|request| *request == path).count();
+        assert_eq!((count("/flaky"), count("/busy"), count("/gone")), (3, 3, 1));
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_fetch_rate_limited() {
+        let server = StubServer::start(|_| StubResponse::ok(GIF));
+        let dir = temp_dir("fetch-rate");
+        let config = FetchConfig {
+            retries: 0,
+            interval: 0.05,
+        };
+        let mut fetcher = ImageFetcher::new(&dir, &config).unwrap();
+
+        let start = Instant::now();
+        for path in ["/a", "/b", "/c"] {
+            fetcher.fetch(&google_url(&server, path)).unwrap();
+        }
+        assert!(start.elapsed() >= Duration::from_millis(100));
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_localize_images() {
+        let server = StubServer::start(|target| match target {
+            "/missing.jpg" => StubResponse::status(404),
+            _ => StubResponse::ok(GIF),
+        });
+        let dir = temp_dir("fetch-localize");
+        let mut fetcher = ImageFetcher::new(&dir, &fast()).unwrap();
+        // The stub server stands in for Google's
+        let stub_url = server.url.clone();
+        fetcher.is_remote = |src| src.starts_with("http://127.0.0.1:");
+
+        let mut post = PostData {
+            images: vec![
+                Image {
+                    link: Some(format!("{}/full.jpg", stub_url)),
+                    ..Image::new(&format!("{}/photo.jpg", stub_url))
+                },
+                Image::new("../Photos/a.jpg"),
+            ],
+            link_preview: Some(Image::new(&format!("{}/missing.jpg", stub_url))),
+            author_photo: Some(format!("{}/me.jpg", stub_url)),
+            comments: vec![Comment {
+                images: vec![format!("{}/reply.gif", stub_url)],
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        fetcher.localize_images(&mut post);
+
+        let cached = fetcher.fetch(&format!("{}/photo.jpg", stub_url)).unwrap();
+        let cached = cached.display().to_string();
+        assert!(cached.starts_with(&dir.display().to_string()));
+        assert_eq!(post.images[0].src, cached);
+        assert_eq!(post.images[0].link.as_ref(), Some(&cached));
+        assert_eq!(post.images[1].src, "../Photos/a.jpg");
+        assert_eq!(post.author_photo.as_ref(), Some(&cached));
+        assert_eq!(post.comments[0].images, vec![cached.clone()]);
+        // Failed downloads keep their URL
+        assert_eq!(
+            post.link_preview.map(|image| image.src),
+            Some(format!("{}/missing.jpg", stub_url))
+        );
+        assert_eq!((fetcher.downloaded, fetcher.failed), (4, 1));
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,379 @@
+//! Choosing which posts to publish
+//!
+//! A `PostFilter` checks each post against the `[filter]` settings and the
+//! matching command line options, so an archive can be published in part:
+//! a range of years, only public posts, only the owner's posts or no
+//! reshares.  Posts left out are counted by the reason they were left out.
+
+use std::collections::BTreeMap;
+use std::fmt;
+
+use chrono::{Months, NaiveDate};
+
+use crate::config::FilterConfig;
+use crate::models::PostData;
+
+/// Why a post was left out, the first filter it failed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Exclusion {
+    BeforeSince,
+    AfterUntil,
+    /// The post has no date the range can be checked against
+    NoDate,
+    Visibility,
+    Author,
+    Reshare,
+    NoImages,
+    NotMatching,
+    Excluded,
+}
+
+impl fmt::Display for Exclusion {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Exclusion::BeforeSince => "posted before the date range",
+            Exclusion::AfterUntil => "posted after the date range",
+            Exclusion::NoDate => "without a date",
+            Exclusion::Visibility => "shared with other audiences",
+            Exclusion::Author => "by other authors",
+            Exclusion::Reshare => "reshares",
+            Exclusion::NoImages => "without photos",
+            Exclusion::NotMatching => "not matching the text",
+            Exclusion::Excluded => "containing the excluded text",
+        })
+    }
+}
+
+/// Settings of a `FilterConfig`, checked and ready to use
+#[derive(Debug)]
+pub struct PostFilter {
+    since: Option<NaiveDate>,
+    until: Option<NaiveDate>,
+    visibility: Vec<String>,
+    authors: Vec<String>,
+    reshares: bool,
+    with_images: bool,
+    matching: Option<String>,
+    excluding: Option<String>,
+    /// Posts left out so far, by reason
+    pub excluded: BTreeMap<Exclusion, usize>,
+}
+
+impl PostFilter {
+    /// Check the settings, failing on dates that can't be read
+    pub fn new(config: &FilterConfig) -> Result<Self, String> {
+        let lowercase = |texts: &[String]| -> Vec<String> {
+            texts
+                .iter()
+                .map(|text| text.trim().to_lowercase())
+                .collect()
+        };
+
+        Ok(PostFilter {
+            since: config
+                .since
+                .as_deref()
+                .map(|since| parse_bound(since, false))
+                .transpose()?,
+            until: config
+                .until
+                .as_deref()
+                .map(|until| parse_bound(until, true))
+                .transpose()?,
+            visibility: lowercase(&config.visibility),
+            authors: lowercase(&config.authors),
+            reshares: config.reshares,
+            with_images: config.with_images,
+            matching: config.matching.as_deref().map(str::to_lowercase),
+            excluding: config.excluding.as_deref().map(str::to_lowercase),
+            excluded: BTreeMap::new(),
+        })
+    }
+
+    /// Whether `post_data` is published, counting it if it isn't
+    pub fn keep(&mut self, post_data: &PostData) -> bool {
+        match self.check(post_data) {
+            Ok(()) => true,
+            Err(exclusion) => {
+                *self.excluded.entry(exclusion).or_default() += 1;
+                false
+            }
+        }
+    }
+
+    /// Why `post_data` is left out, if it is
+    pub fn check(&self, post_data: &PostData) -> Result<(), Exclusion> {
+        if self.since.is_some() || self.until.is_some() {
+            let date = post_date(&post_data.date).ok_or(Exclusion::NoDate)?;
+            if self.since.is_some_and(|since| date < since) {
+                return Err(Exclusion::BeforeSince);
+            }
+            if self.until.is_some_and(|until| date > until) {
+                return Err(Exclusion::AfterUntil);
+            }
+        }
+
+        if !self.visibility.is_empty() {
+            let visibility = &post_data.visibility;
+            let names = visibility.label().into_iter().chain(
+                post_data
+                    .shared_with
+                    .iter()
+                    .map(|recipient| recipient.name.as_str()),
+            );
+            let visible = std::iter::once(visibility.key().to_string())
+                .chain(names.map(str::to_lowercase))
+                .any(|audience| self.visibility.contains(&audience));
+            if !visible {
+                return Err(Exclusion::Visibility);
+            }
+        }
+
+        if !self.authors.is_empty()
+            && !self
+                .authors
+                .contains(&post_data.author.trim().to_lowercase())
+            && !self.authors.contains(&post_data.author_url.to_lowercase())
+        {
+            return Err(Exclusion::Author);
+        }
+
+        if !self.reshares && post_data.reshare_author.is_some() {
+            return Err(Exclusion::Reshare);
+        }
+
+        if self.with_images && post_data.images.is_empty() {
+            return Err(Exclusion::NoImages);
+        }
+
+        let text = post_text(post_data);
+        if let Some(matching) = &self.matching {
+            if !text.contains(matching) {
+                return Err(Exclusion::NotMatching);
+            }
+        }
+        if let Some(excluding) = &self.excluding {
+            if text.contains(excluding) {
+                return Err(Exclusion::Excluded);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Number of posts left out, for every reason
+    pub fn excluded_total(&self) -> usize {
+        self.excluded.values().sum()
+    }
+}
+
+/// The first or last day of `bound`, a year, a month or a day
+fn parse_bound(bound: &str, last_day: bool) -> Result<NaiveDate, String> {
+    let bound = bound.trim();
+    let (first, months) = match bound.len() {
+        4 => (format!("{}-01-01", bound), 12),
+        7 => (format!("{}-01", bound), 1),
+        _ => (bound.to_string(), 0),
+    };
+    let first = NaiveDate::parse_from_str(&first, "%Y-%m-%d")
+        .map_err(|_| format!("{:?} is not a date, use YYYY, YYYY-MM or YYYY-MM-DD", bound))?;
+
+    if !last_day || months == 0 {
+        return Ok(first);
+    }
+    first
+        .checked_add_months(Months::new(months))
+        .and_then(|next| next.pred_opt())
+        .ok_or_else(|| format!("{:?} is out of range", bound))
+}
+
+/// The day a post was made, from its `YYYY-MM-DDTHH:MM:SSZ` date
+fn post_date(date: &str) -> Option<NaiveDate> {
+    NaiveDate::parse_from_str(date.get(..10)?, "%Y-%m-%d").ok()
+}
+
+/// Title, text and reshared text of a post, lowercased for matching
+///
+/// The text is matched as written, without the escaping Markdown needs.
+fn post_text(post_data: &PostData) -> String {
+    let mut references = Vec::new();
+    format!(
+        "{}\n{}\n{}",
+        post_data.title,
+        post_data.body.to_plain_text(&mut references),
+        post_data.reshare_body.to_plain_text(&mut references)
+    )
+    .to_lowercase()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{Image, Recipient, Visibility};
+    use crate::test_helpers::text_body;
+
+    fn post(date: &str) -> PostData {
+        PostData {
+            author: String::from("Christopher Hicks"),
+            author_url: String::from("https://plus.google.com/+ChristopherHicksFINI"),
+            date: date.to_string(),
+            body: text_body("Penn & Teller rock!"),
+            visibility: Visibility::Public,
+            ..Default::default()
+        }
+    }
+
+    fn filter(config: FilterConfig) -> PostFilter {
+        PostFilter::new(&config).unwrap()
+    }
+
+    #[test]
+    fn test_parse_bound() {
+        let day = |text: &str| NaiveDate::parse_from_str(text, "%Y-%m-%d").unwrap();
+        assert_eq!(parse_bound("2013", false), Ok(day("2013-01-01")));
+        assert_eq!(parse_bound("2016", true), Ok(day("2016-12-31")));
+        assert_eq!(parse_bound("2016-02", true), Ok(day("2016-02-29")));
+        assert_eq!(parse_bound(" 2014-06-15 ", true), Ok(day("2014-06-15")));
+        assert!(parse_bound("June 2014", false).is_err());
+    }
+
+    #[test]
+    fn test_date_range() {
+        let years = filter(FilterConfig {
+            since: Some(String::from("2013")),
+            until: Some(String::from("2016")),
+            ..Default::default()
+        });
+        assert_eq!(
+            years.check(&post("2012-12-31T23:59:59Z")),
+            Err(Exclusion::BeforeSince)
+        );
+        assert_eq!(years.check(&post("2013-01-01T00:00:00Z")), Ok(()));
+        assert_eq!(years.check(&post("2016-12-31T23:59:59Z")), Ok(()));
+        assert_eq!(
+            years.check(&post("2017-01-01T00:00:00Z")),
+            Err(Exclusion::AfterUntil)
+        );
+        assert_eq!(years.check(&post("")), Err(Exclusion::NoDate));
+
+        // Posts without a date are kept when there is no range
+        assert_eq!(filter(FilterConfig::default()).check(&post("")), Ok(()));
+    }
+
+    #[test]
+    fn test_visibility_and_author() {
+        let public = filter(FilterConfig {
+            visibility: vec![String::from("Public"), String::from("extended_circles")],
+            ..Default::default()
+        });
+        let mut shared = post("2014-08-22T18:12:55Z");
+        assert_eq!(public.check(&shared), Ok(()));
+        shared.visibility = Visibility::ExtendedCircles;
+        assert_eq!(public.check(&shared), Ok(()));
+        shared.visibility = Visibility::Circles;
+        assert_eq!(public.check(&shared), Err(Exclusion::Visibility));
+
+        let community = filter(FilterConfig {
+            visibility: vec![String::from("rust programming"), String::from("Friends")],
+            ..Default::default()
+        });
+        shared.visibility = Visibility::Community {
+            name: String::from("Rust Programming"),
+            url: String::from("https://plus.google.com/communities/113390432655174294208"),
+            category: None,
+        };
+        assert_eq!(community.check(&shared), Ok(()));
+        shared.visibility = Visibility::Specific;
+        shared.shared_with = vec![Recipient {
+            name: String::from("Friends"),
+            url: None,
+        }];
+        assert_eq!(community.check(&shared), Ok(()));
+
+        let owner = filter(FilterConfig {
+            authors: vec![String::from(
+                "https://plus.google.com/+ChristopherHicksFINI",
+            )],
+            ..Default::default()
+        });
+        let mut post = post("2014-08-22T18:12:55Z");
+        assert_eq!(owner.check(&post), Ok(()));
+        post.author_url = String::from("https://plus.google.com/+PaulFernandez");
+        assert_eq!(owner.check(&post), Err(Exclusion::Author));
+        post.author_url.clear();
+        assert_eq!(
+            filter(FilterConfig {
+                authors: vec![String::from("christopher hicks")],
+                ..Default::default()
+            })
+            .check(&post),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn test_reshares_and_images() {
+        let mut post = post("2014-08-22T18:12:55Z");
+        post.reshare_author = Some(String::from("Paul Fernandez"));
+        let no_reshares = filter(FilterConfig {
+            reshares: false,
+            ..Default::default()
+        });
+        assert_eq!(no_reshares.check(&post), Err(Exclusion::Reshare));
+
+        let with_images = filter(FilterConfig {
+            with_images: true,
+            ..Default::default()
+        });
+        assert_eq!(with_images.check(&post), Err(Exclusion::NoImages));
+        post.images.push(Image::new("../Photos/a.jpg"));
+        assert_eq!(with_images.check(&post), Ok(()));
+    }
+
+    #[test]
+    fn test_text() {
+        let mut post = post("2014-08-22T18:12:55Z");
+        let teller = filter(FilterConfig {
+            matching: Some(String::from("TELLER")),
+            excluding: Some(String::from("magic")),
+            ..Default::default()
+        });
+        assert_eq!(teller.check(&post), Ok(()));
+        post.reshare_body = text_body("Magic show tonight");
+        assert_eq!(teller.check(&post), Err(Exclusion::Excluded));
+        post.body = text_body("Rock!");
+        assert_eq!(teller.check(&post), Err(Exclusion::NotMatching));
+    }
+
+    #[test]
+    fn test_text_is_matched_unescaped() {
+        let mut post = post("2014-08-22T18:12:55Z");
+        post.body = text_body("[draft] *snake_case* notes");
+        assert_eq!(post.content(), "\\[draft\\] \\*snake_case\\* notes");
+        let draft = filter(FilterConfig {
+            matching: Some(String::from("[draft] *snake_case*")),
+            ..Default::default()
+        });
+        assert_eq!(draft.check(&post), Ok(()));
+    }
+
+    #[test]
+    fn test_keep_counts_exclusions() {
+        let mut filter = filter(FilterConfig {
+            since: Some(String::from("2013")),
+            reshares: false,
+            ..Default::default()
+        });
+        let mut reshare = post("2014-01-01T00:00:00Z");
+        reshare.reshare_author = Some(String::from("Paul Fernandez"));
+
+        assert!(filter.keep(&post("2014-01-01T00:00:00Z")));
+        assert!(!filter.keep(&post("2012-01-01T00:00:00Z")));
+        assert!(!filter.keep(&post("2011-01-01T00:00:00Z")));
+        assert!(!filter.keep(&reshare));
+        assert_eq!(
+            filter.excluded.into_iter().collect::<Vec<_>>(),
+            vec![(Exclusion::BeforeSince, 2), (Exclusion::Reshare, 1)]
+        );
+    }
+}
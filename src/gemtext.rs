@@ -0,0 +1,345 @@
+//! Gemini gemtext generation from post data
+//!
+//! Gemtext has no inline links: every link must be a `=> URL text` line of
+//! its own.  Body text keeps the link text in place and the links follow the
+//! block they appeared in.
+
+use crate::content::{inlines_to_text, Block, Inline, RichText};
+use crate::markdown::{post_title, published_image_path, transform_image_path};
+use crate::models::PostData;
+use crate::video::Video;
+
+/// Generate gemtext from post data
+///
+/// # Arguments
+/// * `post_data` - The post data to generate gemtext from
+/// * `date_prefix` - The date prefix (YYYY-MM-DD) for image paths
+pub fn generate_gemtext(post_data: &PostData, date_prefix: &str) -> String {
+    let mut gemtext = String::new();
+
+    gemtext.push_str(&format!("# {}\n\n", single_line(&post_title(post_data))));
+
+    // Post metadata
+    let mut metadata = Vec::new();
+    if !post_data.date.is_empty() {
+        metadata.push(post_data.date.clone());
+    }
+    if !post_data.author.is_empty() {
+        metadata.push(format!("by {}", post_data.author));
+    }
+    if !metadata.is_empty() {
+        gemtext.push_str(&format!("{}\n", metadata.join(" ")));
+    }
+    if let Some(location) = post_data.location_label() {
+        gemtext.push_str(&format!("Location: {}\n", location));
+    }
+    gemtext.push('\n');
+
+    // Main content
+    gemtext.push_str(&rich_text_to_gemtext(&post_data.body));
+
+    // Reshare information
+    if let Some(reshare_author) = &post_data.reshare_author {
+        gemtext.push_str(&format!("## Originally shared by {}\n\n", reshare_author));
+        if let Some(reshare_url) = &post_data.reshare_url {
+            gemtext.push_str(&format!("=> {} Original post\n\n", reshare_url));
+        }
+        gemtext.push_str(&rich_text_to_gemtext(&post_data.reshare_body));
+    }
+
+    // Images
+    if !post_data.images.is_empty() {
+        gemtext.push_str("## Images\n\n");
+        for (index, image) in post_data.images.iter().enumerate() {
+            let transformed_path = transform_image_path(&image.src, date_prefix);
+            let label = match image.caption.as_deref().or(image.description()) {
+                Some(text) => single_line(text),
+                None => format!("Image {}", index + 1),
+            };
+            gemtext.push_str(&format!("=> {} {}\n", transformed_path, label));
+        }
+        gemtext.push('\n');
+    }
+
+    // Video
+    if let Some(video_url) = &post_data.video_url {
+        let watch_url = Video::detect(video_url).watch_url(date_prefix);
+        gemtext.push_str(&format!("## Video\n\n=> {} Watch Video\n\n", watch_url));
+    }
+
+    // Links
+    if !post_data.links.is_empty() {
+        gemtext.push_str("## Links\n\n");
+        for (url, title) in &post_data.links {
+            gemtext.push_str(&link_line(url, title));
+        }
+        gemtext.push('\n');
+    }
+
+    let audience = post_data.audience();
+    if !audience.is_empty() {
+        gemtext.push_str(&format!("Shared with: {}\n", audience));
+    }
+    if let Some(collection) = &post_data.collection {
+        gemtext.push_str(&format!("Collection: {}\n", collection.name));
+    }
+    if !post_data.plus_ones.is_empty() {
+        gemtext.push_str(&format!("+1'd by: {}\n", post_data.plus_ones.join(", ")));
+    }
+    gemtext.push('\n');
+
+    // Comments
+    if !post_data.comments.is_empty() {
+        gemtext.push_str("## Comments\n\n");
+        for comment in &post_data.comments {
+            gemtext.push_str(&format!("### {}", comment.author));
+            if !comment.date.is_empty() {
+                gemtext.push_str(&format!(" - {}", comment.date));
+            }
+            gemtext.push_str("\n\n");
+            gemtext.push_str(&rich_text_to_gemtext(&comment.body));
+            for image_url in &comment.images {
+                let path = published_image_path(image_url, date_prefix);
+                gemtext.push_str(&format!("=> {} Image\n", path));
+            }
+            for (url, title) in &comment.links {
+                gemtext.push_str(&link_line(url, title));
+            }
+        }
+    }
+
+    format!("{}\n", gemtext.trim_end())
+}
+
+/// Render rich text as gemtext, each paragraph followed by its link lines
+///
+/// Returns an empty string for empty text, otherwise blocks ending in a
+/// blank line.
+pub fn rich_text_to_gemtext(rich_text: &RichText) -> String {
+    let mut gemtext = String::new();
+
+    for block in &rich_text.blocks {
+        let mut lines = Vec::new();
+        let mut links = Vec::new();
+        match block {
+            Block::Paragraph { inlines } => {
+                lines.extend(inlines_to_text(inlines).lines().map(escape_gemtext_line));
+                collect_links(inlines, &mut links);
+            }
+            // Gemtext lists are unordered, numbers stay in the text
+            Block::List { start, items } => {
+                for (index, item) in items.iter().enumerate() {
+                    let text = single_line(&inlines_to_text(item));
+                    lines.push(match start {
+                        Some(start) => format!("* {}. {}", *start as usize + index, text),
+                        None => format!("* {}", text),
+                    });
+                    collect_links(item, &mut links);
+                }
+            }
+            Block::Quote { inlines } => {
+                lines.extend(
+                    inlines_to_text(inlines)
+                        .lines()
+                        .map(|line| format!("> {}", line)),
+                );
+                collect_links(inlines, &mut links);
+            }
+        }
+        if lines.is_empty() && links.is_empty() {
+            continue;
+        }
+
+        // Keep the words in the text, move the links to their own lines
+        for line in lines {
+            gemtext.push_str(&line);
+            gemtext.push('\n');
+        }
+        gemtext.push('\n');
+        if !links.is_empty() {
+            gemtext.push_str(&links.concat());
+            gemtext.push('\n');
+        }
+    }
+
+    gemtext
+}
+
+/// Link lines for the links, mentions and hashtags in a paragraph
+fn collect_links(inlines: &[Inline], links: &mut Vec<String>) {
+    for inline in inlines {
+        match inline {
+            Inline::Bold { inlines } | Inline::Italic { inlines } | Inline::Strike { inlines } => {
+                collect_links(inlines, links)
+            }
+            Inline::Link { href, text } => links.push(link_line(href, text)),
+            Inline::Mention { href, name } => links.push(link_line(href, &format!("+{}", name))),
+            Inline::Hashtag { href, tag } => links.push(link_line(href, &format!("#{}", tag))),
+            Inline::Text { .. } | Inline::LineBreak => {}
+        }
+    }
+}
+
+/// Format a `=> URL text` link line, leaving out text that repeats the URL
+fn link_line(url: &str, title: &str) -> String {
+    if title.is_empty() || title == url {
+        format!("=> {}\n", url)
+    } else {
+        format!("=> {} {}\n", url, single_line(title))
+    }
+}
+
+/// Keep text lines from being read as gemtext links, headings, lists,
+/// quotes or preformatting toggles by indenting them one space
+fn escape_gemtext_line(line: &str) -> String {
+    let special = ["=>", "#", "* ", ">", "```"];
+    if special.iter().any(|prefix| line.starts_with(prefix)) {
+        format!(" {}", line)
+    } else {
+        line.to_string()
+    }
+}
+
+fn single_line(text: &str) -> String {
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{Comment, Image, Visibility};
+    use crate::test_helpers::{link, paragraphs, text};
+
+    #[test]
+    fn test_rich_text_to_gemtext_moves_links_out() {
+        let rich_text = paragraphs(vec![
+            vec![
+                text("Read"),
+                link("http://example.com", "this article"),
+                text(" today."),
+            ],
+            vec![text("Thanks "), link("http://example.com/a", "Ann")],
+        ]);
+        assert_eq!(
+            rich_text_to_gemtext(&rich_text),
+            "Read this article today.\n\n=> http://example.com this article\n\n\
+             Thanks Ann\n\n=> http://example.com/a Ann\n\n"
+        );
+    }
+
+    #[test]
+    fn test_rich_text_to_gemtext_bare_url() {
+        let rich_text = paragraphs(vec![vec![
+            text("See "),
+            link("http://example.com", "http://example.com"),
+        ]]);
+        assert_eq!(
+            rich_text_to_gemtext(&rich_text),
+            "See http://example.com\n\n=> http://example.com\n\n"
+        );
+    }
+
+    #[test]
+    fn test_rich_text_to_gemtext_tags() {
+        let rich_text = paragraphs(vec![vec![
+            Inline::Hashtag {
+                href: String::from("https://plus.google.com/s/%23Linux/posts"),
+                tag: String::from("Linux"),
+            },
+            text(" rocks"),
+        ]]);
+        assert_eq!(
+            rich_text_to_gemtext(&rich_text),
+            " #Linux rocks\n\n=> https://plus.google.com/s/%23Linux/posts #Linux\n\n"
+        );
+    }
+
+    #[test]
+    fn test_rich_text_to_gemtext_line_breaks() {
+        let rich_text = paragraphs(vec![vec![
+            text("one"),
+            Inline::LineBreak,
+            Inline::LineBreak,
+            Inline::LineBreak,
+            text("two"),
+        ]]);
+        assert_eq!(rich_text_to_gemtext(&rich_text), "one\n\ntwo\n\n");
+    }
+
+    #[test]
+    fn test_rich_text_to_gemtext_empty() {
+        assert_eq!(rich_text_to_gemtext(&RichText::default()), "");
+    }
+
+    #[test]
+    fn test_escape_gemtext_line() {
+        assert_eq!(escape_gemtext_line("#hashtag first"), " #hashtag first");
+        assert_eq!(escape_gemtext_line("=> not a link"), " => not a link");
+        assert_eq!(escape_gemtext_line("* not a list"), " * not a list");
+        assert_eq!(escape_gemtext_line("> not a quote"), " > not a quote");
+        assert_eq!(escape_gemtext_line("plain"), "plain");
+    }
+
+    #[test]
+    fn test_generate_gemtext() {
+        let post = PostData {
+            author: String::from("Christopher Hicks"),
+            date: String::from("2013-09-05T21:03:42Z"),
+            title: String::from("Vatra Inc"),
+            body: paragraphs(vec![vec![
+                text("Vatra Inc"),
+                Inline::LineBreak,
+                Inline::LineBreak,
+                link("http://goo.gl/maps/luJXU", "http://goo.gl/maps/luJXU"),
+            ]]),
+            images: vec![Image::new("../Photos/a.jpg")],
+            links: vec![(
+                String::from("http://goo.gl/maps/luJXU"),
+                String::from("Google Maps"),
+            )],
+            visibility: Visibility::Public,
+            comments: vec![Comment {
+                author: String::from("Paul"),
+                date: String::from("2013-09-06T01:00:00Z"),
+                body: paragraphs(vec![vec![text("Yum")]]),
+                images: vec![
+                    String::from("../Photos/b.jpg"),
+                    String::from("https://lh3.googleusercontent.com/-x/AAuE7m=s64-c"),
+                ],
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+
+        assert_eq!(
+            generate_gemtext(&post, "2013-09-05"),
+            "# Vatra Inc\n\n\
+             2013-09-05T21:03:42Z by Christopher Hicks\n\n\
+             Vatra Inc\n\nhttp://goo.gl/maps/luJXU\n\n\
+             => http://goo.gl/maps/luJXU\n\n\
+             ## Images\n\n=> /posts/2013-09-05-a.jpg Image 1\n\n\
+             ## Links\n\n=> http://goo.gl/maps/luJXU Google Maps\n\n\
+             Shared with: Public\n\n\
+             ## Comments\n\n### Paul - 2013-09-06T01:00:00Z\n\nYum\n\n\
+             => /posts/2013-09-05-b.jpg Image\n\
+             => https://lh3.googleusercontent.com/-x/AAuE7m=s64-c Image\n"
+        );
+    }
+
+    #[test]
+    fn test_generate_gemtext_reshare() {
+        let post = PostData {
+            reshare_author: Some(String::from("Jake Gross")),
+            reshare_url: Some(String::from(
+                "https://plus.google.com/+JakeGross/posts/HgJTPoeCorQ",
+            )),
+            reshare_body: paragraphs(vec![vec![text("For the noobs.")]]),
+            ..Default::default()
+        };
+        assert!(generate_gemtext(&post, "").contains(
+            "## Originally shared by Jake Gross\n\n\
+             => https://plus.google.com/+JakeGross/posts/HgJTPoeCorQ Original post\n\n\
+             For the noobs.\n"
+        ));
+    }
+}
@@ -0,0 +1,619 @@
+//! Photo metadata from the Takeout
+//!
+//! Takeout writes a `.json` sidecar next to every photo in
+//! `Photos/Photos from posts/<album>/` with its title, description, geo
+//! data and creation time.  Photos get that metadata, and their pixel
+//! dimensions, as an `ImageInfo` so the output can use them for alt text,
+//! captions and front matter.
+//!
+//! With an `[exif]` section in the settings file, the EXIF data of the
+//! photos fills in when and where they were taken where the sidecar
+//! doesn't say, and a post without a location of its own gets the position
+//! of its first geotagged photo.
+
+use std::fs::File;
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, Duration, NaiveDate};
+use exif::{In, Tag, Value};
+use serde::Deserialize;
+
+use crate::media::local_media_path;
+use crate::models::{GeoPoint, Image, ImageInfo, PostData};
+
+/// File names are cut to this many characters before `.json` is added
+const SIDECAR_NAME_LIMIT: usize = 46;
+
+/// The parts of a Takeout sidecar that are used
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Sidecar {
+    title: Option<String>,
+    description: Option<String>,
+    creation_time: Option<Timestamp>,
+    photo_taken_time: Option<Timestamp>,
+    geo_data: Option<GeoData>,
+}
+
+#[derive(Deserialize)]
+struct Timestamp {
+    /// Seconds since the epoch, as a string
+    timestamp: String,
+}
+
+#[derive(Deserialize)]
+struct GeoData {
+    latitude: f64,
+    longitude: f64,
+}
+
+/// Add Takeout metadata to the local photos of a post
+///
+/// Photos get their sidecar metadata and dimensions.  A description fills
+/// in the alt text and caption when the post HTML had none.
+pub fn add_image_info(post_data: &mut PostData, post_file: &Path) {
+    for image in &mut post_data.images {
+        let Some(path) = local_media_path(post_file, &image.src) else {
+            continue;
+        };
+        if let Some(info) = load_image_info(&path) {
+            apply_image_info(image, info);
+        }
+    }
+}
+
+/// Metadata for the photo at `path`, `None` if nothing could be read
+///
+/// A sidecar that isn't valid JSON is reported and skipped, the
+/// dimensions are still read.
+pub fn load_image_info(path: &Path) -> Option<ImageInfo> {
+    let mut info = match sidecar_path(path) {
+        Some(sidecar) => match std::fs::read_to_string(&sidecar)
+            .map_err(|why| why.to_string())
+            .and_then(|json| parse_sidecar(&json))
+        {
+            Ok(info) => info,
+            Err(why) => {
+                eprintln!("Warning: couldn't read {}: {}", sidecar.display(), why);
+                ImageInfo::default()
+            }
+        },
+        None => ImageInfo::default(),
+    };
+    if let Ok(size) = imagesize::size(path) {
+        info.width = u32::try_from(size.width).ok();
+        info.height = u32::try_from(size.height).ok();
+    }
+
+    (info != ImageInfo::default()).then_some(info)
+}
+
+/// The sidecar of the photo at `path`, if there is one
+///
+/// Tries `name.jpg.json`, then `name.json`, then the name cut to the length
+/// Takeout allows.  Duplicates named `name(1).jpg` have their sidecar at
+/// `name.jpg(1).json`.
+pub fn sidecar_path(path: &Path) -> Option<PathBuf> {
+    let dir = path.parent()?;
+    let file_name = path.file_name()?.to_str()?;
+    let stem = path.file_stem()?.to_str()?;
+
+    let mut candidates = vec![format!("{}.json", file_name), format!("{}.json", stem)];
+    if let Some((base, copy)) = stem.strip_suffix(')').and_then(|s| s.rsplit_once('(')) {
+        let extension = path.extension().and_then(|e| e.to_str()).unwrap_or("");
+        candidates.push(format!("{}.{}({}).json", base, extension, copy));
+    }
+    if file_name.chars().count() > SIDECAR_NAME_LIMIT {
+        let cut: String = file_name.chars().take(SIDECAR_NAME_LIMIT).collect();
+        candidates.push(format!("{}.json", cut));
+    }
+
+    candidates
+        .into_iter()
+        .map(|name| dir.join(name))
+        .find(|candidate| candidate.is_file())
+}
+
+/// Parse a Takeout photo sidecar
+///
+/// Empty strings, `0,0` positions and unreadable timestamps count as
+/// missing.
+pub fn parse_sidecar(json: &str) -> Result<ImageInfo, String> {
+    let sidecar: Sidecar = serde_json::from_str(json).map_err(|why| why.to_string())?;
+    let non_empty = |text: Option<String>| {
+        text.map(|text| text.trim().to_string())
+            .filter(|text| !text.is_empty())
+    };
+
+    Ok(ImageInfo {
+        title: non_empty(sidecar.title),
+        description: non_empty(sidecar.description),
+        taken: sidecar.photo_taken_time.as_ref().and_then(utc_time),
+        created: sidecar.creation_time.as_ref().and_then(utc_time),
+        location: sidecar
+            .geo_data
+            .filter(|geo| geo.latitude != 0.0 || geo.longitude != 0.0)
+            .map(|geo| GeoPoint {
+                latitude: geo.latitude,
+                longitude: geo.longitude,
+            }),
+        width: None,
+        height: None,
+    })
+}
+
+/// A sidecar timestamp as `YYYY-MM-DDTHH:MM:SSZ`
+fn utc_time(timestamp: &Timestamp) -> Option<String> {
+    let seconds = timestamp.timestamp.trim().parse::<i64>().ok()?;
+    DateTime::from_timestamp(seconds, 0).map(|time| time.format("%Y-%m-%dT%H:%M:%SZ").to_string())
+}
+
+/// Attach metadata to a photo, filling in missing alt text and caption
+fn apply_image_info(image: &mut Image, info: ImageInfo) {
+    if let Some(description) = &info.description {
+        let description = description.split_whitespace().collect::<Vec<_>>().join(" ");
+        if image.alt.is_empty() {
+            image.alt = description.clone();
+        }
+        if image.caption.is_none() {
+            image.caption = Some(description);
+        }
+    }
+    image.info = Some(info);
+}
+
+/// Fill in when and where local photos were taken from their EXIF data
+///
+/// Only times and positions the sidecar left out are filled in.
+pub fn add_exif_info(post_data: &mut PostData, post_file: &Path) {
+    for image in &mut post_data.images {
+        let Some(path) = local_media_path(post_file, &image.src) else {
+            continue;
+        };
+        let (taken, location) = read_exif(&path);
+        if taken.is_none() && location.is_none() {
+            continue;
+        }
+        let info = image.info.get_or_insert_with(ImageInfo::default);
+        if info.taken.is_none() {
+            info.taken = taken;
+        }
+        if info.location.is_none() {
+            info.location = location;
+        }
+    }
+}
+
+/// When and where the photo at `path` was taken, according to its EXIF data
+///
+/// Files without EXIF data return nothing rather than an error.
+pub fn read_exif(path: &Path) -> (Option<String>, Option<GeoPoint>) {
+    let exif = File::open(path).ok().and_then(|file| {
+        exif::Reader::new()
+            .read_from_container(&mut BufReader::new(file))
+            .ok()
+    });
+    match exif {
+        Some(exif) => (exif_taken(&exif), exif_location(&exif)),
+        None => (None, None),
+    }
+}
+
+/// `DateTimeOriginal` as `YYYY-MM-DDTHH:MM:SSZ`
+///
+/// Cameras record local time, which is converted with `OffsetTimeOriginal`
+/// when there is one and taken as UTC otherwise.
+fn exif_taken(exif: &exif::Exif) -> Option<String> {
+    let mut time = exif::DateTime::from_ascii(exif_ascii(exif, Tag::DateTimeOriginal)?).ok()?;
+    if let Some(offset) = exif_ascii(exif, Tag::OffsetTimeOriginal) {
+        let _ = time.parse_offset(offset);
+    }
+
+    let local = NaiveDate::from_ymd_opt(time.year.into(), time.month.into(), time.day.into())?
+        .and_hms_opt(time.hour.into(), time.minute.into(), time.second.into())?;
+    let utc = local - Duration::minutes(time.offset.unwrap_or(0).into());
+    Some(utc.format("%Y-%m-%dT%H:%M:%SZ").to_string())
+}
+
+/// GPS position in decimal degrees, `0,0` counting as missing
+fn exif_location(exif: &exif::Exif) -> Option<GeoPoint> {
+    let latitude = exif_degrees(exif, Tag::GPSLatitude, Tag::GPSLatitudeRef, b'S')?;
+    let longitude = exif_degrees(exif, Tag::GPSLongitude, Tag::GPSLongitudeRef, b'W')?;
+    (latitude != 0.0 || longitude != 0.0).then_some(GeoPoint {
+        latitude,
+        longitude,
+    })
+}
+
+/// A GPS coordinate stored as degrees, minutes and seconds, negative when
+/// its reference is `negative` (south or west)
+fn exif_degrees(exif: &exif::Exif, tag: Tag, reference: Tag, negative: u8) -> Option<f64> {
+    let Value::Rational(parts) = &exif.get_field(tag, In::PRIMARY)?.value else {
+        return None;
+    };
+    let degrees = parts
+        .iter()
+        .zip([1.0, 60.0, 3600.0])
+        .map(|(part, scale)| part.to_f64() / scale)
+        .sum::<f64>();
+    if !degrees.is_finite() {
+        return None;
+    }
+
+    match exif_ascii(exif, reference).and_then(|reference| reference.first()) {
+        Some(&reference) if reference == negative => Some(-degrees),
+        _ => Some(degrees),
+    }
+}
+
+/// The first string of an ASCII field
+fn exif_ascii(exif: &exif::Exif, tag: Tag) -> Option<&[u8]> {
+    match &exif.get_field(tag, In::PRIMARY)?.value {
+        Value::Ascii(strings) => strings.first().map(Vec::as_slice),
+        _ => None,
+    }
+}
+
+/// Use the position of the first geotagged photo as the post's location
+///
+/// Only for posts without a location of their own.  The location is
+/// written as `latitude, longitude` and marked as inferred.  Returns
+/// whether a location was added.
+pub fn infer_location(post_data: &mut PostData) -> bool {
+    if post_data.location.is_some() {
+        return false;
+    }
+    let Some(point) = post_data
+        .images
+        .iter()
+        .find_map(|image| image.info.as_ref()?.location)
+    else {
+        return false;
+    };
+
+    post_data.location = Some(format!("{:.5}, {:.5}", point.latitude, point.longitude));
+    post_data.location_inferred = true;
+    true
+}
+
+/// A photo taken long before or after the post it is in
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DateGap {
+    /// When the photo was taken, in UTC
+    pub taken: String,
+    /// Whole days between the photo and the post
+    pub days: i64,
+}
+
+/// The photo taken furthest from the post's date, if that is more than
+/// `tolerance` days
+///
+/// Reshared old photos are common, but a large gap can also mean the post
+/// date is wrong.
+pub fn photo_date_gap(post_data: &PostData, tolerance: u32) -> Option<DateGap> {
+    let posted = DateTime::parse_from_rfc3339(&post_data.date).ok()?;
+    post_data
+        .images
+        .iter()
+        .filter_map(|image| image.info.as_ref()?.taken.as_deref())
+        .filter_map(|taken| {
+            let days = (posted - DateTime::parse_from_rfc3339(taken).ok()?)
+                .num_days()
+                .abs();
+            Some(DateGap {
+                taken: taken.to_string(),
+                days,
+            })
+        })
+        .filter(|gap| gap.days > i64::from(tolerance))
+        .max_by_key(|gap| gap.days)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_helpers::temp_dir;
+    use exif::experimental::Writer;
+    use exif::{Field, Rational};
+    use image::codecs::jpeg::JpegEncoder;
+    use image::{DynamicImage, ImageEncoder};
+
+    /// A 3x2 pixel GIF
+    const GIF: &[u8] = b"GIF89a\x03\x00\x02\x00\x00\x00\x00;";
+
+    const SIDECAR: &str = r#"{
+        "title": "IMG_0042.jpg",
+        "description": "Santa Monica Pier",
+        "imageViews": "12",
+        "creationTime": {"timestamp": "1338069600", "formatted": "May 26, 2012, 10:00:00 PM UTC"},
+        "photoTakenTime": {"timestamp": "1338050000", "formatted": "May 26, 2012, 4:33:20 PM UTC"},
+        "geoData": {"latitude": 34.0094, "longitude": -118.4973, "altitude": 0.0},
+        "url": "https://lh3.googleusercontent.com/x"
+    }"#;
+
+    #[test]
+    fn test_parse_sidecar() {
+        assert_eq!(
+            parse_sidecar(SIDECAR),
+            Ok(ImageInfo {
+                title: Some(String::from("IMG_0042.jpg")),
+                description: Some(String::from("Santa Monica Pier")),
+                taken: Some(String::from("2012-05-26T16:33:20Z")),
+                created: Some(String::from("2012-05-26T22:00:00Z")),
+                location: Some(GeoPoint {
+                    latitude: 34.0094,
+                    longitude: -118.4973,
+                }),
+                width: None,
+                height: None,
+            })
+        );
+    }
+
+    #[test]
+    fn test_parse_sidecar_missing_values() {
+        let json = r#"{"title": "", "description": " ", "photoTakenTime": {"timestamp": "soon"},
+            "geoData": {"latitude": 0.0, "longitude": 0.0, "altitude": 0.0}}"#;
+        assert_eq!(parse_sidecar(json), Ok(ImageInfo::default()));
+        assert!(parse_sidecar("[]").is_err());
+    }
+
+    #[test]
+    fn test_sidecar_path() {
+        let dir = temp_dir("imageinfo-sidecars");
+        for name in [
+            "a.jpg.json",
+            "b.json",
+            "c.jpg(1).json",
+            "a_very_long_file_name_that_takeout_had_to_cut_.json",
+        ] {
+            std::fs::write(dir.join(name), "{}").unwrap();
+        }
+
+        let sidecar = |name: &str| {
+            sidecar_path(&dir.join(name))
+                .map(|path| path.file_name().unwrap().to_str().unwrap().to_string())
+        };
+        assert_eq!(sidecar("a.jpg").as_deref(), Some("a.jpg.json"));
+        assert_eq!(sidecar("b.jpg").as_deref(), Some("b.json"));
+        assert_eq!(sidecar("c(1).jpg").as_deref(), Some("c.jpg(1).json"));
+        assert_eq!(
+            sidecar("a_very_long_file_name_that_takeout_had_to_cut_short.jpg").as_deref(),
+            Some("a_very_long_file_name_that_takeout_had_to_cut_.json")
+        );
+        assert_eq!(sidecar("d.jpg"), None);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_add_image_info() {
+        let takeout = temp_dir("imageinfo-takeout");
+        let album = takeout
+            .join("Photos")
+            .join("Photos from posts")
+            .join("Pier");
+        std::fs::create_dir_all(&album).unwrap();
+        std::fs::write(album.join("pier.gif"), GIF).unwrap();
+        std::fs::write(album.join("pier.gif.json"), SIDECAR).unwrap();
+        let post_file = takeout.join("Posts").join("20120526 - Post.html");
+
+        let mut post = PostData {
+            images: vec![
+                Image::new("../Photos/Photos%20from%20posts/Pier/pier.gif"),
+                Image::new("https://lh3.googleusercontent.com/x/w288-h288/y.jpg"),
+            ],
+            ..Default::default()
+        };
+        add_image_info(&mut post, &post_file);
+
+        let image = &post.images[0];
+        assert_eq!(image.alt, "Santa Monica Pier");
+        assert_eq!(image.caption.as_deref(), Some("Santa Monica Pier"));
+        assert_eq!(image.dimensions(), Some((3, 2)));
+        assert_eq!(
+            image.info.as_ref().unwrap().taken.as_deref(),
+            Some("2012-05-26T16:33:20Z")
+        );
+        assert_eq!(post.images[1].info, None);
+
+        std::fs::remove_dir_all(takeout).unwrap();
+    }
+
+    #[test]
+    fn test_apply_image_info_keeps_html_text() {
+        let mut image = Image {
+            alt: String::from("Pier at dusk"),
+            caption: Some(String::from("Pier at dusk")),
+            ..Image::new("pier.jpg")
+        };
+        apply_image_info(
+            &mut image,
+            ImageInfo {
+                description: Some(String::from("Santa Monica Pier")),
+                ..Default::default()
+            },
+        );
+        assert_eq!(image.alt, "Pier at dusk");
+        assert_eq!(image.caption.as_deref(), Some("Pier at dusk"));
+    }
+
+    #[test]
+    fn test_apply_image_info_caption_from_sidecar() {
+        let mut image = Image {
+            alt: String::from("Pier at dusk"),
+            ..Image::new("pier.jpg")
+        };
+        apply_image_info(
+            &mut image,
+            ImageInfo {
+                description: Some(String::from("Santa Monica\n Pier")),
+                ..Default::default()
+            },
+        );
+        assert_eq!(image.alt, "Pier at dusk");
+        assert_eq!(image.caption.as_deref(), Some("Santa Monica Pier"));
+    }
+
+    /// EXIF data with a capture time and GPS position
+    fn camera_exif(time: &str, offset: Option<&str>, latitude_ref: &str) -> Vec<u8> {
+        let ascii = |tag: Tag, text: &str| Field {
+            tag,
+            ifd_num: In::PRIMARY,
+            value: Value::Ascii(vec![text.as_bytes().to_vec()]),
+        };
+        let degrees = |tag: Tag, parts: [u32; 3]| Field {
+            tag,
+            ifd_num: In::PRIMARY,
+            value: Value::Rational(parts.iter().map(|&num| Rational::from((num, 1))).collect()),
+        };
+        let mut fields = vec![
+            ascii(Tag::DateTimeOriginal, time),
+            degrees(Tag::GPSLatitude, [34, 0, 36]),
+            ascii(Tag::GPSLatitudeRef, latitude_ref),
+            degrees(Tag::GPSLongitude, [118, 29, 24]),
+            ascii(Tag::GPSLongitudeRef, "W"),
+        ];
+        if let Some(offset) = offset {
+            fields.push(ascii(Tag::OffsetTimeOriginal, offset));
+        }
+
+        let mut writer = Writer::new();
+        for field in &fields {
+            writer.push_field(field);
+        }
+        let mut tiff = std::io::Cursor::new(Vec::new());
+        writer.write(&mut tiff, false).unwrap();
+        tiff.into_inner()
+    }
+
+    fn write_jpeg(path: &Path, exif: Vec<u8>) {
+        let mut encoder = JpegEncoder::new(File::create(path).unwrap());
+        encoder.set_exif_metadata(exif).unwrap();
+        DynamicImage::new_rgb8(2, 2)
+            .write_with_encoder(encoder)
+            .unwrap();
+    }
+
+    #[test]
+    fn test_read_exif() {
+        let dir = temp_dir("imageinfo-exif");
+        let path = dir.join("pier.jpg");
+
+        write_jpeg(
+            &path,
+            camera_exif("2012:05:26 09:33:20", Some("-07:00"), "N"),
+        );
+        let (taken, location) = read_exif(&path);
+        assert_eq!(taken.as_deref(), Some("2012-05-26T16:33:20Z"));
+        let location = location.unwrap();
+        assert!((location.latitude - 34.01).abs() < 1e-9);
+        assert!((location.longitude + 118.49).abs() < 1e-9);
+
+        write_jpeg(&path, camera_exif("2012:05:26 09:33:20", None, "S"));
+        let (taken, location) = read_exif(&path);
+        assert_eq!(taken.as_deref(), Some("2012-05-26T09:33:20Z"));
+        assert!(location.unwrap().latitude < 0.0);
+
+        std::fs::write(&path, GIF).unwrap();
+        assert_eq!(read_exif(&path), (None, None));
+        assert_eq!(read_exif(&dir.join("missing.jpg")), (None, None));
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_add_exif_info_keeps_sidecar() {
+        let dir = temp_dir("imageinfo-exif-sidecar");
+        write_jpeg(
+            &dir.join("pier.jpg"),
+            camera_exif("2012:05:20 09:33:20", None, "N"),
+        );
+        let post_file = dir.join("post.html");
+
+        let mut post = PostData {
+            images: vec![
+                Image {
+                    info: Some(ImageInfo {
+                        taken: Some(String::from("2012-05-26T16:33:20Z")),
+                        ..Default::default()
+                    }),
+                    ..Image::new("pier.jpg")
+                },
+                Image::new("missing.jpg"),
+            ],
+            ..Default::default()
+        };
+        add_exif_info(&mut post, &post_file);
+
+        let info = post.images[0].info.as_ref().unwrap();
+        assert_eq!(info.taken.as_deref(), Some("2012-05-26T16:33:20Z"));
+        assert!(info.location.is_some());
+        assert_eq!(post.images[1].info, None);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    fn taken_at(taken: &str, location: Option<GeoPoint>) -> Image {
+        Image {
+            info: Some(ImageInfo {
+                taken: Some(taken.to_string()),
+                location,
+                ..Default::default()
+            }),
+            ..Image::new("a.jpg")
+        }
+    }
+
+    #[test]
+    fn test_infer_location() {
+        let pier = GeoPoint {
+            latitude: 34.0094,
+            longitude: -118.4973,
+        };
+        let mut post = PostData {
+            images: vec![
+                taken_at("2012-05-26T16:33:20Z", None),
+                taken_at("2012-05-26T16:33:20Z", Some(pier)),
+            ],
+            ..Default::default()
+        };
+        assert!(infer_location(&mut post));
+        assert_eq!(post.location.as_deref(), Some("34.00940, -118.49730"));
+        assert!(post.location_inferred);
+
+        let mut post = PostData {
+            location: Some(String::from("Santa Monica Pier")),
+            images: vec![taken_at("2012-05-26T16:33:20Z", Some(pier))],
+            ..Default::default()
+        };
+        assert!(!infer_location(&mut post));
+        assert_eq!(post.location.as_deref(), Some("Santa Monica Pier"));
+        assert!(!post.location_inferred);
+    }
+
+    #[test]
+    fn test_photo_date_gap() {
+        let mut post = PostData {
+            date: String::from("2012-06-10T12:00:00Z"),
+            images: vec![
+                taken_at("2012-06-09T12:00:00Z", None),
+                taken_at("2012-05-26T16:33:20Z", None),
+                Image::new("b.jpg"),
+            ],
+            ..Default::default()
+        };
+        assert_eq!(
+            photo_date_gap(&post, 3),
+            Some(DateGap {
+                taken: String::from("2012-05-26T16:33:20Z"),
+                days: 14,
+            })
+        );
+        assert_eq!(photo_date_gap(&post, 14), None);
+
+        post.date = String::new();
+        assert_eq!(photo_date_gap(&post, 0), None);
+    }
+}
@@ -1,13 +1,43 @@
 //! Library for parsing Google+ Takeout HTML files and converting to Markdown
 
+pub mod activitypub;
+pub mod comments;
+pub mod config;
+pub mod content;
+pub mod cover;
+pub mod crosslinks;
 pub mod dom;
+pub mod fetch;
+pub mod filter;
+pub mod gemtext;
+pub mod imageinfo;
+pub mod linkcheck;
+pub mod links;
 pub mod markdown;
+pub mod media;
 pub mod models;
 pub mod parser;
+pub mod plaintext;
+pub mod privacy;
+pub mod shortlinks;
+#[cfg(test)]
+mod stub_server;
+pub mod template;
+#[cfg(test)]
+mod test_helpers;
 pub mod utils;
+pub mod variants;
+pub mod video;
+pub mod wayback;
 
 // Re-export main types and functions for convenient access
+pub use comments::comments_to_json;
+pub use content::RichText;
+pub use gemtext::generate_gemtext;
+pub use links::normalize_post_links;
 pub use markdown::generate_markdown;
 pub use models::{Comment, PostData};
 pub use parser::extract_post_data;
+pub use plaintext::generate_plain_text;
+pub use template::PostTemplate;
 pub use utils::format_filename_date;
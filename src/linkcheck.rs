@@ -0,0 +1,514 @@
+//! Checking which links in the archive still work
+//!
+//! Every URL a post points at is requested (`HEAD`, falling back to `GET`
+//! for servers that refuse it), redirects are followed by hand so they can
+//! be reported, and results are cached on disk so a re-run only checks
+//! what it hasn't seen.
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+use crate::content::{Inline, RichText};
+use crate::media::post_media;
+use crate::models::PostData;
+use crate::utils::to_pretty_json;
+
+/// Requests in flight at once unless told otherwise
+pub const DEFAULT_CONCURRENCY: usize = 8;
+
+/// Redirects followed before giving up on a link
+const MAX_REDIRECTS: usize = 10;
+
+/// What became of a link
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LinkState {
+    /// Answers with a success status
+    Live,
+    /// Redirects somewhere that answers with a success status
+    Redirected,
+    /// Answers with an error status, redirects to one, or can't be reached
+    Dead,
+}
+
+/// Result of checking one URL
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LinkCheck {
+    pub url: String,
+    pub state: LinkState,
+    /// Last HTTP status seen, if the server answered
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub status: Option<u16>,
+    /// Where redirects ended up
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub location: Option<String>,
+    /// Why the link couldn't be checked
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Checks links over HTTP, optionally through a proxy
+pub struct LinkChecker {
+    agent: ureq::Agent,
+    concurrency: usize,
+}
+
+impl LinkChecker {
+    /// Check links directly, or through `proxy` (`http://host:port` or
+    /// `socks5://host:port`)
+    pub fn new(proxy: Option<&str>) -> Result<Self, String> {
+        let mut builder = ureq::AgentBuilder::new()
+            .timeout(Duration::from_secs(30))
+            .redirects(0)
+            .user_agent(concat!(
+                env!("CARGO_PKG_NAME"),
+                "/",
+                env!("CARGO_PKG_VERSION")
+            ));
+        if let Some(proxy) = proxy {
+            builder = builder.proxy(ureq::Proxy::new(proxy).map_err(|why| why.to_string())?);
+        }
+        Ok(LinkChecker {
+            agent: builder.build(),
+            concurrency: DEFAULT_CONCURRENCY,
+        })
+    }
+
+    /// Number of links to check at once
+    pub fn with_concurrency(mut self, concurrency: usize) -> Self {
+        self.concurrency = concurrency.max(1);
+        self
+    }
+
+    /// Check one URL, following redirects
+    pub fn check(&self, url: &str) -> LinkCheck {
+        let mut current = url.to_string();
+        let mut redirected = false;
+
+        for _ in 0..=MAX_REDIRECTS {
+            let status = match self.status(&current) {
+                Ok(status) => status,
+                Err(error) => {
+                    return LinkCheck {
+                        url: url.to_string(),
+                        state: LinkState::Dead,
+                        status: None,
+                        location: redirected.then_some(current),
+                        error: Some(error),
+                    }
+                }
+            };
+
+            match status {
+                (300..=399, Some(location)) => {
+                    current = match Url::parse(&current).and_then(|base| base.join(&location)) {
+                        Ok(next) => next.to_string(),
+                        Err(_) => location,
+                    };
+                    redirected = true;
+                }
+                (status, _) => {
+                    let state = match status {
+                        200..=299 if redirected => LinkState::Redirected,
+                        200..=299 => LinkState::Live,
+                        _ => LinkState::Dead,
+                    };
+                    return LinkCheck {
+                        url: url.to_string(),
+                        state,
+                        status: Some(status),
+                        location: redirected.then_some(current),
+                        error: None,
+                    };
+                }
+            }
+        }
+
+        LinkCheck {
+            url: url.to_string(),
+            state: LinkState::Dead,
+            status: None,
+            location: Some(current),
+            error: Some(format!("more than {} redirects", MAX_REDIRECTS)),
+        }
+    }
+
+    /// Status and `Location` header of one request, trying `GET` when
+    /// `HEAD` isn't allowed
+    fn status(&self, url: &str) -> Result<(u16, Option<String>), String> {
+        let response = match self.request("HEAD", url)? {
+            (405 | 501, _) => self.request("GET", url)?,
+            response => response,
+        };
+        Ok(response)
+    }
+
+    fn request(&self, method: &str, url: &str) -> Result<(u16, Option<String>), String> {
+        let response = match self.agent.request(method, url).call() {
+            Ok(response) => response,
+            Err(ureq::Error::Status(_, response)) => response,
+            Err(why) => return Err(why.to_string()),
+        };
+        let location = response.header("location").map(str::to_string);
+        Ok((response.status(), location))
+    }
+
+    /// Check every URL not already in `cache`, adding the results to it
+    ///
+    /// Runs up to the configured number of checks at once.  `progress` is
+    /// called with each result as it comes in.
+    pub fn check_all(
+        &self,
+        urls: &[String],
+        cache: &mut LinkCache,
+        mut progress: impl FnMut(&LinkCheck),
+    ) {
+        let mut pending: Vec<String> = Vec::new();
+        for url in urls {
+            if cache.get(url).is_none() && !pending.contains(url) {
+                pending.push(url.clone());
+            }
+        }
+        if pending.is_empty() {
+            return;
+        }
+
+        let queue = Arc::new(Mutex::new(pending));
+        let (sender, receiver) = mpsc::channel();
+        thread::scope(|scope| {
+            for _ in 0..self.concurrency {
+                let queue = Arc::clone(&queue);
+                let sender = sender.clone();
+                scope.spawn(move || loop {
+                    let next = queue.lock().unwrap().pop();
+                    match next {
+                        Some(url) => {
+                            if sender.send(self.check(&url)).is_err() {
+                                break;
+                            }
+                        }
+                        None => break,
+                    }
+                });
+            }
+            drop(sender);
+
+            for check in receiver {
+                progress(&check);
+                cache.insert(check);
+            }
+        });
+    }
+}
+
+/// Link check results kept between runs
+#[derive(Debug, Default)]
+pub struct LinkCache {
+    path: Option<PathBuf>,
+    checks: BTreeMap<String, LinkCheck>,
+}
+
+impl LinkCache {
+    /// Load the cache at `path`, starting empty if there is none yet
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let checks = match std::fs::read_to_string(path) {
+            Ok(json) => {
+                let checks: Vec<LinkCheck> =
+                    serde_json::from_str(&json).map_err(|why| why.to_string())?;
+                checks
+                    .into_iter()
+                    .map(|check| (check.url.clone(), check))
+                    .collect()
+            }
+            Err(why) if why.kind() == std::io::ErrorKind::NotFound => BTreeMap::new(),
+            Err(why) => return Err(why.to_string()),
+        };
+        Ok(LinkCache {
+            path: Some(path.to_path_buf()),
+            checks,
+        })
+    }
+
+    /// Write the cache back to where it was loaded from
+    ///
+    /// Links that couldn't be reached at all aren't saved, so they are
+    /// tried again next time.
+    pub fn save(&self) -> Result<(), String> {
+        let path = match &self.path {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+        let checks: Vec<&LinkCheck> = self
+            .checks
+            .values()
+            .filter(|check| check.status.is_some())
+            .collect();
+        std::fs::write(path, to_pretty_json(&checks)).map_err(|why| why.to_string())
+    }
+
+    pub fn get(&self, url: &str) -> Option<&LinkCheck> {
+        self.checks.get(url)
+    }
+
+    pub fn insert(&mut self, check: LinkCheck) {
+        self.checks.insert(check.url.clone(), check);
+    }
+}
+
+/// Every http(s) URL a post points at, in order of appearance
+///
+/// Links in the post, reshare and comment bodies, embedded links, and every
+/// photo, video, link preview and author photo with an http(s) source, see
+/// `post_media()`.  Mentions and hashtags point back at
+/// Google+ and are left out.
+pub fn post_urls(post_data: &PostData) -> Vec<String> {
+    let mut urls: Vec<&str> = Vec::new();
+    let body_links = |body: &RichText| -> Vec<String> {
+        body.inlines()
+            .into_iter()
+            .filter_map(|inline| match inline {
+                Inline::Link { href, .. } => Some(href.clone()),
+                _ => None,
+            })
+            .collect()
+    };
+
+    let mut body_urls = body_links(&post_data.body);
+    body_urls.extend(body_links(&post_data.reshare_body));
+    for comment in &post_data.comments {
+        body_urls.extend(body_links(&comment.body));
+    }
+    urls.extend(body_urls.iter().map(String::as_str));
+    urls.extend(post_data.links.iter().map(|(url, _)| url.as_str()));
+    for comment in &post_data.comments {
+        urls.extend(comment.links.iter().map(|(url, _)| url.as_str()));
+    }
+    urls.extend(post_media(post_data));
+
+    let mut unique: Vec<String> = Vec::new();
+    for url in urls {
+        let is_http = url.starts_with("http://") || url.starts_with("https://");
+        if is_http && !unique.iter().any(|seen| seen == url) {
+            unique.push(url.to_string());
+        }
+    }
+    unique
+}
+
+/// Dead, redirected and live links of one post
+#[derive(Debug, Default, Serialize)]
+pub struct PostLinkReport {
+    /// Output name of the post, without extension
+    pub post: String,
+    pub dead: Vec<LinkCheck>,
+    pub redirected: Vec<LinkCheck>,
+    pub live: Vec<String>,
+}
+
+impl PostLinkReport {
+    /// Sort a post's URLs by their cached results
+    ///
+    /// URLs missing from the cache are left out.
+    pub fn new(post: &str, urls: &[String], cache: &LinkCache) -> Self {
+        let mut report = PostLinkReport {
+            post: post.to_string(),
+            ..Default::default()
+        };
+        for check in urls.iter().filter_map(|url| cache.get(url)) {
+            match check.state {
+                LinkState::Live => report.live.push(check.url.clone()),
+                LinkState::Redirected => report.redirected.push(check.clone()),
+                LinkState::Dead => report.dead.push(check.clone()),
+            }
+        }
+        report
+    }
+}
+
+/// Serialize link check reports as a JSON array
+pub fn link_check_reports_to_json(reports: &[PostLinkReport]) -> String {
+    to_pretty_json(reports)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{Comment, Image};
+    use crate::stub_server::{StubResponse, StubServer};
+    use crate::test_helpers::{link, mention, paragraph, temp_dir};
+
+    fn stub() -> StubServer {
+        StubServer::start(|target| {
+            // Requests through the stub as a proxy carry the full URL
+            let path = target
+                .strip_prefix("http://archive.example")
+                .unwrap_or(target);
+            match path {
+                "/live" => StubResponse::ok("hello"),
+                "/moved" => StubResponse::redirect("/live"),
+                "/moved-to-dead" => StubResponse::redirect("/gone"),
+                "/loop" => StubResponse::redirect("/loop"),
+                "/no-head" => StubResponse::status(405),
+                _ => StubResponse::status(404),
+            }
+        })
+    }
+
+    #[test]
+    fn test_check_live() {
+        let server = stub();
+        let checker = LinkChecker::new(None).unwrap();
+        let check = checker.check(&format!("{}/live", server.url));
+        assert_eq!(check.state, LinkState::Live);
+        assert_eq!(check.status, Some(200));
+        assert_eq!(check.location, None);
+    }
+
+    #[test]
+    fn test_check_redirected() {
+        let server = stub();
+        let checker = LinkChecker::new(None).unwrap();
+        let check = checker.check(&format!("{}/moved", server.url));
+        assert_eq!(check.state, LinkState::Redirected);
+        assert_eq!(check.location, Some(format!("{}/live", server.url)));
+    }
+
+    #[test]
+    fn test_check_dead() {
+        let server = stub();
+        let checker = LinkChecker::new(None).unwrap();
+        let check = checker.check(&format!("{}/gone", server.url));
+        assert_eq!(check.state, LinkState::Dead);
+        assert_eq!(check.status, Some(404));
+
+        let check = checker.check(&format!("{}/moved-to-dead", server.url));
+        assert_eq!(check.state, LinkState::Dead);
+        assert_eq!(check.location, Some(format!("{}/gone", server.url)));
+
+        let check = checker.check(&format!("{}/loop", server.url));
+        assert_eq!(check.state, LinkState::Dead);
+        assert_eq!(check.error, Some(String::from("more than 10 redirects")));
+
+        let check = checker.check("http://127.0.0.1:9/unreachable");
+        assert_eq!(check.state, LinkState::Dead);
+        assert_eq!(check.status, None);
+        assert!(check.error.is_some());
+    }
+
+    #[test]
+    fn test_check_falls_back_to_get() {
+        let server = stub();
+        let checker = LinkChecker::new(None).unwrap();
+        // The stub answers 405 to both, but it has to be asked twice
+        checker.check(&format!("{}/no-head", server.url));
+        assert_eq!(server.requests(), vec!["/no-head", "/no-head"]);
+    }
+
+    #[test]
+    fn test_check_through_proxy() {
+        let server = stub();
+        let checker = LinkChecker::new(Some(&server.url)).unwrap();
+        let check = checker.check("http://archive.example/live");
+        assert_eq!(check.state, LinkState::Live);
+        assert_eq!(server.requests(), vec!["http://archive.example/live"]);
+    }
+
+    #[test]
+    fn test_check_all_uses_cache() {
+        let server = stub();
+        let checker = LinkChecker::new(None).unwrap().with_concurrency(4);
+        let urls: Vec<String> = ["/live", "/moved", "/gone", "/live"]
+            .iter()
+            .map(|path| format!("{}{}", server.url, path))
+            .collect();
+
+        let mut cache = LinkCache::default();
+        let mut seen = 0;
+        checker.check_all(&urls, &mut cache, |_| seen += 1);
+        assert_eq!(seen, 3);
+        // "/moved" also fetches "/live" while following the redirect
+        assert_eq!(server.requests().len(), 4);
+
+        checker.check_all(&urls, &mut cache, |_| seen += 1);
+        assert_eq!(seen, 3);
+        assert_eq!(server.requests().len(), 4);
+
+        let report = PostLinkReport::new("2013-09-05-Vatra", &urls, &cache);
+        assert_eq!(report.live, vec![urls[0].clone(), urls[0].clone()]);
+        assert_eq!(report.redirected[0].url, urls[1]);
+        assert_eq!(report.dead[0].url, urls[2]);
+    }
+
+    #[test]
+    fn test_link_cache_round_trip() {
+        let dir = temp_dir("linkcheck-cache");
+        let path = dir.join("link-cache.json");
+
+        let mut cache = LinkCache::load(&path).unwrap();
+        cache.insert(LinkCheck {
+            url: String::from("http://example.com/"),
+            state: LinkState::Live,
+            status: Some(200),
+            location: None,
+            error: None,
+        });
+        cache.insert(LinkCheck {
+            url: String::from("http://unreachable.example/"),
+            state: LinkState::Dead,
+            status: None,
+            location: None,
+            error: Some(String::from("dns failed")),
+        });
+        cache.save().unwrap();
+
+        let reloaded = LinkCache::load(&path).unwrap();
+        std::fs::remove_dir_all(dir).unwrap();
+        assert_eq!(
+            reloaded.get("http://example.com/").map(|check| check.state),
+            Some(LinkState::Live)
+        );
+        // Unreachable links are tried again next run
+        assert_eq!(reloaded.get("http://unreachable.example/"), None);
+    }
+
+    #[test]
+    fn test_post_urls() {
+        let post = PostData {
+            body: paragraph(vec![
+                link("http://example.com/a", "a"),
+                mention("https://plus.google.com/1", "Ann"),
+            ]),
+            links: vec![(String::from("http://example.com/a"), String::from("A"))],
+            video_url: Some(String::from("https://youtu.be/x")),
+            images: vec![
+                Image::new("../Photos/a.jpg"),
+                Image::new("https://lh3.googleusercontent.com/b.jpg"),
+            ],
+            link_preview: Some(Image::new("https://example.net/preview.jpg")),
+            author_photo: Some(String::from("https://lh3.googleusercontent.com/me.jpg")),
+            comments: vec![Comment {
+                links: vec![(String::from("https://example.org/"), String::new())],
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        assert_eq!(
+            post_urls(&post),
+            vec![
+                "http://example.com/a",
+                "https://example.org/",
+                "https://lh3.googleusercontent.com/b.jpg",
+                "https://youtu.be/x",
+                "https://example.net/preview.jpg",
+                "https://lh3.googleusercontent.com/me.jpg"
+            ]
+        );
+    }
+}
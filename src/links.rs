@@ -0,0 +1,555 @@
+//! Link normalization
+//!
+//! Google+ wrapped many outgoing links in `google.com/url?q=` redirectors,
+//! shared pages came with `utm_*` tracking parameters and profile links
+//! turn up in several spellings.  Normalizing a link expands short links
+//! from a `ShortLinkMap`, unwraps and strips those so the archive points
+//! straight at the target, and every change is reported so it can be
+//! reviewed.
+
+use std::fmt;
+
+use serde::Serialize;
+use url::Url;
+
+use crate::models::PostData;
+use crate::shortlinks::ShortLinkMap;
+use crate::utils::to_pretty_json;
+
+/// A change made while normalizing a link
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Normalization {
+    /// Expanded a short link from the short link map
+    ShortLink,
+    /// Unwrapped a `google.com/url?q=` redirector
+    GoogleRedirect,
+    /// Removed `utm_*` query parameters
+    TrackingParameters,
+    /// Rewrote a `plus.google.com` profile link to its short form
+    ProfileLink,
+    /// Pointed a link to a post at its copy in the archive
+    ArchivedPost,
+}
+
+impl fmt::Display for Normalization {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Normalization::ShortLink => "short link",
+            Normalization::GoogleRedirect => "Google redirect",
+            Normalization::TrackingParameters => "tracking parameters",
+            Normalization::ProfileLink => "profile link",
+            Normalization::ArchivedPost => "post in archive",
+        })
+    }
+}
+
+/// One link that was rewritten, and why
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct LinkRewrite {
+    pub from: String,
+    pub to: String,
+    pub normalizations: Vec<Normalization>,
+}
+
+/// The links rewritten in one post
+#[derive(Debug, Serialize)]
+pub struct LinkReport {
+    /// Output name of the post, without extension
+    pub post: String,
+    pub rewrites: Vec<LinkRewrite>,
+    /// Links to Google+ posts that are not in the archive
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub missing_posts: Vec<String>,
+}
+
+/// Most redirectors and short links unwrapped from one link, in case a
+/// short link map loops
+const MAX_UNWRAPS: usize = 16;
+
+/// Normalize a single link, returning it unchanged when no rule applies
+pub fn normalize_link(href: &str) -> String {
+    normalize(href, &ShortLinkMap::default()).0
+}
+
+/// Normalize a link and list the changes made, in the order applied
+fn normalize(href: &str, short_links: &ShortLinkMap) -> (String, Vec<Normalization>) {
+    let mut href = href.to_string();
+    let mut normalizations = Vec::new();
+
+    // Redirectors wrap short links and short links expand to redirectors,
+    // so take turns until neither applies.  Long URLs then get the same
+    // cleanup as any other link.
+    for _ in 0..MAX_UNWRAPS {
+        let (next, normalization) = if let Some(long) = short_links.expand(&href) {
+            (long.to_string(), Normalization::ShortLink)
+        } else if let Some(target) = google_redirect_target(&href) {
+            (target, Normalization::GoogleRedirect)
+        } else {
+            break;
+        };
+        href = next;
+        if !normalizations.contains(&normalization) {
+            normalizations.push(normalization);
+        }
+    }
+    if let Some(stripped) = strip_tracking_parameters(&href) {
+        href = stripped;
+        normalizations.push(Normalization::TrackingParameters);
+    }
+    if let Some(profile) = profile_link(&href) {
+        href = profile;
+        normalizations.push(Normalization::ProfileLink);
+    }
+
+    (href, normalizations)
+}
+
+/// Normalize every link in a post and its comments
+///
+/// Covers inline links, mentions and hashtags in the post, reshare and
+/// comment bodies, the embedded links list, the video link and author
+/// profile links.  The canonical post URL and image sources are left as
+/// they are.  Short links found in `short_links` are expanded, also those
+/// inside redirectors.  Returns each distinct rewrite once.
+pub fn normalize_post_links(
+    post_data: &mut PostData,
+    short_links: &ShortLinkMap,
+) -> Vec<LinkRewrite> {
+    let mut rewrites: Vec<LinkRewrite> = Vec::new();
+    rewrite_post_links(post_data, |href| {
+        let (normalized, normalizations) = normalize(href, short_links);
+        if normalized == href {
+            return None;
+        }
+        if !rewrites.iter().any(|rewrite| rewrite.from == href) {
+            rewrites.push(LinkRewrite {
+                from: href.to_string(),
+                to: normalized.clone(),
+                normalizations,
+            });
+        }
+        Some(normalized)
+    });
+    rewrites
+}
+
+/// Replace links in a post and its comments
+///
+/// `rewrite` is called with every link URL (see `normalize_post_links()`
+/// for which) and returns the replacement, or `None` to keep the link.
+pub fn rewrite_post_links(
+    post_data: &mut PostData,
+    mut rewrite: impl FnMut(&str) -> Option<String>,
+) {
+    let mut apply = |href: &mut String| {
+        if let Some(new_href) = rewrite(href) {
+            *href = new_href;
+        }
+    };
+
+    apply(&mut post_data.author_url);
+    if let Some(reshare_url) = &mut post_data.reshare_url {
+        apply(reshare_url);
+    }
+    if let Some(video_url) = &mut post_data.video_url {
+        apply(video_url);
+    }
+    for link in &mut post_data.links {
+        rewrite_link(link, &mut apply);
+    }
+    for (url, _) in &mut post_data.mentions {
+        apply(url);
+    }
+    for comment in &mut post_data.comments {
+        apply(&mut comment.author_url);
+        for link in &mut comment.links {
+            rewrite_link(link, &mut apply);
+        }
+        for (url, _) in &mut comment.mentions {
+            apply(url);
+        }
+    }
+
+    // Bodies last
+    post_data.body.rewrite_hrefs(&mut rewrite);
+    post_data.reshare_body.rewrite_hrefs(&mut rewrite);
+    for comment in &mut post_data.comments {
+        comment.body.rewrite_hrefs(&mut rewrite);
+    }
+}
+
+/// Rewrite a `(url, title)` link, keeping a title that repeats the URL
+/// in step with it
+fn rewrite_link((url, title): &mut (String, String), apply: &mut impl FnMut(&mut String)) {
+    let bare = title == url;
+    apply(url);
+    if bare {
+        *title = url.clone();
+    }
+}
+
+/// Serialize link reports as a JSON array
+pub fn link_reports_to_json(reports: &[LinkReport]) -> String {
+    to_pretty_json(reports)
+}
+
+/// The link with any `google.com/url?q=` redirectors around it removed
+pub(crate) fn unwrap_redirects(href: &str) -> String {
+    let mut href = href.to_string();
+    for _ in 0..MAX_UNWRAPS {
+        match google_redirect_target(&href) {
+            Some(target) => href = target,
+            None => break,
+        }
+    }
+    href
+}
+
+/// Parse a link, allowing the scheme-relative `//host/path` form
+pub(crate) fn parse_link(href: &str) -> Option<Url> {
+    if href.starts_with("//") {
+        Url::parse(&format!("https:{}", href)).ok()
+    } else {
+        Url::parse(href).ok()
+    }
+}
+
+/// The target of a `google.com/url?q=` (or `?url=`) redirector
+fn google_redirect_target(href: &str) -> Option<String> {
+    let url = parse_link(href)?;
+    let host = url.host_str()?;
+    let is_google = host == "google.com"
+        || host.ends_with(".google.com")
+        || host.starts_with("google.")
+        || host.starts_with("www.google.");
+    if !is_google || url.path() != "/url" {
+        return None;
+    }
+
+    // Only unwrap to real links, `q` is a search query on some pages
+    url.query_pairs()
+        .find(|(name, _)| name == "q" || name == "url")
+        .map(|(_, target)| target.into_owned())
+        .filter(|target| {
+            Url::parse(target).is_ok_and(|url| matches!(url.scheme(), "http" | "https"))
+        })
+}
+
+/// The link without its `utm_*` query parameters, if it had any
+///
+/// Works on the text of the link so the remaining parameters keep their
+/// original order and encoding.
+fn strip_tracking_parameters(href: &str) -> Option<String> {
+    let (rest, fragment) = match href.split_once('#') {
+        Some((rest, fragment)) => (rest, Some(fragment)),
+        None => (href, None),
+    };
+    let (base, query) = rest.split_once('?')?;
+
+    let is_tracking = |parameter: &&str| {
+        let name = parameter.split('=').next().unwrap_or_default();
+        name.to_ascii_lowercase().starts_with("utm_")
+    };
+    if !query.split('&').any(|parameter| is_tracking(&parameter)) {
+        return None;
+    }
+
+    let kept: Vec<&str> = query
+        .split('&')
+        .filter(|parameter| !parameter.is_empty() && !is_tracking(parameter))
+        .collect();
+    let mut stripped = base.to_string();
+    if !kept.is_empty() {
+        stripped.push('?');
+        stripped.push_str(&kept.join("&"));
+    }
+    if let Some(fragment) = fragment {
+        stripped.push('#');
+        stripped.push_str(fragment);
+    }
+    Some(stripped)
+}
+
+/// The `https://plus.google.com/<id>` form of a profile link
+///
+/// Accepts numeric IDs and `+Name` vanity URLs, with or without a
+/// `/u/<n>` account prefix, `/posts` or `/about` suffix, query string or
+/// `http` scheme.  Links to individual posts, hashtags or communities are
+/// not profile links and return `None`, as do links already in short form.
+fn profile_link(href: &str) -> Option<String> {
+    let url = parse_link(href)?;
+    if url.host_str()? != "plus.google.com" {
+        return None;
+    }
+
+    let mut segments: Vec<&str> = url.path_segments()?.filter(|s| !s.is_empty()).collect();
+    if segments.len() >= 2 && segments[0] == "u" && segments[1].parse::<u32>().is_ok() {
+        segments.drain(..2);
+    }
+    let (profile, rest) = segments.split_first()?;
+    let is_profile = (profile.len() > 1 && profile.starts_with('+'))
+        || (profile.len() >= 10 && profile.chars().all(|c| c.is_ascii_digit()));
+    if !is_profile || !matches!(rest, [] | ["posts"] | ["about"]) {
+        return None;
+    }
+
+    let normalized = format!("https://plus.google.com/{}", profile);
+    (normalized != href).then_some(normalized)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::Comment;
+    use crate::test_helpers::{link, paragraph};
+
+    #[test]
+    fn test_normalize_link_google_redirect() {
+        assert_eq!(
+            normalize_link(
+                "https://www.google.com/url?q=http%3A%2F%2Fexample.com%2Fa%3Fb%3D1&sa=D&usg=AFQ"
+            ),
+            "http://example.com/a?b=1"
+        );
+    }
+
+    #[test]
+    fn test_normalize_link_nested_redirect() {
+        let inner = "https://www.google.com/url?q=https%3A%2F%2Fexample.com%2F";
+        let outer = format!(
+            "http://google.com/url?url={}",
+            url::form_urlencoded::byte_serialize(inner.as_bytes()).collect::<String>()
+        );
+        assert_eq!(
+            normalize(&outer, &ShortLinkMap::default()),
+            (
+                String::from("https://example.com/"),
+                vec![Normalization::GoogleRedirect]
+            )
+        );
+    }
+
+    #[test]
+    fn test_normalize_link_redirect_needs_url_target() {
+        let search = "https://www.google.com/url?q=cats";
+        assert_eq!(normalize_link(search), search);
+        let maps = "https://www.google.com/maps/search/?api=1&query=34.1,%20-118.3";
+        assert_eq!(normalize_link(maps), maps);
+    }
+
+    #[test]
+    fn test_normalize_link_strips_utm_parameters() {
+        assert_eq!(
+            normalize_link(
+                "http://www.npr.org/2016/12/08/504815928/trump?utm_campaign=storyshare&utm_source=plus.google.com&utm_medium=social"
+            ),
+            "http://www.npr.org/2016/12/08/504815928/trump"
+        );
+        assert_eq!(
+            normalize_link("https://wapo.st/2mtZzzw?tid=ss_gp&utm_term=.464d23d342e3#top"),
+            "https://wapo.st/2mtZzzw?tid=ss_gp#top"
+        );
+    }
+
+    #[test]
+    fn test_normalize_link_keeps_other_parameters_verbatim() {
+        let href = "https://example.com/?q=a+b%20c&utmost=1";
+        assert_eq!(normalize_link(href), href);
+    }
+
+    #[test]
+    fn test_normalize_link_redirect_then_utm() {
+        assert_eq!(
+            normalize(
+                "https://www.google.com/url?q=https://example.com/story?id%3D7%26UTM_SOURCE%3Dgplus",
+                &ShortLinkMap::default()
+            ),
+            (
+                String::from("https://example.com/story?id=7"),
+                vec![
+                    Normalization::GoogleRedirect,
+                    Normalization::TrackingParameters
+                ]
+            )
+        );
+    }
+
+    #[test]
+    fn test_normalize_link_profiles() {
+        for href in [
+            "http://plus.google.com/116599377175135138184",
+            "https://plus.google.com/u/0/116599377175135138184/posts",
+            "https://plus.google.com/116599377175135138184/about?hl=en",
+            "//plus.google.com/116599377175135138184/",
+        ] {
+            assert_eq!(
+                normalize_link(href),
+                "https://plus.google.com/116599377175135138184",
+                "{}",
+                href
+            );
+        }
+        assert_eq!(
+            normalize_link("https://plus.google.com/+WingWong/posts?rel=author"),
+            "https://plus.google.com/+WingWong"
+        );
+    }
+
+    #[test]
+    fn test_normalize_link_leaves_other_plus_links() {
+        for href in [
+            "https://plus.google.com/+WingWong",
+            "https://plus.google.com/+ChristopherHicksFINI/posts/6nFPRjoLERU",
+            "https://plus.google.com/s/%23Linux/posts",
+            "https://plus.google.com/communities/1234567890123",
+        ] {
+            assert_eq!(normalize_link(href), href);
+        }
+    }
+
+    #[test]
+    fn test_normalize_link_unchanged() {
+        assert_eq!(normalize_link("http://example.com"), "http://example.com");
+        assert_eq!(normalize_link("not a url"), "not a url");
+    }
+
+    #[test]
+    fn test_normalize_expands_short_links_first() {
+        let short_links = ShortLinkMap::from_csv(
+            "http://goo.gl/news/Tb5W2,https://example.com/news?utm_medium=social\n",
+        )
+        .unwrap();
+        assert_eq!(
+            normalize("https://goo.gl/news/Tb5W2", &short_links),
+            (
+                String::from("https://example.com/news"),
+                vec![Normalization::ShortLink, Normalization::TrackingParameters]
+            )
+        );
+        assert_eq!(
+            normalize("http://goo.gl/maps/luJXU", &short_links),
+            (String::from("http://goo.gl/maps/luJXU"), vec![])
+        );
+    }
+
+    #[test]
+    fn test_normalize_expands_short_links_in_redirects() {
+        let short_links = ShortLinkMap::from_csv(
+            "http://goo.gl/news/Tb5W2,https://www.google.com/url?q=https://example.com/news\n",
+        )
+        .unwrap();
+        assert_eq!(
+            normalize(
+                "https://www.google.com/url?q=http%3A%2F%2Fgoo.gl%2Fnews%2FTb5W2&sa=D",
+                &short_links
+            ),
+            (
+                String::from("https://example.com/news"),
+                vec![Normalization::GoogleRedirect, Normalization::ShortLink]
+            )
+        );
+    }
+
+    #[test]
+    fn test_normalize_post_links_expands_embeds_and_comments() {
+        let short_links =
+            ShortLinkMap::from_csv("http://goo.gl/maps/luJXU,https://maps.example.com/vatra\n")
+                .unwrap();
+        let mut post = PostData {
+            links: vec![
+                (
+                    String::from("http://goo.gl/maps/luJXU"),
+                    String::from("Google Maps"),
+                ),
+                (
+                    String::from("http://goo.gl/maps/luJXU"),
+                    String::from("http://goo.gl/maps/luJXU"),
+                ),
+            ],
+            comments: vec![Comment {
+                body: paragraph(vec![link(
+                    "http://goo.gl/maps/luJXU",
+                    "http://goo.gl/maps/luJXU",
+                )]),
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+
+        let rewrites = normalize_post_links(&mut post, &short_links);
+
+        assert_eq!(post.links[0].0, "https://maps.example.com/vatra");
+        assert_eq!(post.links[1].1, "https://maps.example.com/vatra");
+        assert_eq!(
+            post.comments[0].content(),
+            "<https://maps.example.com/vatra>"
+        );
+        assert_eq!(rewrites.len(), 1);
+        assert_eq!(rewrites[0].normalizations, vec![Normalization::ShortLink]);
+    }
+
+    #[test]
+    fn test_normalize_post_links() {
+        let tracked = "http://example.com/?utm_source=gplus";
+        let body = |href: &str| paragraph(vec![link(href, "read")]);
+        let mut post = PostData {
+            author_url: String::from("https://plus.google.com/u/0/+PaulFernandez"),
+            body: body(tracked),
+            links: vec![(tracked.to_string(), String::from("Example"))],
+            comments: vec![Comment {
+                body: body("https://www.google.com/url?q=http://example.org/"),
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+
+        let rewrites = normalize_post_links(&mut post, &ShortLinkMap::default());
+
+        assert_eq!(post.author_url, "https://plus.google.com/+PaulFernandez");
+        assert_eq!(post.content(), "[read](http://example.com/)");
+        assert_eq!(post.links[0].0, "http://example.com/");
+        assert_eq!(post.comments[0].content(), "[read](http://example.org/)");
+        assert_eq!(
+            rewrites,
+            vec![
+                LinkRewrite {
+                    from: String::from("https://plus.google.com/u/0/+PaulFernandez"),
+                    to: String::from("https://plus.google.com/+PaulFernandez"),
+                    normalizations: vec![Normalization::ProfileLink],
+                },
+                LinkRewrite {
+                    from: tracked.to_string(),
+                    to: String::from("http://example.com/"),
+                    normalizations: vec![Normalization::TrackingParameters],
+                },
+                LinkRewrite {
+                    from: String::from("https://www.google.com/url?q=http://example.org/"),
+                    to: String::from("http://example.org/"),
+                    normalizations: vec![Normalization::GoogleRedirect],
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_link_reports_to_json() {
+        let reports = [LinkReport {
+            post: String::from("2013-09-05-Vatra"),
+            rewrites: vec![LinkRewrite {
+                from: String::from("http://example.com/?utm_source=x"),
+                to: String::from("http://example.com/"),
+                normalizations: vec![Normalization::TrackingParameters],
+            }],
+            missing_posts: Vec::new(),
+        }];
+        let parsed: serde_json::Value =
+            serde_json::from_str(&link_reports_to_json(&reports)).unwrap();
+        assert_eq!(parsed[0]["post"], "2013-09-05-Vatra");
+        assert_eq!(
+            parsed[0]["rewrites"][0]["normalizations"][0],
+            "tracking_parameters"
+        );
+        assert!(parsed[0].get("missing_posts").is_none());
+    }
+}
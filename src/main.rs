@@ -19,34 +19,468 @@
 extern crate html5ever;
 extern crate markup5ever_rcdom as rcdom;
 
+use std::collections::BTreeSet;
 use std::env;
 use std::fs::File;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
 use html5ever::parse_document;
 use html5ever::tendril::TendrilSink;
 use rcdom::RcDom;
 
 use glob::glob;
-use google_plus_posts_dumper::{extract_post_data, format_filename_date, generate_markdown};
+use google_plus_posts_dumper::activitypub::{outbox_to_json, post_activities, Activity};
+use google_plus_posts_dumper::comments::{
+    comments_to_isso, comments_to_staticman, isso_threads_to_json, post_path, IssoThread,
+};
+use google_plus_posts_dumper::config::{Config, ExifMode, FilterConfig};
+use google_plus_posts_dumper::crosslinks::{link_archived_posts, ArchiveIndex};
+use google_plus_posts_dumper::fetch::ImageFetcher;
+use google_plus_posts_dumper::filter::PostFilter;
+use google_plus_posts_dumper::imageinfo::{
+    add_exif_info, add_image_info, infer_location, photo_date_gap,
+};
+use google_plus_posts_dumper::linkcheck::{
+    link_check_reports_to_json, post_urls, LinkCache, LinkChecker, LinkState, PostLinkReport,
+};
+use google_plus_posts_dumper::links::{link_reports_to_json, LinkReport};
+use google_plus_posts_dumper::markdown::post_title;
+use google_plus_posts_dumper::media::{copy_media, local_media_path, post_media};
+use google_plus_posts_dumper::models::PostData;
+use google_plus_posts_dumper::privacy::{redacted_file_name, Redactor, Treatment};
+use google_plus_posts_dumper::shortlinks::{post_short_links, short_links_to_csv, ShortLinkMap};
+use google_plus_posts_dumper::utils::slugify;
+use google_plus_posts_dumper::variants::{make_variants, strip_exif};
+use google_plus_posts_dumper::wayback::{Wayback, WaybackMode};
+use google_plus_posts_dumper::{
+    comments_to_json, extract_post_data, format_filename_date, generate_gemtext, generate_markdown,
+    generate_plain_text, normalize_post_links, PostTemplate,
+};
+
+/// Site path prefix posts are served under, matching the image paths
+const DEFAULT_POST_URL_PREFIX: &str = "/posts/";
+
+/// Where comments end up in the output
+#[derive(Default, PartialEq)]
+enum CommentsMode {
+    /// Under "## Comments" in each Markdown file
+    #[default]
+    Inline,
+    /// In a separate `comments/<slug>.json` data file per post
+    Data,
+    /// As Staticman entries in `comments/<slug>/entry*.yml`
+    Staticman,
+    /// In one `isso-comments.json` file for `isso import`
+    Isso,
+}
+
+/// Which built-in layout posts are written in
+#[derive(Default, PartialEq)]
+enum OutputFormat {
+    /// Hugo Markdown with TOML front matter
+    #[default]
+    Markdown,
+    /// Gemini gemtext
+    Gemtext,
+    /// Plain text wrapped for reading in a terminal
+    Text,
+}
+
+impl OutputFormat {
+    /// File extension for generated posts
+    fn extension(&self) -> &'static str {
+        match self {
+            OutputFormat::Markdown => "md",
+            OutputFormat::Gemtext => "gmi",
+            OutputFormat::Text => "txt",
+        }
+    }
+}
+
+/// Command line options given alongside the positional arguments
+#[derive(Default)]
+struct Options {
+    /// TOML settings file
+    config: Option<String>,
+    /// Template file to render posts with instead of the built-in layout
+    template: Option<String>,
+    /// Built-in layout to write, also picks the file extension
+    format: OutputFormat,
+    /// Where comments are written
+    comments: CommentsMode,
+    /// Root for data files, defaults to `data/` inside the destination
+    data_dir: Option<String>,
+    /// Where to copy photos and videos from the Takeout
+    copy_media: Option<String>,
+    /// Where to keep downloaded copies of remote images
+    fetch_images: Option<String>,
+    /// Which posts to publish, on top of the settings file
+    filter: FilterConfig,
+    /// Write posts made in a community into a directory named after it
+    community_dirs: bool,
+    /// Site path prefix used to key comment threads to posts
+    post_url_prefix: Option<String>,
+    /// Where to write an ActivityPub outbox of the whole archive
+    outbox: Option<String>,
+    /// Actor URL for the outbox
+    actor: Option<String>,
+    /// Base URL of the published site, for absolute links in the outbox
+    site_url: Option<String>,
+    /// Where to write a JSON report of every normalized link
+    link_report: Option<String>,
+    /// CSV or JSON map of short links to expand
+    short_links: Option<String>,
+    /// Add Wayback Machine links to external links
+    wayback: Option<WaybackMode>,
+    /// Availability API to check Wayback Machine snapshots against
+    wayback_check: Option<String>,
+    /// Proxy to check links through
+    proxy: Option<String>,
+    /// Where `check-links` keeps results between runs
+    link_cache: Option<String>,
+    /// Number of links `check-links` checks at once
+    concurrency: Option<usize>,
+}
+
+/// Link rewriting applied to every post before output
+struct LinkStages {
+    short_links: ShortLinkMap,
+    /// Every post in the archive, so posts can link to each other
+    archive_index: ArchiveIndex,
+    wayback: Option<Wayback>,
+}
+
+/// Output collected across every post and written once at the end
+#[derive(Default)]
+struct ArchiveOutputs {
+    isso_threads: Vec<IssoThread>,
+    activities: Vec<Activity>,
+    link_reports: Vec<LinkReport>,
+}
 
 fn main() {
     // get directory argument and verify that it is actually a directory
-    let args: Vec<String> = env::args().collect();
+    let (options, args) = parse_args(env::args().skip(1));
     // dbg!(args);
-    let base_path_arg = args.get(1).expect("Missing required argument: source directory path (e.g., 'examples' or path to Google+ Takeout)");
+
+    // optional map to expand short links with
+    let short_links = match &options.short_links {
+        Some(map_path) => ShortLinkMap::load(Path::new(map_path))
+            .unwrap_or_else(|why| panic!("couldn't load short links {}: {}", map_path, why)),
+        None => ShortLinkMap::default(),
+    };
+
+    if args.first().map(String::as_str) == Some("short-links") {
+        let base_path_arg = args
+            .get(1)
+            .expect("Missing required argument: source directory path to list short links from");
+        list_short_links(base_path_arg, &short_links);
+        return;
+    }
+
+    if args.first().map(String::as_str) == Some("check-links") {
+        let base_path_arg = args
+            .get(1)
+            .expect("Missing required argument: source directory path to check links in");
+        let report_path = args
+            .get(2)
+            .expect("Missing required argument: path of the JSON link report to write");
+        check_links(base_path_arg, report_path, &options, &short_links);
+        return;
+    }
+
+    // optional Wayback Machine links, checked if asked to
+    let wayback = match (options.wayback, &options.wayback_check) {
+        (Some(mode), Some(endpoint)) => Some(Wayback::new(mode).with_availability_check(endpoint)),
+        (Some(mode), None) => Some(Wayback::new(mode)),
+        (None, Some(_)) => panic!("--wayback-check needs --wayback companion or replace"),
+        (None, None) => None,
+    };
+    let mut link_stages = LinkStages {
+        short_links,
+        archive_index: ArchiveIndex::default(),
+        wayback,
+    };
+
+    let base_path_arg = args.first().expect("Missing required argument: source directory path (e.g., 'examples' or path to Google+ Takeout)");
     let base_path = Path::new(base_path_arg);
     assert_dir(base_path);
 
     // destination directory
-    let dest_path_arg = args.get(2).expect(
+    let dest_path_arg = args.get(1).expect(
         "Missing required argument: destination directory path for generated Markdown files",
     );
     let dest_path = Path::new(dest_path_arg);
     assert_dir(dest_path);
 
+    // optional settings file
+    let config = match &options.config {
+        Some(config_path) => Config::load(Path::new(config_path))
+            .unwrap_or_else(|why| panic!("couldn't load settings {}: {}", config_path, why)),
+        None => Config::default(),
+    };
+    if config.images.is_some() && options.copy_media.is_none() {
+        panic!("[images] in the settings file needs --copy-media");
+    }
+    if options.fetch_images.is_some() && options.copy_media.is_none() {
+        panic!("--fetch-images needs --copy-media");
+    }
+    if options.outbox.is_some() && (options.actor.is_none() || options.site_url.is_none()) {
+        panic!("--outbox needs --actor and --site-url");
+    }
+
+    // optional output template
+    let template = options.template.as_ref().map(|template_path| {
+        let source = std::fs::read_to_string(template_path)
+            .unwrap_or_else(|why| panic!("couldn't read template {}: {}", template_path, why));
+        PostTemplate::new(source)
+            .unwrap_or_else(|why| panic!("couldn't compile template {}: {}", template_path, why))
+            .with_cover(config.cover.clone())
+    });
+
+    // data files go next to the Markdown unless told otherwise
+    let data_dir = match &options.data_dir {
+        Some(data_dir) => Path::new(data_dir).to_path_buf(),
+        None => dest_path.join("data"),
+    };
+
+    // optional cache of downloaded remote images
+    let mut fetcher = options.fetch_images.as_ref().map(|cache_dir| {
+        ImageFetcher::new(Path::new(cache_dir), &config.fetch)
+            .unwrap_or_else(|why| panic!("couldn't use image cache {}: {}", cache_dir, why))
+    });
+
+    // Posts to leave out, from the settings file and the command line
+    let mut filter = PostFilter::new(&config.filter.clone().overridden_by(options.filter.clone()))
+        .unwrap_or_else(|why| panic!("couldn't use filter: {}", why));
+
+    // optional redaction of private posts and other people
+    let mut redactor = config.privacy.as_ref().map(Redactor::new);
+
+    // Parse every post first, links between them need the whole archive
+    let (mut inferred_locations, mut date_gaps) = (0, 0);
+    let posts: Vec<(String, PostData)> = post_files(base_path_arg, true)
+        .iter()
+        .filter_map(|path| {
+            let mut file_name = path.display().to_string();
+            let mut post_data = parse_post_file(&file_name);
+            if !filter.keep(&post_data) {
+                return None;
+            }
+            if let Some(redactor) = &mut redactor {
+                match redactor.apply(&mut post_data) {
+                    Treatment::Published => {}
+                    Treatment::Redacted => {
+                        file_name = redacted_file_name(path, &post_data.canonical_url)
+                            .display()
+                            .to_string();
+                    }
+                    Treatment::Skipped => return None,
+                }
+            }
+            if let Some(fetcher) = &mut fetcher {
+                fetcher.localize_images(&mut post_data);
+            }
+            add_image_info(&mut post_data, path);
+            if let Some(exif) = &config.exif {
+                add_exif_info(&mut post_data, path);
+                if infer_location(&mut post_data) {
+                    inferred_locations += 1;
+                }
+                if let Some(gap) = photo_date_gap(&post_data, exif.date_tolerance) {
+                    eprintln!(
+                        "Warning: {} is dated {} but has a photo taken {}, {} days apart",
+                        file_name, post_data.date, gap.taken, gap.days
+                    );
+                    date_gaps += 1;
+                }
+            }
+            Some((file_name, post_data))
+        })
+        .collect();
+    if filter.excluded_total() > 0 {
+        println!("Left out {} posts:", filter.excluded_total());
+        for (exclusion, count) in &filter.excluded {
+            println!("\t{} {}", count, exclusion);
+        }
+    }
+    if let Some(redactor) = &redactor {
+        println!(
+            "Left out {} and redacted {} posts that weren't public",
+            redactor.skipped, redactor.redacted
+        );
+    }
+    if config.exif.is_some() {
+        println!(
+            "Took {} locations from photos, {} posts have photos from other dates",
+            inferred_locations, date_gaps
+        );
+    }
+    if let Some(fetcher) = &fetcher {
+        if let Err(why) = fetcher.save() {
+            panic!("couldn't write image cache index: {}", why);
+        }
+        println!(
+            "Downloaded {} remote images, {} failed",
+            fetcher.downloaded, fetcher.failed
+        );
+    }
+    for (file_name, post_data) in &posts {
+        let path = post_link(&options, &output_name(&options, file_name, post_data));
+        link_stages
+            .archive_index
+            .insert(&post_data.canonical_url, &path);
+    }
+
+    // Loop through parsed posts
+    let mut archive = ArchiveOutputs::default();
+    for (file_name, post_data) in posts {
+        process_file(
+            &file_name,
+            post_data,
+            dest_path_arg,
+            &data_dir,
+            &options,
+            &config,
+            template.as_ref(),
+            &mut link_stages,
+            &mut archive,
+        );
+    }
+
+    // Isso imports the whole archive from a single file
+    if options.comments == CommentsMode::Isso {
+        write_data_file(
+            &data_dir,
+            "isso-comments.json",
+            &isso_threads_to_json(&archive.isso_threads),
+        );
+    }
+
+    if let Some(outbox_path) = &options.outbox {
+        let json = outbox_to_json(archive.activities);
+        match std::fs::write(outbox_path, json) {
+            Err(why) => panic!("couldn't write {}: {}", outbox_path, why),
+            Ok(_) => println!("generated {:?}", outbox_path),
+        }
+    }
+
+    let missing_posts: usize = archive
+        .link_reports
+        .iter()
+        .map(|report| report.missing_posts.len())
+        .sum();
+    if missing_posts > 0 {
+        println!("{} links to posts not in the archive", missing_posts);
+    }
+
+    if let Some(report_path) = &options.link_report {
+        let json = link_reports_to_json(&archive.link_reports);
+        match std::fs::write(report_path, json) {
+            Err(why) => panic!("couldn't write {}: {}", report_path, why),
+            Ok(_) => println!("generated {:?}", report_path),
+        }
+    }
+}
+
+/// Split the arguments into options and positional arguments
+///
+/// Options take a value either as `--name value` or `--name=value`.
+fn parse_args(mut args: impl Iterator<Item = String>) -> (Options, Vec<String>) {
+    let mut options = Options::default();
+    let mut positional = Vec::new();
+
+    while let Some(arg) = args.next() {
+        if !arg.starts_with("--") {
+            positional.push(arg);
+            continue;
+        }
+
+        let (name, inline_value) = match arg.split_once('=') {
+            Some((name, value)) => (name.to_string(), Some(value.to_string())),
+            None => (arg, None),
+        };
+        let mut value = || {
+            inline_value
+                .clone()
+                .or_else(|| args.next())
+                .unwrap_or_else(|| panic!("Missing value for {}", name))
+        };
+
+        match name.as_str() {
+            "--config" => options.config = Some(value()),
+            "--template" => options.template = Some(value()),
+            "--format" => {
+                options.format = match value().as_str() {
+                    "markdown" => OutputFormat::Markdown,
+                    "gemtext" => OutputFormat::Gemtext,
+                    "text" => OutputFormat::Text,
+                    other => panic!(
+                        "Unknown --format {:?}, use markdown, gemtext or text",
+                        other
+                    ),
+                }
+            }
+            "--comments" => {
+                options.comments = match value().as_str() {
+                    "inline" => CommentsMode::Inline,
+                    "data" => CommentsMode::Data,
+                    "staticman" => CommentsMode::Staticman,
+                    "isso" => CommentsMode::Isso,
+                    other => panic!(
+                        "Unknown --comments mode {:?}, use inline, data, staticman or isso",
+                        other
+                    ),
+                }
+            }
+            "--data-dir" => options.data_dir = Some(value()),
+            "--copy-media" => options.copy_media = Some(value()),
+            "--fetch-images" => options.fetch_images = Some(value()),
+            "--since" => options.filter.since = Some(value()),
+            "--until" => options.filter.until = Some(value()),
+            "--visibility" => options.filter.visibility.push(value()),
+            "--author" => options.filter.authors.push(value()),
+            "--no-reshares" => options.filter.reshares = false,
+            "--with-images" => options.filter.with_images = true,
+            "--match" => options.filter.matching = Some(value()),
+            "--exclude" => options.filter.excluding = Some(value()),
+            "--community-dirs" => options.community_dirs = true,
+            "--post-url-prefix" => options.post_url_prefix = Some(value()),
+            "--outbox" => options.outbox = Some(value()),
+            "--actor" => options.actor = Some(value()),
+            "--site-url" => options.site_url = Some(value()),
+            "--link-report" => options.link_report = Some(value()),
+            "--short-links" => options.short_links = Some(value()),
+            "--wayback" => {
+                options.wayback = match value().as_str() {
+                    "companion" => Some(WaybackMode::Companion),
+                    "replace" => Some(WaybackMode::Replace),
+                    other => panic!(
+                        "Unknown --wayback mode {:?}, use companion or replace",
+                        other
+                    ),
+                }
+            }
+            "--wayback-check" => options.wayback_check = Some(value()),
+            "--proxy" => options.proxy = Some(value()),
+            "--link-cache" => options.link_cache = Some(value()),
+            "--concurrency" => {
+                let concurrency = value();
+                options.concurrency = Some(concurrency.parse().unwrap_or_else(|_| {
+                    panic!("--concurrency needs a number, not {:?}", concurrency)
+                }))
+            }
+            _ => panic!("Unknown option: {}", name),
+        }
+    }
+
+    (options, positional)
+}
+
+/// Find the post HTML files, in a Takeout or a plain directory of posts
+fn post_files(base_path_arg: &str, verbose: bool) -> Vec<PathBuf> {
     // find posts directory - either Google+ structure or direct examples
-    let posts_path = Path::new(base_path).join("Google+ Stream/Posts");
+    let posts_path = Path::new(base_path_arg).join("Google+ Stream/Posts");
     let posts_path_string = if posts_path.exists() && posts_path.is_dir() {
         // Original Google+ Takeout structure
         posts_path
@@ -57,81 +491,372 @@ fn main() {
         // Direct examples directory or other structure
         base_path_arg.to_string()
     };
-    println!("Posts are in {posts_path_string:?}");
 
-    // Loop through html files
     let mut post_pattern: String = posts_path_string.clone();
     post_pattern.push_str("/*.html");
-    println!("Debug: {post_pattern}");
+    if verbose {
+        println!("Posts are in {posts_path_string:?}");
+        println!("Debug: {post_pattern}");
+    }
+
+    let mut paths = Vec::new();
     for entry in glob(&post_pattern).expect("Failed to glob") {
         match entry {
-            Ok(path) => process_file(&path.display().to_string(), dest_path_arg),
-            Err(e) => println!("{:?}", e),
+            Ok(path) => paths.push(path),
+            Err(e) => eprintln!("{:?}", e),
         }
     }
+    paths
 }
 
-/// Is it a valid directory?
-fn assert_dir(dir_path: &Path) {
-    assert!(dir_path.exists());
-    assert!(dir_path.is_dir());
+/// Print every short link in the archive as a CSV map to fill in
+fn list_short_links(base_path_arg: &str, short_links: &ShortLinkMap) {
+    assert_dir(Path::new(base_path_arg));
+
+    let mut found = BTreeSet::new();
+    for path in post_files(base_path_arg, false) {
+        let post_data = parse_post_file(&path.display().to_string());
+        found.extend(post_short_links(&post_data));
+    }
+    print!("{}", short_links_to_csv(&found, short_links));
 }
 
-/// Parse an HTML file and generate Markdown
-fn process_file(file_name: &str, dest_dir: &str) {
-    let file_path = Path::new(file_name);
-    assert!(file_path.exists());
-    assert!(file_path.is_file());
+/// Check every link in the archive and write a report of dead, redirected
+/// and live links per post
+fn check_links(
+    base_path_arg: &str,
+    report_path: &str,
+    options: &Options,
+    short_links: &ShortLinkMap,
+) {
+    assert_dir(Path::new(base_path_arg));
 
-    println!("processing {:?}", file_name);
-    println!("\tinto {:?}", dest_dir);
+    // Check links as they will appear in the output
+    let mut posts = Vec::new();
+    for path in post_files(base_path_arg, false) {
+        let file_name = path.display().to_string();
+        let mut post_data = parse_post_file(&file_name);
+        normalize_post_links(&mut post_data, short_links);
+        posts.push((post_name(&file_name), post_urls(&post_data)));
+    }
 
-    // Open the path in read-only mode, returns `io::Result<File>`
-    let mut file_handle = match File::open(file_path) {
-        Err(why) => panic!("couldn't open {}: {}", file_name, why),
-        Ok(file_handle) => file_handle,
+    // results are kept next to the report unless told otherwise
+    let cache_path = match &options.link_cache {
+        Some(cache_path) => PathBuf::from(cache_path),
+        None => Path::new(report_path).with_file_name("link-cache.json"),
     };
+    let mut cache = LinkCache::load(&cache_path)
+        .unwrap_or_else(|why| panic!("couldn't load {}: {}", cache_path.display(), why));
 
-    // HTML parsing
-    let dom = parse_document(RcDom::default(), Default::default())
-        .from_utf8()
-        .read_from(&mut file_handle)
-        .unwrap_or_else(|_| panic!("Failed to parse HTML from file: {}", file_name));
-    // Parse the document to extract post data
-    // Note: html5ever may report parsing errors, but they typically don't affect extraction
+    let mut checker = LinkChecker::new(options.proxy.as_deref())
+        .unwrap_or_else(|why| panic!("couldn't use proxy: {}", why));
+    if let Some(concurrency) = options.concurrency {
+        checker = checker.with_concurrency(concurrency);
+    }
+    let urls: Vec<String> = posts.iter().flat_map(|(_, urls)| urls.clone()).collect();
+    checker.check_all(&urls, &mut cache, |check| {
+        println!("{:?}\t{}", check.state, check.url);
+    });
+    if let Err(why) = cache.save() {
+        panic!("couldn't write {}: {}", cache_path.display(), why);
+    }
 
-    let post_data = extract_post_data(&dom.document);
+    let reports: Vec<PostLinkReport> = posts
+        .iter()
+        .map(|(post, urls)| PostLinkReport::new(post, urls, &cache))
+        .collect();
+    let unique: BTreeSet<&String> = urls.iter().collect();
+    let count = |state: LinkState| {
+        unique
+            .iter()
+            .filter(|url| cache.get(url).is_some_and(|check| check.state == state))
+            .count()
+    };
+    println!(
+        "{} live, {} redirected, {} dead",
+        count(LinkState::Live),
+        count(LinkState::Redirected),
+        count(LinkState::Dead)
+    );
+    match std::fs::write(report_path, link_check_reports_to_json(&reports)) {
+        Err(why) => panic!("couldn't write {}: {}", report_path, why),
+        Ok(_) => println!("generated {:?}", report_path),
+    }
+}
 
-    // Generate output filename and extract date prefix
-    let input_filename = file_path
+/// Output name of a post, the input filename with its date reformatted
+fn post_name(file_name: &str) -> String {
+    let input_filename = Path::new(file_name)
         .file_stem()
         .unwrap_or_else(|| panic!("Failed to extract filename stem from: {}", file_name))
         .to_str()
         .unwrap_or_else(|| panic!("Filename contains invalid UTF-8: {}", file_name));
-    let formatted_name = format_filename_date(input_filename);
-    let output_filename = format!("{}.md", formatted_name);
+    format_filename_date(input_filename)
+}
+
+/// Output path of a post below the destination, without extension
+///
+/// With `--community-dirs` posts made in a community go in a directory
+/// named after it, like `rust-programming/2014-08-22-Title`.
+fn output_name(options: &Options, file_name: &str, post_data: &PostData) -> String {
+    let name = post_name(file_name);
+    match post_data.visibility.community() {
+        Some(community) if options.community_dirs => {
+            let dir = slugify(community);
+            let dir = if dir.is_empty() { "community" } else { &dir };
+            format!("{}/{}", dir, name)
+        }
+        _ => name,
+    }
+}
+
+/// Where a post is published, for links from other posts
+///
+/// Hugo serves each Markdown post from its own directory, gemtext and
+/// plain text files are linked to directly.
+fn post_link(options: &Options, name: &str) -> String {
+    let url_prefix = options
+        .post_url_prefix
+        .as_deref()
+        .unwrap_or(DEFAULT_POST_URL_PREFIX);
+    match options.format {
+        OutputFormat::Markdown => post_path(url_prefix, name),
+        OutputFormat::Gemtext | OutputFormat::Text => format!(
+            "/{}/{}.{}",
+            url_prefix.trim_matches('/'),
+            name,
+            options.format.extension()
+        )
+        .replace("//", "/"),
+    }
+}
+
+/// Is it a valid directory?
+fn assert_dir(dir_path: &Path) {
+    assert!(dir_path.exists());
+    assert!(dir_path.is_dir());
+}
+
+/// Generate Markdown, gemtext or plain text from a parsed HTML file
+#[allow(clippy::too_many_arguments)]
+fn process_file(
+    file_name: &str,
+    mut post_data: PostData,
+    dest_dir: &str,
+    data_dir: &Path,
+    options: &Options,
+    config: &Config,
+    template: Option<&PostTemplate>,
+    link_stages: &mut LinkStages,
+    archive: &mut ArchiveOutputs,
+) {
+    println!("processing {:?}", file_name);
+    println!("\tinto {:?}", dest_dir);
+
+    // Generate output filename and extract date prefix.  Every file made
+    // for the post is named after the output, community directory included.
+    let input_name = post_name(file_name);
+    let formatted_name = output_name(options, file_name, &post_data);
+    let output_filename = format!("{}.{}", formatted_name, options.format.extension());
 
     // Extract date prefix (YYYY-MM-DD) from formatted filename
     // Check if it matches the expected format: YYYY-MM-DD
-    let date_prefix = if formatted_name.len() >= 10
-        && formatted_name.as_bytes().get(4) == Some(&b'-')
-        && formatted_name.as_bytes().get(7) == Some(&b'-')
+    let date_prefix = if input_name.len() >= 10
+        && input_name.as_bytes().get(4) == Some(&b'-')
+        && input_name.as_bytes().get(7) == Some(&b'-')
     {
-        &formatted_name[..10]
+        &input_name[..10]
     } else {
         eprintln!(
             "Warning: Could not extract date prefix from filename '{}' - images will use empty date prefix",
-            formatted_name
+            input_name
         );
         ""
     };
 
-    let markdown_content = generate_markdown(&post_data, date_prefix);
+    // Unwrap redirectors and strip tracking from links before any output
+    let mut rewrites = normalize_post_links(&mut post_data, &link_stages.short_links);
+    for rewrite in &rewrites {
+        let reasons: Vec<String> = rewrite
+            .normalizations
+            .iter()
+            .map(|normalization| normalization.to_string())
+            .collect();
+        println!(
+            "\trewrote {} -> {} ({})",
+            rewrite.from,
+            rewrite.to,
+            reasons.join(", ")
+        );
+    }
+
+    // Links to other archived posts point at their copies
+    let references = link_archived_posts(&mut post_data, &link_stages.archive_index);
+    for rewrite in &references.linked {
+        println!("\tlinked {} -> {}", rewrite.from, rewrite.to);
+    }
+    for missing in &references.missing {
+        println!("\tnot in archive: {}", missing);
+    }
+
+    rewrites.extend(references.linked);
+    if !rewrites.is_empty() || !references.missing.is_empty() {
+        archive.link_reports.push(LinkReport {
+            post: formatted_name.clone(),
+            rewrites,
+            missing_posts: references.missing,
+        });
+    }
+
+    // Archived copies are of the cleaned up links
+    if let Some(wayback) = &mut link_stages.wayback {
+        wayback.add_archive_links(&mut post_data);
+    }
+
+    // Photos and videos go where the post links to them
+    // The originals lose their EXIF data too, resized copies link to them
+    let strip_exif_data = config
+        .images
+        .as_ref()
+        .is_some_and(|images| images.exif == ExifMode::Strip);
+    if let Some(media_dir) = &options.copy_media {
+        for src in post_media(&post_data) {
+            match copy_media(Path::new(file_name), src, date_prefix, Path::new(media_dir)) {
+                Ok(Some(target)) => {
+                    println!("\tcopied {:?}", target);
+                    if strip_exif_data {
+                        match strip_exif(&target) {
+                            Ok(true) => println!("\tstripped EXIF data from {:?}", target),
+                            Ok(false) => {}
+                            Err(why) => eprintln!("Warning: {}", why),
+                        }
+                    }
+                }
+                Ok(None) => {}
+                Err(why) => eprintln!("Warning: {}", why),
+            }
+        }
+    }
+
+    // Resized copies of the photos, if the settings file asks for them
+    if let (Some(media_dir), Some(images)) = (&options.copy_media, &config.images) {
+        for image in &mut post_data.images {
+            let Some(source) = local_media_path(Path::new(file_name), &image.src) else {
+                continue;
+            };
+            match make_variants(&source, date_prefix, Path::new(media_dir), images) {
+                Ok(variants) => {
+                    if !variants.is_empty() {
+                        println!("\tresized {:?} into {} copies", source, variants.len());
+                    }
+                    image.variants = variants;
+                }
+                Err(why) => eprintln!("Warning: {}", why),
+            }
+        }
+    }
+
+    // The outbox gets every comment, so build it before they are moved out
+    if let (Some(_), Some(actor), Some(site_url)) =
+        (&options.outbox, &options.actor, &options.site_url)
+    {
+        archive.activities.extend(post_activities(
+            &post_data,
+            actor,
+            site_url,
+            &formatted_name,
+            date_prefix,
+        ));
+    }
+
+    // Move comments out of the Markdown and into their own files
+    if options.comments != CommentsMode::Inline && !post_data.comments.is_empty() {
+        let comments = std::mem::take(&mut post_data.comments);
+        let comments_dir = data_dir.join("comments");
+        match options.comments {
+            CommentsMode::Inline => {}
+            CommentsMode::Data => write_data_file(
+                &comments_dir,
+                &format!("{}.json", formatted_name),
+                &comments_to_json(&comments),
+            ),
+            CommentsMode::Staticman => {
+                for (path, yaml) in comments_to_staticman(&formatted_name, &comments) {
+                    write_data_file(&comments_dir, &path, &yaml);
+                }
+            }
+            CommentsMode::Isso => {
+                let url_prefix = options
+                    .post_url_prefix
+                    .as_deref()
+                    .unwrap_or(DEFAULT_POST_URL_PREFIX);
+                archive.isso_threads.push(comments_to_isso(
+                    &post_path(url_prefix, &formatted_name),
+                    &post_title(&post_data),
+                    &comments,
+                ));
+            }
+        }
+    }
+
+    let markdown_content = match template {
+        Some(template) => template
+            .render(&post_data, date_prefix)
+            .unwrap_or_else(|why| panic!("couldn't render {}: {}", file_name, why)),
+        None => match options.format {
+            OutputFormat::Markdown => generate_markdown(&post_data, date_prefix, config),
+            OutputFormat::Gemtext => generate_gemtext(&post_data, date_prefix),
+            OutputFormat::Text => generate_plain_text(&post_data, date_prefix),
+        },
+    };
     let output_path = Path::new(dest_dir).join(output_filename);
+    if let Some(parent) = output_path.parent() {
+        if let Err(why) = std::fs::create_dir_all(parent) {
+            panic!("couldn't create {}: {}", parent.display(), why);
+        }
+    }
 
-    // Write markdown file
+    // Write output file
     match std::fs::write(&output_path, markdown_content) {
         Err(why) => panic!("couldn't write {}: {}", output_path.display(), why),
         Ok(_) => println!("\tgenerated {:?}", output_path),
     }
 }
+
+/// Parse a post HTML file into post data
+fn parse_post_file(file_name: &str) -> PostData {
+    let file_path = Path::new(file_name);
+    assert!(file_path.exists());
+    assert!(file_path.is_file());
+
+    // Open the path in read-only mode, returns `io::Result<File>`
+    let mut file_handle = match File::open(file_path) {
+        Err(why) => panic!("couldn't open {}: {}", file_name, why),
+        Ok(file_handle) => file_handle,
+    };
+
+    // HTML parsing
+    let dom = parse_document(RcDom::default(), Default::default())
+        .from_utf8()
+        .read_from(&mut file_handle)
+        .unwrap_or_else(|_| panic!("Failed to parse HTML from file: {}", file_name));
+    // Parse the document to extract post data
+    // Note: html5ever may report parsing errors, but they typically don't affect extraction
+
+    extract_post_data(&dom.document)
+}
+
+/// Write a data file, creating its directory first
+fn write_data_file(dir: &Path, filename: &str, contents: &str) {
+    // Names of posts in community directories have a directory in them
+    let output_path = dir.join(filename);
+    let parent = output_path.parent().unwrap_or(dir);
+    if let Err(why) = std::fs::create_dir_all(parent) {
+        panic!("couldn't create {}: {}", parent.display(), why);
+    }
+
+    match std::fs::write(&output_path, contents) {
+        Err(why) => panic!("couldn't write {}: {}", output_path.display(), why),
+        Ok(_) => println!("\tgenerated {:?}", output_path),
+    }
+}
@@ -1,7 +1,12 @@
 //! Markdown generation from post data
 
-use crate::models::PostData;
-use crate::utils::{clean_title, escape_toml_string};
+use crate::config::{Config, DEFAULT_SIZES};
+use crate::cover::post_cover;
+use crate::dom::markdown_link_destination;
+use crate::links::parse_link;
+use crate::models::{Image, PostData, VariantFormat, Visibility};
+use crate::utils::{clean_title, escape_markdown_inline, escape_toml_string};
+use crate::video::Video;
 use std::path::Path;
 
 /// Transform an image path to /posts/YYYY-MM-DD-filename.ext format
@@ -12,7 +17,7 @@ use std::path::Path;
 ///
 /// # Returns
 /// The transformed path in the format /posts/YYYY-MM-DD-filename.ext
-fn transform_image_path(image_path: &str, date_prefix: &str) -> String {
+pub(crate) fn transform_image_path(image_path: &str, date_prefix: &str) -> String {
     // Extract just the filename from the path
     let filename = Path::new(image_path)
         .file_name()
@@ -22,27 +27,196 @@ fn transform_image_path(image_path: &str, date_prefix: &str) -> String {
     format!("/posts/{}-{}", date_prefix, filename)
 }
 
+/// Heading for the photos of one album, `Album 1` for the first
+fn album_heading(album: Option<usize>) -> String {
+    match album {
+        Some(index) => format!("Album {}", index + 1),
+        None => String::from("Photos"),
+    }
+}
+
+/// Where a photo is published: its `transform_image_path()` when the file
+/// is copied with the post, or the URL of a remote image that wasn't
+/// downloaded
+pub(crate) fn published_image_path(src: &str, date_prefix: &str) -> String {
+    if parse_link(src).is_some() {
+        src.to_string()
+    } else {
+        transform_image_path(src, date_prefix)
+    }
+}
+
+/// Hugo `figure` shortcode for a photo, linking to its full-size version
+pub(crate) fn figure_shortcode(image: &Image, date_prefix: &str) -> String {
+    let src = transform_image_path(&image.src, date_prefix);
+    let mut shortcode = format!("{{{{< figure src=\"{}\"", shortcode_value(&src));
+    let link = transform_image_path(image.full_size(), date_prefix);
+    if link != src {
+        shortcode.push_str(&format!(" link=\"{}\"", shortcode_value(&link)));
+    }
+    if let Some(alt) = image.description() {
+        shortcode.push_str(&format!(" alt=\"{}\"", shortcode_value(alt)));
+    }
+    if let Some((width, height)) = image.dimensions() {
+        shortcode.push_str(&format!(" width=\"{}\" height=\"{}\"", width, height));
+    }
+    if let Some(caption) = &image.caption {
+        shortcode.push_str(&format!(" caption=\"{}\"", shortcode_value(caption)));
+    }
+    shortcode.push_str(" >}}");
+    shortcode
+}
+
+/// `<picture>` HTML offering a photo's resized variants in a `srcset`
+///
+/// Formats other than JPEG become `<source>` elements and JPEG variants
+/// the `srcset` of the `<img>`, whose `src` is the largest JPEG copy or
+/// else the photo itself.  The whole figure is one HTML block without
+/// blank lines, which Hugo passes through with `markup.goldmark.renderer.unsafe`.
+pub(crate) fn picture_html(image: &Image, date_prefix: &str, sizes: &str) -> String {
+    let attr = |value: &str| html_escape::encode_double_quoted_attribute(value).into_owned();
+    let srcset = |format: VariantFormat| {
+        image
+            .variants
+            .iter()
+            .filter(|variant| variant.format == format)
+            .map(|variant| format!("{} {}w", srcset_url(&variant.src), variant.width))
+            .collect::<Vec<_>>()
+            .join(", ")
+    };
+
+    let mut html = String::from("<figure>\n");
+    let src = transform_image_path(&image.src, date_prefix);
+    let link = transform_image_path(image.full_size(), date_prefix);
+    if link != src {
+        html.push_str(&format!("<a href=\"{}\">\n", attr(&link)));
+    }
+    html.push_str("<picture>\n");
+    let mut formats: Vec<VariantFormat> = Vec::new();
+    for variant in &image.variants {
+        if variant.format != VariantFormat::Jpeg && !formats.contains(&variant.format) {
+            formats.push(variant.format);
+        }
+    }
+    for format in formats {
+        html.push_str(&format!(
+            "<source type=\"{}\" srcset=\"{}\" sizes=\"{}\">\n",
+            format.mime_type(),
+            attr(&srcset(format)),
+            attr(sizes)
+        ));
+    }
+
+    let largest_jpeg = image
+        .variants
+        .iter()
+        .rfind(|variant| variant.format == VariantFormat::Jpeg);
+    html.push_str(&format!(
+        "<img src=\"{}\"",
+        attr(largest_jpeg.map_or(&src, |variant| &variant.src))
+    ));
+    if largest_jpeg.is_some() {
+        html.push_str(&format!(
+            " srcset=\"{}\" sizes=\"{}\"",
+            attr(&srcset(VariantFormat::Jpeg)),
+            attr(sizes)
+        ));
+    }
+    html.push_str(&format!(
+        " alt=\"{}\"",
+        attr(&single_line(image.description().unwrap_or_default()))
+    ));
+    if let Some(largest) = image.variants.last() {
+        html.push_str(&format!(
+            " width=\"{}\" height=\"{}\"",
+            largest.width, largest.height
+        ));
+    }
+    html.push_str(" loading=\"lazy\">\n</picture>\n");
+    if link != src {
+        html.push_str("</a>\n");
+    }
+    if let Some(caption) = &image.caption {
+        html.push_str(&format!(
+            "<figcaption>{}</figcaption>\n",
+            html_escape::encode_text(&single_line(caption))
+        ));
+    }
+    html.push_str("</figure>\n");
+    html
+}
+
+/// A path percent-encoded for a `srcset`, where spaces and commas would
+/// split the candidate
+fn srcset_url(path: &str) -> String {
+    let mut encoded = String::with_capacity(path.len());
+    for byte in path.bytes() {
+        if byte.is_ascii_alphanumeric() || b"-._~/".contains(&byte) {
+            encoded.push(char::from(byte));
+        } else {
+            encoded.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    encoded
+}
+
+/// A shortcode parameter value for use inside double quotes, on one line
+fn shortcode_value(value: &str) -> String {
+    single_line(value)
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+}
+
+/// Text with runs of whitespace, newlines included, as single spaces
+fn single_line(text: &str) -> String {
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// Pick a title for the post
+///
+/// Uses the cleaned HTML title when there is one, otherwise the first
+/// 50 characters of the content, otherwise a generic placeholder.
+pub fn post_title(post_data: &PostData) -> String {
+    if !post_data.title.is_empty() {
+        clean_title(&post_data.title)
+    } else if !post_data.body.is_empty() {
+        let truncated = post_data
+            .body
+            .to_text()
+            .chars()
+            .take(50)
+            .collect::<String>();
+        format!("{}...", truncated.trim())
+    } else {
+        String::from("Google+ Post")
+    }
+}
+
+/// Build a description from the first 150 characters of the content
+pub(crate) fn post_description(post_data: &PostData) -> String {
+    let truncated = post_data
+        .body
+        .to_text()
+        .chars()
+        .take(150)
+        .collect::<String>();
+    truncated.trim().to_string()
+}
+
 /// Generate markdown from post data
 ///
 /// # Arguments
 /// * `post_data` - The post data to generate markdown from
 /// * `date_prefix` - The date prefix (YYYY-MM-DD) for image paths
-pub fn generate_markdown(post_data: &PostData, date_prefix: &str) -> String {
+/// * `config` - Settings for the cover image and resized photos
+pub fn generate_markdown(post_data: &PostData, date_prefix: &str, config: &Config) -> String {
     let mut markdown = String::new();
 
     // Generate TOML front matter
     markdown.push_str("+++\n");
 
     // Title - use post title if available, otherwise use truncated content
-    let title = if !post_data.title.is_empty() {
-        let cleaned = clean_title(&post_data.title);
-        escape_toml_string(&cleaned)
-    } else if !post_data.content.is_empty() {
-        let truncated = post_data.content.chars().take(50).collect::<String>();
-        escape_toml_string(&format!("{}...", truncated.trim()))
-    } else {
-        String::from("Google+ Post")
-    };
+    let title = escape_toml_string(&post_title(post_data));
     markdown.push_str(&format!("title = \"{}\"\n", title));
 
     // Date - use raw format from Google+ for now
@@ -58,12 +232,7 @@ pub fn generate_markdown(post_data: &PostData, date_prefix: &str) -> String {
     markdown.push_str("draft = false\n");
 
     // Description - first 150 chars of content
-    let description = if !post_data.content.is_empty() {
-        let truncated = post_data.content.chars().take(150).collect::<String>();
-        escape_toml_string(truncated.trim())
-    } else {
-        String::from("")
-    };
+    let description = escape_toml_string(&post_description(post_data));
     markdown.push_str(&format!("# description = \"{}\"\n", description));
 
     // Canonical URL - original Google+ post URL
@@ -78,9 +247,23 @@ pub fn generate_markdown(post_data: &PostData, date_prefix: &str) -> String {
         markdown.push_str("ShowCanonicalLink = false\n");
     }
 
-    // Cover image settings
-    markdown.push_str("# cover.image = \"/posts/\"\n");
-    markdown.push_str("cover.hidden = true\n");
+    // Cover image, the first or largest photo or the link preview
+    match post_cover(post_data, date_prefix, config.cover.pick) {
+        Some(post_cover) => {
+            markdown.push_str(&format!(
+                "cover.image = \"{}\"\n",
+                escape_toml_string(&post_cover.image)
+            ));
+            if !post_cover.alt.is_empty() {
+                markdown.push_str(&format!(
+                    "cover.alt = \"{}\"\n",
+                    escape_toml_string(&post_cover.alt)
+                ));
+            }
+        }
+        None => markdown.push_str("# cover.image = \"/posts/\"\n"),
+    }
+    markdown.push_str(&format!("cover.hidden = {}\n", config.cover.hidden));
 
     // Optional metadata as comments
     if !post_data.author.is_empty() {
@@ -91,13 +274,50 @@ pub fn generate_markdown(post_data: &PostData, date_prefix: &str) -> String {
     }
     markdown.push_str("# keywords = [\"google-plus\", \"archive\"]\n");
     markdown.push_str("tags = [\"google-plus\"]\n");
+    if post_data.visibility != Visibility::Unknown {
+        markdown.push_str(&format!(
+            "visibility = \"{}\"\n",
+            post_data.visibility.key()
+        ));
+    }
+    // Taxonomies for Hugo to list the posts of a community or collection
+    if let Some(community) = post_data.visibility.community() {
+        markdown.push_str(&format!(
+            "communities = [\"{}\"]\n",
+            escape_toml_string(community)
+        ));
+    }
+    if let Some(collection) = &post_data.collection {
+        markdown.push_str(&format!(
+            "collections = [\"{}\"]\n",
+            escape_toml_string(&collection.name)
+        ));
+    }
+
+    // Dimensions of the photos that have them, for layouts sizing images
+    let photos: Vec<String> = post_data
+        .images
+        .iter()
+        .filter_map(|image| {
+            let (width, height) = image.dimensions()?;
+            Some(format!(
+                "{{ src = \"{}\", width = {}, height = {} }}",
+                escape_toml_string(&transform_image_path(&image.src, date_prefix)),
+                width,
+                height
+            ))
+        })
+        .collect();
+    if !photos.is_empty() {
+        markdown.push_str(&format!("photos = [{}]\n", photos.join(", ")));
+    }
 
     markdown.push_str("# ShowToc = false\n");
     markdown.push_str("+++\n\n");
 
     // Post metadata section
     let mut metadata_parts = Vec::new();
-    if let Some(location) = &post_data.location {
+    if let Some(location) = post_data.location_label() {
         metadata_parts.push(format!("**Location:** {}", location));
     }
     if !metadata_parts.is_empty() {
@@ -106,32 +326,82 @@ pub fn generate_markdown(post_data: &PostData, date_prefix: &str) -> String {
     }
 
     // Add main content
-    if !post_data.content.is_empty() {
-        markdown.push_str(&post_data.content);
+    let content = post_data.content();
+    if !content.is_empty() {
+        markdown.push_str(&content);
         markdown.push_str("\n\n");
     }
 
     // Add reshare information
     if let Some(reshare_author) = &post_data.reshare_author {
-        markdown.push_str(&format!("**Originally shared by {}**\n\n", reshare_author));
-        if let Some(reshare_content) = &post_data.reshare_content {
-            markdown.push_str(reshare_content);
+        let reshare_author = escape_markdown_inline(reshare_author);
+        match &post_data.reshare_url {
+            Some(reshare_url) => markdown.push_str(&format!(
+                "**Originally shared by [{}]({})**\n\n",
+                reshare_author,
+                markdown_link_destination(reshare_url)
+            )),
+            None => markdown.push_str(&format!("**Originally shared by {}**\n\n", reshare_author)),
+        }
+        if let Some(reshare_content) = post_data.reshare_content() {
+            markdown.push_str(&reshare_content);
             markdown.push_str("\n\n");
         }
     }
 
-    // Add images
-    if !post_data.images.is_empty() {
-        markdown.push_str("## Images\n\n");
-        for image_url in &post_data.images {
-            let transformed_path = transform_image_path(image_url, date_prefix);
-            markdown.push_str(&format!("![Image]({})\n\n", transformed_path));
+    // Add images, several photos become a list of figures with captions and
+    // photos with resized copies a <picture> each, under a heading per album
+    // when the post has more than one
+    let sizes = config
+        .images
+        .as_ref()
+        .map_or(DEFAULT_SIZES, |images| images.sizes.as_str());
+    match post_data.images.as_slice() {
+        [] => {}
+        [image] if image.variants.is_empty() => {
+            markdown.push_str("## Images\n\n");
+            markdown.push_str(&format!(
+                "![{}]({})\n\n",
+                escape_markdown_inline(image.description().unwrap_or("Image")),
+                markdown_link_destination(&transform_image_path(&image.src, date_prefix))
+            ));
+            if let Some(caption) = &image.caption {
+                markdown.push_str(&format!("*{}*\n\n", escape_markdown_inline(caption)));
+            }
+        }
+        images => {
+            markdown.push_str("## Images\n\n");
+            let albums: Vec<&[Image]> = images.chunk_by(|a, b| a.album == b.album).collect();
+            for album in &albums {
+                if albums.len() > 1 {
+                    markdown.push_str(&format!("### {}\n\n", album_heading(album[0].album)));
+                }
+                for image in *album {
+                    if image.variants.is_empty() {
+                        markdown.push_str(&figure_shortcode(image, date_prefix));
+                    } else {
+                        // HTML blocks end at a blank line
+                        markdown.push_str(&picture_html(image, date_prefix, sizes));
+                    }
+                    markdown.push('\n');
+                }
+                if !markdown.ends_with("\n\n") {
+                    markdown.push('\n');
+                }
+            }
         }
     }
 
-    // Add video
+    // Add video, embedded when Hugo has a shortcode for the provider
     if let Some(video_url) = &post_data.video_url {
-        markdown.push_str(&format!("## Video\n\n[Watch Video]({})\n\n", video_url));
+        let video = Video::detect(video_url);
+        let embed = video.hugo_shortcode().unwrap_or_else(|| {
+            format!(
+                "[Watch Video]({})",
+                markdown_link_destination(&video.watch_url(date_prefix))
+            )
+        });
+        markdown.push_str(&format!("## Video\n\n{}\n\n", embed));
     }
 
     // Add links
@@ -139,21 +409,35 @@ pub fn generate_markdown(post_data: &PostData, date_prefix: &str) -> String {
         markdown.push_str("## Links\n\n");
         for (url, title) in &post_data.links {
             let link_text = if title.is_empty() { url } else { title };
-            markdown.push_str(&format!("- [{}]({})\n", link_text, url));
+            markdown.push_str(&format!(
+                "- [{}]({})\n",
+                escape_markdown_inline(link_text),
+                markdown_link_destination(url)
+            ));
         }
         markdown.push('\n');
     }
 
     // Add shared with
-    if !post_data.visibility.is_empty() {
-        markdown.push_str(&format!("**Shared with:** {}\n\n", post_data.visibility));
+    let audience = post_data.audience();
+    if !audience.is_empty() {
+        markdown.push_str(&format!(
+            "**Shared with:** {}\n\n",
+            escape_markdown_inline(&audience)
+        ));
+    }
+    if let Some(collection) = &post_data.collection {
+        markdown.push_str(&format!(
+            "**Collection:** {}\n\n",
+            escape_markdown_inline(&collection.name)
+        ));
     }
 
     // Add +1s
     if !post_data.plus_ones.is_empty() {
         markdown.push_str(&format!(
             "**+1'd by:** {}\n\n",
-            post_data.plus_ones.join(", ")
+            escape_markdown_inline(&post_data.plus_ones.join(", "))
         ));
     }
 
@@ -161,11 +445,32 @@ pub fn generate_markdown(post_data: &PostData, date_prefix: &str) -> String {
     if !post_data.comments.is_empty() {
         markdown.push_str("## Comments\n\n");
         for comment in &post_data.comments {
-            markdown.push_str(&format!("**{}**", comment.author));
+            markdown.push_str(&format!("**{}**", escape_markdown_inline(&comment.author)));
             if !comment.date.is_empty() {
                 markdown.push_str(&format!(" - {}", comment.date));
             }
-            markdown.push_str(&format!("\n\n{}\n\n---\n\n", comment.content));
+            markdown.push_str(&format!("\n\n{}\n\n", comment.content()));
+            if !comment.images.is_empty() {
+                for image_url in &comment.images {
+                    markdown.push_str(&format!(
+                        "![Image]({})\n",
+                        markdown_link_destination(&published_image_path(image_url, date_prefix))
+                    ));
+                }
+                markdown.push('\n');
+            }
+            if !comment.links.is_empty() {
+                for (url, title) in &comment.links {
+                    let link_text = if title.is_empty() { url } else { title };
+                    markdown.push_str(&format!(
+                        "- [{}]({})\n",
+                        escape_markdown_inline(link_text),
+                        markdown_link_destination(url)
+                    ));
+                }
+                markdown.push('\n');
+            }
+            markdown.push_str("---\n\n");
         }
     }
 
@@ -175,6 +480,9 @@ pub fn generate_markdown(post_data: &PostData, date_prefix: &str) -> String {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::content::Inline;
+    use crate::models::{Collection, Comment, ImageInfo, ImageVariant, Recipient};
+    use crate::test_helpers::{link, paragraph, text};
 
     #[test]
     fn test_transform_image_path_standard() {
@@ -269,4 +577,370 @@ mod tests {
             "/posts/2019-01-01-.."
         );
     }
+
+    #[test]
+    fn test_generate_markdown_single_image() {
+        let post = PostData {
+            images: vec![Image {
+                caption: Some(String::from("Santa Monica Pier")),
+                ..Image::new("../Photos/Photos%20from%20posts/5-26-12/17nvve40qhwaa.jpg")
+            }],
+            ..Default::default()
+        };
+        assert!(generate_markdown(&post, "2012-05-26", &Config::default()).contains(
+            "## Images\n\n![Santa Monica Pier](/posts/2012-05-26-17nvve40qhwaa.jpg)\n\n*Santa Monica Pier*\n"
+        ));
+    }
+
+    #[test]
+    fn test_generate_markdown_figures() {
+        let post = PostData {
+            images: vec![
+                Image {
+                    link: Some(String::from("https://lh3.googleusercontent.com/x/s0/a.jpg")),
+                    alt: String::from("Tulips"),
+                    caption: Some(String::from("Tulips at the\nshow")),
+                    album: Some(0),
+                    ..Image::new("https://lh3.googleusercontent.com/x/w288-h288/a-small.jpg")
+                },
+                Image {
+                    album: Some(0),
+                    ..Image::new("../Photos/b.jpg")
+                },
+            ],
+            ..Default::default()
+        };
+        assert!(generate_markdown(&post, "2015-03-31", &Config::default()).contains(
+            "## Images\n\n\
+             {{< figure src=\"/posts/2015-03-31-a-small.jpg\" link=\"/posts/2015-03-31-a.jpg\" alt=\"Tulips\" caption=\"Tulips at the show\" >}}\n\
+             {{< figure src=\"/posts/2015-03-31-b.jpg\" >}}\n"
+        ));
+    }
+
+    #[test]
+    fn test_generate_markdown_albums() {
+        let post = PostData {
+            images: vec![
+                Image {
+                    album: Some(0),
+                    ..Image::new("../Photos/a.jpg")
+                },
+                Image {
+                    album: Some(0),
+                    ..Image::new("../Photos/b.jpg")
+                },
+                Image {
+                    album: Some(1),
+                    ..Image::new("../Photos/c.jpg")
+                },
+                Image::new("../Photos/d.jpg"),
+            ],
+            ..Default::default()
+        };
+        assert!(
+            generate_markdown(&post, "2015-03-31", &Config::default()).contains(
+                "## Images\n\n\
+             ### Album 1\n\n\
+             {{< figure src=\"/posts/2015-03-31-a.jpg\" >}}\n\
+             {{< figure src=\"/posts/2015-03-31-b.jpg\" >}}\n\n\
+             ### Album 2\n\n\
+             {{< figure src=\"/posts/2015-03-31-c.jpg\" >}}\n\n\
+             ### Photos\n\n\
+             {{< figure src=\"/posts/2015-03-31-d.jpg\" >}}\n"
+            )
+        );
+    }
+
+    #[test]
+    fn test_generate_markdown_photo_dimensions() {
+        let sized = |src: &str| Image {
+            info: Some(ImageInfo {
+                width: Some(1600),
+                height: Some(1200),
+                ..Default::default()
+            }),
+            ..Image::new(src)
+        };
+        let post = PostData {
+            images: vec![
+                sized("../Photos/a.jpg"),
+                Image::new("../Photos/b.jpg"),
+                sized("../Photos/c.jpg"),
+            ],
+            ..Default::default()
+        };
+        let markdown = generate_markdown(&post, "2015-03-31", &Config::default());
+        assert!(markdown.contains(
+            "photos = [{ src = \"/posts/2015-03-31-a.jpg\", width = 1600, height = 1200 }, \
+             { src = \"/posts/2015-03-31-c.jpg\", width = 1600, height = 1200 }]\n"
+        ));
+        assert!(markdown.contains(
+            "{{< figure src=\"/posts/2015-03-31-a.jpg\" width=\"1600\" height=\"1200\" >}}\n\
+             {{< figure src=\"/posts/2015-03-31-b.jpg\" >}}\n"
+        ));
+    }
+
+    #[test]
+    fn test_generate_markdown_cover() {
+        let mut post = PostData::default();
+        let mut config = Config::default();
+        assert!(generate_markdown(&post, "2012-05-26", &config)
+            .contains("# cover.image = \"/posts/\"\ncover.hidden = true\n"));
+
+        post.images.push(Image {
+            alt: String::from("Santa \"Monica\" Pier"),
+            ..Image::new("../Photos/Photos%20from%20posts/5-26-12/17nvve40qhwaa.jpg")
+        });
+        config.cover.hidden = false;
+        assert!(generate_markdown(&post, "2012-05-26", &config).contains(
+            "cover.image = \"/posts/2012-05-26-17nvve40qhwaa.jpg\"\n\
+             cover.alt = \"Santa \\\"Monica\\\" Pier\"\n\
+             cover.hidden = false\n"
+        ));
+    }
+
+    fn resized(src: &str) -> Image {
+        let variant = |width: u32, height: u32, format: VariantFormat| ImageVariant {
+            src: format!("/posts/2012-05-26-pier-{}w.{}", width, format.extension()),
+            width,
+            height,
+            format,
+        };
+        Image {
+            link: Some(String::from(
+                "https://lh3.googleusercontent.com/x/s0/pier-full.jpg",
+            )),
+            alt: String::from("Pier & \"boardwalk\""),
+            caption: Some(String::from("Santa Monica <Pier>")),
+            variants: vec![
+                variant(480, 360, VariantFormat::Webp),
+                variant(480, 360, VariantFormat::Jpeg),
+                variant(960, 720, VariantFormat::Webp),
+                variant(960, 720, VariantFormat::Jpeg),
+            ],
+            ..Image::new(src)
+        }
+    }
+
+    #[test]
+    fn test_picture_html() {
+        assert_eq!(
+            picture_html(&resized("../Photos/pier.jpg"), "2012-05-26", "100vw"),
+            "<figure>\n\
+             <a href=\"/posts/2012-05-26-pier-full.jpg\">\n\
+             <picture>\n\
+             <source type=\"image/webp\" srcset=\"/posts/2012-05-26-pier-480w.webp 480w, /posts/2012-05-26-pier-960w.webp 960w\" sizes=\"100vw\">\n\
+             <img src=\"/posts/2012-05-26-pier-960w.jpg\" srcset=\"/posts/2012-05-26-pier-480w.jpg 480w, /posts/2012-05-26-pier-960w.jpg 960w\" sizes=\"100vw\" alt=\"Pier &amp; &quot;boardwalk&quot;\" width=\"960\" height=\"720\" loading=\"lazy\">\n\
+             </picture>\n\
+             </a>\n\
+             <figcaption>Santa Monica &lt;Pier&gt;</figcaption>\n\
+             </figure>\n"
+        );
+    }
+
+    #[test]
+    fn test_picture_html_without_jpeg() {
+        let mut image = resized("../Photos/pier.png");
+        image.link = None;
+        image.caption = None;
+        image
+            .variants
+            .retain(|variant| variant.format == VariantFormat::Webp);
+        let html = picture_html(&image, "2012-05-26", "50vw");
+        assert!(html.starts_with("<figure>\n<picture>\n"));
+        assert!(html.contains(
+            "<img src=\"/posts/2012-05-26-pier.png\" alt=\"Pier &amp; &quot;boardwalk&quot;\" width=\"960\""
+        ));
+        assert!(html.ends_with("</picture>\n</figure>\n"));
+    }
+
+    #[test]
+    fn test_picture_html_encodes_srcset() {
+        let mut image = resized("../Photos/my photo (1).jpg");
+        for variant in &mut image.variants {
+            variant.src = variant.src.replace("pier", "my photo, (1)");
+        }
+        let html = picture_html(&image, "2012-05-26", "100vw");
+        assert!(html.contains(
+            "srcset=\"/posts/2012-05-26-my%20photo%2C%20%281%29-480w.jpg 480w, /posts/2012-05-26-my%20photo%2C%20%281%29-960w.jpg 960w\""
+        ));
+        assert!(html.contains("<img src=\"/posts/2012-05-26-my photo, (1)-960w.jpg\""));
+    }
+
+    #[test]
+    fn test_generate_markdown_pictures() {
+        let mut post = PostData {
+            images: vec![resized("../Photos/pier.jpg")],
+            ..Default::default()
+        };
+        let markdown = generate_markdown(&post, "2012-05-26", &Config::default());
+        assert!(markdown.contains("## Images\n\n<figure>\n"));
+        assert!(markdown.ends_with("</figure>\n"));
+
+        post.images.push(Image::new("../Photos/b.jpg"));
+        post.video_url = Some(String::from("http://youtu.be/u6XAPnuFjJc"));
+        assert!(generate_markdown(&post, "2012-05-26", &Config::default())
+            .contains("</figure>\n\n{{< figure src=\"/posts/2012-05-26-b.jpg\" >}}\n\n## Video\n"));
+    }
+
+    #[test]
+    fn test_generate_markdown_video() {
+        let mut post = PostData {
+            video_url: Some(String::from("http://www.youtube.com/watch?v=u6XAPnuFjJc")),
+            ..Default::default()
+        };
+        assert!(generate_markdown(&post, "2011-11-05", &Config::default())
+            .contains("## Video\n\n{{< youtube u6XAPnuFjJc >}}\n"));
+
+        post.video_url = Some(String::from(
+            "../Photos/Photos%20from%20posts/2013-03-10/18h0nir4n94ya.mp4",
+        ));
+        assert!(generate_markdown(&post, "2013-03-09", &Config::default())
+            .contains("## Video\n\n[Watch Video](/posts/2013-03-09-18h0nir4n94ya.mp4)\n"));
+    }
+
+    #[test]
+    fn test_generate_markdown_inferred_location() {
+        let mut post = PostData {
+            location: Some(String::from("34.00940, -118.49730")),
+            ..Default::default()
+        };
+        assert!(generate_markdown(&post, "2012-05-26", &Config::default())
+            .contains("**Location:** 34.00940, -118.49730\n"));
+
+        post.location_inferred = true;
+        assert!(generate_markdown(&post, "2012-05-26", &Config::default())
+            .contains("**Location:** 34.00940, -118.49730 (from photo)\n"));
+    }
+
+    #[test]
+    fn test_title_and_description_are_plain_text() {
+        let post = PostData {
+            body: paragraph(vec![
+                text("<< ~~waaaa!~~ *really*"),
+                Inline::LineBreak,
+                link("http://example.com/", "And dancing girls."),
+            ]),
+            ..Default::default()
+        };
+        assert_eq!(
+            post_title(&post),
+            "<< ~~waaaa!~~ *really*\nAnd dancing girls...."
+        );
+        assert_eq!(
+            post_description(&post),
+            "<< ~~waaaa!~~ *really*\nAnd dancing girls."
+        );
+    }
+
+    #[test]
+    fn test_generate_markdown_reshare() {
+        let mut post = PostData {
+            reshare_author: Some(String::from("Jake Gross")),
+            reshare_body: paragraph(vec![text("For the noobs.")]),
+            ..Default::default()
+        };
+        assert!(generate_markdown(&post, "", &Config::default())
+            .contains("**Originally shared by Jake Gross**\n\nFor the noobs.\n"));
+
+        post.reshare_url = Some(String::from("/posts/2011-08-14-today_is_my_first_day/"));
+        assert!(generate_markdown(&post, "", &Config::default()).contains(
+            "**Originally shared by [Jake Gross](/posts/2011-08-14-today_is_my_first_day/)**\n\n"
+        ));
+    }
+
+    #[test]
+    fn test_generate_markdown_escapes_names() {
+        let post = PostData {
+            reshare_author: Some(String::from("*NSYNC fan")),
+            reshare_url: Some(String::from("https://plus.google.com/+Fan/posts/x")),
+            plus_ones: vec![String::from("[bot]"), String::from("Wil Schultz")],
+            comments: vec![Comment {
+                author: String::from("*NSYNC fan"),
+                body: paragraph(vec![text("Bye bye bye")]),
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        let markdown = generate_markdown(&post, "", &Config::default());
+        assert!(markdown.contains(
+            "**Originally shared by [\\*NSYNC fan](https://plus.google.com/+Fan/posts/x)**\n"
+        ));
+        assert!(markdown.contains("**+1'd by:** \\[bot\\], Wil Schultz\n"));
+        assert!(markdown.contains("**\\*NSYNC fan**\n"));
+    }
+
+    #[test]
+    fn test_generate_markdown_visibility() {
+        let mut post = PostData::default();
+        let markdown = generate_markdown(&post, "2014-08-22", &Config::default());
+        assert!(!markdown.contains("visibility ="));
+        assert!(!markdown.contains("**Shared with:**"));
+
+        post.visibility = Visibility::ExtendedCircles;
+        post.shared_with = vec![
+            Recipient {
+                name: String::from("Paul Fernandez"),
+                url: Some(String::from("https://plus.google.com/+PaulFernandez")),
+            },
+            Recipient {
+                name: String::from("*Best* [friends]"),
+                url: None,
+            },
+        ];
+        let markdown = generate_markdown(&post, "2014-08-22", &Config::default());
+        assert!(markdown.contains("tags = [\"google-plus\"]\nvisibility = \"extended_circles\"\n"));
+        assert!(markdown.contains(
+            "**Shared with:** Extended circles, Paul Fernandez, \\*Best\\* \\[friends\\]\n"
+        ));
+    }
+
+    #[test]
+    fn test_generate_markdown_community_and_collection() {
+        let post = PostData {
+            visibility: Visibility::Community {
+                name: String::from("Rust \"Programming\""),
+                url: String::from("https://plus.google.com/communities/113390432655174294208"),
+                category: Some(String::from("Show and Tell")),
+            },
+            collection: Some(Collection {
+                name: String::from("Side_projects"),
+                url: String::from("https://plus.google.com/collection/AsA6OE"),
+            }),
+            ..Default::default()
+        };
+        let markdown = generate_markdown(&post, "2014-08-22", &Config::default());
+        assert!(markdown.contains(
+            "visibility = \"community\"\ncommunities = [\"Rust \\\"Programming\\\"\"]\ncollections = [\"Side_projects\"]\n"
+        ));
+        assert!(markdown.contains("**Shared with:** Rust \"Programming\" (Show and Tell)\n"));
+        assert!(markdown.contains("**Collection:** Side_projects\n"));
+    }
+
+    #[test]
+    fn test_generate_markdown_comment_images_and_links() {
+        let post = PostData {
+            comments: vec![Comment {
+                author: String::from("Paul"),
+                body: paragraph(vec![text("Yum")]),
+                images: vec![
+                    String::from("../Photos/b.jpg"),
+                    String::from("https://lh3.googleusercontent.com/-x/AAuE7m=s64-c"),
+                ],
+                links: vec![(
+                    String::from("http://example.com/recipe"),
+                    String::from("Recipe"),
+                )],
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        let markdown = generate_markdown(&post, "2013-09-05", &Config::default());
+        assert!(markdown.ends_with(
+            "**Paul**\n\nYum\n\n\
+             ![Image](/posts/2013-09-05-b.jpg)\n\
+             ![Image](https://lh3.googleusercontent.com/-x/AAuE7m=s64-c)\n\n\
+             - [Recipe](http://example.com/recipe)\n\n---\n"
+        ));
+    }
 }
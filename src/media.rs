@@ -0,0 +1,175 @@
+//! Copying photos and videos out of the Takeout
+//!
+//! Posts refer to uploaded photos and videos by paths relative to the post
+//! HTML file, like `../Photos/Photos%20from%20posts/2013-03-10/x.mp4`.
+//! The output links them as `/posts/YYYY-MM-DD-x.mp4` (see
+//! `transform_image_path()`), so copies get the same `YYYY-MM-DD-name`
+//! file names.
+
+use std::path::{Path, PathBuf};
+
+use url::Url;
+
+use crate::links::parse_link;
+use crate::models::PostData;
+
+/// Photo and video sources of a post and its comments, in order
+///
+/// Includes the full-size versions photos link to, the link preview and the
+/// author's photo, each source once.
+pub fn post_media(post_data: &PostData) -> Vec<&str> {
+    let mut sources: Vec<&str> = Vec::new();
+    for image in &post_data.images {
+        sources.push(&image.src);
+        sources.extend(image.link.as_deref());
+    }
+    sources.extend(post_data.video_url.as_deref());
+    sources.extend(
+        post_data
+            .link_preview
+            .as_ref()
+            .map(|image| image.src.as_str()),
+    );
+    sources.extend(post_data.author_photo.as_deref());
+    for comment in &post_data.comments {
+        sources.extend(comment.images.iter().map(String::as_str));
+    }
+
+    let mut seen = std::collections::HashSet::new();
+    sources.retain(|src| seen.insert(*src));
+    sources
+}
+
+/// The file a media source refers to, `None` for web URLs
+///
+/// Relative sources are resolved against the directory of `post_file` and
+/// percent-decoded.
+pub fn local_media_path(post_file: &Path, src: &str) -> Option<PathBuf> {
+    if src.is_empty() || parse_link(src).is_some() {
+        return None;
+    }
+    let post_file = std::path::absolute(post_file).ok()?;
+    let base = Url::from_file_path(post_file).ok()?;
+    base.join(src).ok()?.to_file_path().ok()
+}
+
+/// Copy a post's local photo or video into `dest_dir`
+///
+/// The copy is named `<date_prefix>-<file name>`, matching the path the
+/// post links to.  Web URLs are left alone and return `Ok(None)`.
+pub fn copy_media(
+    post_file: &Path,
+    src: &str,
+    date_prefix: &str,
+    dest_dir: &Path,
+) -> Result<Option<PathBuf>, String> {
+    let source = match local_media_path(post_file, src) {
+        Some(source) => source,
+        None => return Ok(None),
+    };
+    let file_name = source
+        .file_name()
+        .and_then(|name| name.to_str())
+        .ok_or_else(|| format!("{} has no file name", source.display()))?;
+    let target = dest_dir.join(format!("{}-{}", date_prefix, file_name));
+
+    std::fs::create_dir_all(dest_dir)
+        .map_err(|why| format!("couldn't create {}: {}", dest_dir.display(), why))?;
+    std::fs::copy(&source, &target).map_err(|why| {
+        format!(
+            "couldn't copy {} to {}: {}",
+            source.display(),
+            target.display(),
+            why
+        )
+    })?;
+    Ok(Some(target))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{Comment, Image};
+    use crate::test_helpers::temp_dir;
+
+    #[test]
+    fn test_post_media() {
+        let post = PostData {
+            images: vec![
+                Image::new("../Photos/a.jpg"),
+                Image {
+                    link: Some(String::from("../Photos/d.jpg")),
+                    ..Image::new("../Photos/d-small.jpg")
+                },
+            ],
+            video_url: Some(String::from("../Photos/b.mp4")),
+            author_photo: Some(String::from("../Photos/a.jpg")),
+            comments: vec![Comment {
+                images: vec![String::from("https://lh3.googleusercontent.com/c")],
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        assert_eq!(
+            post_media(&post),
+            vec![
+                "../Photos/a.jpg",
+                "../Photos/d-small.jpg",
+                "../Photos/d.jpg",
+                "../Photos/b.mp4",
+                "https://lh3.googleusercontent.com/c"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_local_media_path() {
+        let post_file = Path::new("/takeout/Google+ Stream/Posts/20130309 - Post.html");
+        assert_eq!(
+            local_media_path(
+                post_file,
+                "../Photos/Photos%20from%20posts/2013-03-10/18h0nir4n94ya.mp4"
+            ),
+            Some(PathBuf::from(
+                "/takeout/Google+ Stream/Photos/Photos from posts/2013-03-10/18h0nir4n94ya.mp4"
+            ))
+        );
+        assert_eq!(
+            local_media_path(post_file, "https://lh3.googleusercontent.com/a.jpg"),
+            None
+        );
+        assert_eq!(local_media_path(post_file, ""), None);
+    }
+
+    #[test]
+    fn test_copy_media() {
+        let takeout = temp_dir("media-takeout");
+        let photos = takeout.join("Photos").join("Photos from posts");
+        std::fs::create_dir_all(&photos).unwrap();
+        std::fs::write(photos.join("clip one.mp4"), "video").unwrap();
+        let post_file = takeout.join("Posts").join("20130309 - Post.html");
+        let dest = temp_dir("media-dest");
+
+        let copied = copy_media(
+            &post_file,
+            "../Photos/Photos%20from%20posts/clip%20one.mp4",
+            "2013-03-09",
+            &dest,
+        )
+        .unwrap();
+        let target = dest.join("2013-03-09-clip one.mp4");
+        assert_eq!(copied, Some(target.clone()));
+        assert_eq!(std::fs::read_to_string(target).unwrap(), "video");
+
+        assert_eq!(
+            copy_media(&post_file, "http://youtu.be/u6XAPnuFjJc", "", &dest),
+            Ok(None)
+        );
+        assert!(copy_media(&post_file, "../Photos/missing.jpg", "", &dest)
+            .unwrap_err()
+            .contains("missing.jpg"));
+
+        std::fs::remove_dir_all(takeout).unwrap();
+        std::fs::remove_dir_all(dest).unwrap();
+    }
+}
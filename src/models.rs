@@ -1,26 +1,330 @@
 //! Data models for Google+ posts
 
-#[derive(Debug, Default)]
+use serde::ser::SerializeMap;
+use serde::{Deserialize, Serialize, Serializer};
+
+use crate::content::RichText;
+
+#[derive(Debug, Default, Serialize)]
 pub struct PostData {
     pub author: String,
+    pub author_url: String,
+    /// Profile photo of the author
+    pub author_photo: Option<String>,
     pub date: String,
     pub canonical_url: String,
     pub title: String,
-    pub content: String,
+    /// Post text, serialized along with its Markdown as `content`
+    #[serde(flatten, serialize_with = "serialize_body")]
+    pub body: RichText,
     pub reshare_author: Option<String>,
-    pub reshare_content: Option<String>,
+    /// Google+ URL of the reshared post
+    pub reshare_url: Option<String>,
+    /// Reshared text, serialized along with its Markdown as
+    /// `reshare_content`
+    #[serde(flatten, serialize_with = "serialize_reshare_body")]
+    pub reshare_body: RichText,
     pub location: Option<String>,
-    pub images: Vec<String>,
+    /// Whether `location` is the position of a geotagged photo rather than
+    /// a place the post was tagged with
+    pub location_inferred: bool,
+    pub images: Vec<Image>,
     pub video_url: Option<String>,
     pub links: Vec<(String, String)>, // (url, title)
-    pub visibility: String,
+    /// Preview image of the shared link, with the link title as alt text
+    pub link_preview: Option<Image>,
+    pub hashtags: Vec<String>,
+    pub mentions: Vec<(String, String)>, // (profile url, name)
+    pub visibility: Visibility,
+    /// Circles and people named on the "Shared with" line besides the
+    /// audience
+    pub shared_with: Vec<Recipient>,
+    /// The collection the post was made in
+    pub collection: Option<Collection>,
     pub plus_ones: Vec<String>,
     pub comments: Vec<Comment>,
 }
 
-#[derive(Debug)]
+impl PostData {
+    /// The "Shared with" line as Google+ showed it, empty when unknown
+    pub fn audience(&self) -> String {
+        let label = match &self.visibility {
+            Visibility::Community {
+                name,
+                category: Some(category),
+                ..
+            } => Some(format!("{} ({})", name, category)),
+            visibility => visibility.label().map(str::to_string),
+        };
+        let names = self
+            .shared_with
+            .iter()
+            .map(|recipient| recipient.name.clone())
+            .filter(|name| !name.is_empty());
+        let audience: Vec<String> = label.into_iter().chain(names).collect();
+        audience.join(", ")
+    }
+
+    /// The location as shown in posts, saying so when it is a photo's
+    pub fn location_label(&self) -> Option<String> {
+        let location = self.location.as_deref()?;
+        Some(if self.location_inferred {
+            format!("{} (from photo)", location)
+        } else {
+            location.to_string()
+        })
+    }
+
+    /// The post text as Markdown
+    pub fn content(&self) -> String {
+        self.body.to_markdown()
+    }
+
+    /// The reshared text as Markdown, `None` when nothing was reshared or
+    /// only an attachment
+    pub fn reshare_content(&self) -> Option<String> {
+        (!self.reshare_body.is_empty()).then(|| self.reshare_body.to_markdown())
+    }
+}
+
+/// Serialize a post or comment body as its `content` Markdown and the
+/// `body` tree, for templates and data files
+fn serialize_body<S: Serializer>(body: &RichText, serializer: S) -> Result<S::Ok, S::Error> {
+    let mut map = serializer.serialize_map(Some(2))?;
+    map.serialize_entry("content", &body.to_markdown())?;
+    map.serialize_entry("body", body)?;
+    map.end()
+}
+
+/// Serialize a reshared body as `reshare_content` Markdown, null when
+/// empty, and the `reshare_body` tree
+fn serialize_reshare_body<S: Serializer>(
+    body: &RichText,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    let content = (!body.is_empty()).then(|| body.to_markdown());
+    let mut map = serializer.serialize_map(Some(2))?;
+    map.serialize_entry("reshare_content", &content)?;
+    map.serialize_entry("reshare_body", body)?;
+    map.end()
+}
+
+/// Who a post was shared with
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Visibility {
+    /// The export doesn't say
+    #[default]
+    Unknown,
+    Public,
+    /// The author's circles
+    Circles,
+    /// The author's circles and the people in theirs
+    ExtendedCircles,
+    /// Only the circles and people in `PostData::shared_with`
+    Specific,
+    /// Members of a community, in one of its categories
+    Community {
+        name: String,
+        url: String,
+        category: Option<String>,
+    },
+}
+
+impl Visibility {
+    /// The audience named on a "Shared with" line, `None` for circles and
+    /// people
+    pub fn from_label(label: &str) -> Option<Self> {
+        match label.trim().to_lowercase().as_str() {
+            "public" => Some(Visibility::Public),
+            "your circles" => Some(Visibility::Circles),
+            "extended circles" => Some(Visibility::ExtendedCircles),
+            _ => None,
+        }
+    }
+
+    /// How the audience was shown, `None` when only circles and people
+    /// were named
+    pub fn label(&self) -> Option<&str> {
+        match self {
+            Visibility::Public => Some("Public"),
+            Visibility::Circles => Some("Your circles"),
+            Visibility::ExtendedCircles => Some("Extended circles"),
+            Visibility::Community { name, .. } => Some(name),
+            Visibility::Specific | Visibility::Unknown => None,
+        }
+    }
+
+    /// Name of the community the post was made in
+    pub fn community(&self) -> Option<&str> {
+        match self {
+            Visibility::Community { name, .. } => Some(name),
+            _ => None,
+        }
+    }
+
+    /// Short name for front matter and settings, like `extended_circles`
+    pub fn key(&self) -> &'static str {
+        match self {
+            Visibility::Unknown => "unknown",
+            Visibility::Public => "public",
+            Visibility::Circles => "circles",
+            Visibility::ExtendedCircles => "extended_circles",
+            Visibility::Specific => "specific",
+            Visibility::Community { .. } => "community",
+        }
+    }
+}
+
+/// A circle or person a post was shared with
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct Recipient {
+    pub name: String,
+    /// Profile of a person or page, `None` for circles
+    pub url: Option<String>,
+}
+
+/// A collection of the author's posts on one topic
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct Collection {
+    pub name: String,
+    pub url: String,
+}
+
+/// A photo attached to a post
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Image {
+    /// Image source, a Takeout path or a googleusercontent URL
+    pub src: String,
+    /// Where the photo links to, usually its full-size version
+    pub link: Option<String>,
+    /// Alt text, empty when Google+ only gave its "Image" placeholder
+    pub alt: String,
+    /// Caption shown under the photo
+    pub caption: Option<String>,
+    /// Which album of the post the photo is in, counting from 0, `None`
+    /// for photos outside an album
+    pub album: Option<usize>,
+    /// Metadata of the photo file in the Takeout, if it was found
+    pub info: Option<ImageInfo>,
+    /// Resized copies for `srcset`, smallest first, empty unless the
+    /// settings file asks for them
+    pub variants: Vec<ImageVariant>,
+}
+
+/// A resized copy of a photo
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ImageVariant {
+    /// Site path of the copy
+    pub src: String,
+    pub width: u32,
+    pub height: u32,
+    pub format: VariantFormat,
+}
+
+/// File format of a resized photo
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum VariantFormat {
+    Jpeg,
+    /// Lossless, best for screenshots and graphics
+    Webp,
+}
+
+impl VariantFormat {
+    /// File extension for copies in this format
+    pub fn extension(self) -> &'static str {
+        match self {
+            VariantFormat::Jpeg => "jpg",
+            VariantFormat::Webp => "webp",
+        }
+    }
+
+    /// MIME type for `<source type="...">`
+    pub fn mime_type(self) -> &'static str {
+        match self {
+            VariantFormat::Jpeg => "image/jpeg",
+            VariantFormat::Webp => "image/webp",
+        }
+    }
+}
+
+/// What the Takeout knows about a photo file
+///
+/// Comes from the `.json` sidecar Google writes next to each photo, with
+/// the dimensions read from the file itself.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ImageInfo {
+    /// Usually the original file name
+    pub title: Option<String>,
+    /// Description typed in for the photo
+    pub description: Option<String>,
+    /// When the photo was taken, in UTC
+    pub taken: Option<String>,
+    /// When the photo was uploaded, in UTC
+    pub created: Option<String>,
+    pub location: Option<GeoPoint>,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+}
+
+/// A position on Earth in decimal degrees
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct GeoPoint {
+    pub latitude: f64,
+    pub longitude: f64,
+}
+
+impl Image {
+    /// A photo with only a source
+    pub fn new(src: &str) -> Self {
+        Image {
+            src: src.to_string(),
+            ..Default::default()
+        }
+    }
+
+    /// The full-size version of the photo, falling back to its source
+    pub fn full_size(&self) -> &str {
+        self.link
+            .as_deref()
+            .filter(|link| !link.is_empty())
+            .unwrap_or(&self.src)
+    }
+
+    /// Width and height in pixels, when known
+    pub fn dimensions(&self) -> Option<(u32, u32)> {
+        let info = self.info.as_ref()?;
+        Some((info.width?, info.height?))
+    }
+
+    /// Text describing the photo, its alt text or else its caption
+    pub fn description(&self) -> Option<&str> {
+        Some(self.alt.as_str())
+            .filter(|alt| !alt.is_empty())
+            .or(self.caption.as_deref())
+    }
+}
+
+#[derive(Debug, Default, Serialize)]
 pub struct Comment {
     pub author: String,
+    pub author_url: String,
     pub date: String,
-    pub content: String,
+    /// Comment text, serialized along with its Markdown as `content`
+    #[serde(flatten, serialize_with = "serialize_body")]
+    pub body: RichText,
+    pub images: Vec<String>,
+    pub links: Vec<(String, String)>, // (url, title)
+    pub hashtags: Vec<String>,
+    pub mentions: Vec<(String, String)>, // (profile url, name)
+}
+
+impl Comment {
+    /// The comment text as Markdown
+    pub fn content(&self) -> String {
+        self.body.to_markdown()
+    }
 }
@@ -3,11 +3,12 @@
 use markup5ever_rcdom as rcdom;
 use rcdom::{Handle, NodeData};
 
+use crate::content::{push_text, split_blocks, Inline, RichText};
 use crate::dom::{
-    find_parent_href, format_markdown_link, get_attr_value, get_text_content,
-    get_text_content_formatted, has_attr, has_class,
+    find_ancestor, find_parent_link, get_attr_value, get_parent, get_text_content, has_attr,
+    has_class, has_class_name,
 };
-use crate::models::{Comment, PostData};
+use crate::models::{Collection, Comment, Image, PostData, Recipient, Visibility};
 use crate::utils::{clean_location, convert_to_utc};
 
 /// Extract structured data from the HTML document
@@ -36,6 +37,7 @@ fn find_post_elements(handle: &Handle, post_data: &mut PostData) {
         // Extract author from header
         if tag_name == "a" && has_class(&attrs, "author") && post_data.author.is_empty() {
             post_data.author = get_text_content(handle);
+            post_data.author_url = get_attr_value(&attrs, "href").unwrap_or_default();
         }
 
         // Extract date/time and canonical URL from post header (not comments)
@@ -54,7 +56,12 @@ fn find_post_elements(handle: &Handle, post_data: &mut PostData) {
 
         // Extract main content
         if has_class(&attrs, "main-content") {
-            post_data.content = get_text_content_formatted(handle);
+            post_data.body = extract_rich_text(handle);
+            collect_tags(
+                &post_data.body,
+                &mut post_data.hashtags,
+                &mut post_data.mentions,
+            );
         }
 
         // Extract title from HTML title tag
@@ -71,16 +78,55 @@ fn find_post_elements(handle: &Handle, post_data: &mut PostData) {
             post_data.location = Some(clean_location(&location_text));
         }
 
-        // Extract images from albums or media links
-        if tag_name == "img" && has_class(&attrs, "media") {
-            if let Some(src) = get_attr_value(&attrs, "src") {
-                post_data.images.push(src);
+        // Albums keep their photos together
+        if tag_name == "div" && has_class_name(&attrs, "album") {
+            let album = post_data
+                .images
+                .iter()
+                .filter_map(|image| image.album)
+                .max()
+                .map_or(0, |last| last + 1);
+            collect_album(handle, album, &mut post_data.images);
+        }
+
+        // Extract photos outside albums, comments have their own
+        if tag_name == "img"
+            && has_class_name(&attrs, "media")
+            && find_ancestor(handle, "div", "album").is_none()
+            && find_ancestor(handle, "div", "comment").is_none()
+        {
+            if let Some(image) = extract_image(handle, &attrs, None) {
+                post_data.images.push(image);
             }
         }
 
-        // Extract video links
+        // Extract the author's profile photo, comments have their own
+        if tag_name == "img"
+            && has_class_name(&attrs, "author-photo")
+            && post_data.author_photo.is_none()
+            && find_ancestor(handle, "div", "comment").is_none()
+        {
+            post_data.author_photo = get_attr_value(&attrs, "src");
+        }
+
+        // Extract the preview image of the shared link
+        if tag_name == "img" && post_data.link_preview.is_none() {
+            if let Some(embed) = find_ancestor(handle, "a", "link-embed") {
+                if let Some(src) = get_attr_value(&attrs, "src").filter(|src| !src.is_empty()) {
+                    post_data.link_preview = Some(Image {
+                        alt: get_text_content(&embed)
+                            .split_whitespace()
+                            .collect::<Vec<_>>()
+                            .join(" "),
+                        ..Image::new(&src)
+                    });
+                }
+            }
+        }
+
+        // Extract video links, from the media link the placeholder is in
         if has_class(&attrs, "video-placeholder") {
-            if let Some(href) = find_parent_href(handle) {
+            if let Some(href) = find_parent_link(handle, "media-link") {
                 post_data.video_url = Some(href);
             }
         }
@@ -97,7 +143,7 @@ fn find_post_elements(handle: &Handle, post_data: &mut PostData) {
 
         // Extract visibility
         if has_class(&attrs, "visibility") {
-            post_data.visibility = get_text_content(handle).replace("Shared with: ", "");
+            parse_visibility(handle, post_data);
         }
 
         // Extract +1 information
@@ -114,15 +160,22 @@ fn find_post_elements(handle: &Handle, post_data: &mut PostData) {
             let attribution_text = get_text_content(handle);
             // Extract author name from "Originally shared by Author Name"
             post_data.reshare_author = Some(attribution_text.replace("Originally shared by ", ""));
-
-            // Get reshare content from parent div's text nodes
-            if let Some(reshare_content) = extract_reshare_content(handle) {
-                post_data.reshare_content = Some(reshare_content);
+            post_data.reshare_url = get_attr_value(&attrs, "href");
+
+            // Reshared text is the rest of the parent div
+            if let Some(parent) = get_parent(handle) {
+                let reshare_body = extract_rich_text(&parent);
+                collect_tags(
+                    &reshare_body,
+                    &mut post_data.hashtags,
+                    &mut post_data.mentions,
+                );
+                post_data.reshare_body = reshare_body;
             }
         }
 
-        // Extract comments
-        if has_class(&attrs, "comment") {
+        // Extract comments (not the "comments" wrapper around them)
+        if has_class_name(&attrs, "comment") {
             if let Some(comment) = extract_comment(handle) {
                 post_data.comments.push(comment);
             }
@@ -135,18 +188,75 @@ fn find_post_elements(handle: &Handle, post_data: &mut PostData) {
     }
 }
 
+/// Collect the photos of an album, in order
+fn collect_album(handle: &Handle, album: usize, images: &mut Vec<Image>) {
+    for child in handle.children.borrow().iter() {
+        if let NodeData::Element {
+            ref name,
+            ref attrs,
+            ..
+        } = child.data
+        {
+            let attrs = attrs.borrow();
+            if name.local.as_ref() == "img" && has_class_name(&attrs, "media") {
+                images.extend(extract_image(child, &attrs, Some(album)));
+            }
+        }
+        collect_album(child, album, images);
+    }
+}
+
+/// Extract a photo with the media link around it
+///
+/// The link's target is kept as the full-size version and a `<p>` inside
+/// the link as the caption, both on one line.  Google+ gave most photos
+/// the alt text "Image", which says nothing and is dropped.
+fn extract_image(
+    handle: &Handle,
+    attrs: &[markup5ever::interface::Attribute],
+    album: Option<usize>,
+) -> Option<Image> {
+    let src = get_attr_value(attrs, "src")?;
+    let single_line = |text: &str| text.split_whitespace().collect::<Vec<_>>().join(" ");
+    let alt = get_attr_value(attrs, "alt")
+        .map(|alt| single_line(&alt))
+        .filter(|alt| alt != "Image")
+        .unwrap_or_default();
+
+    let media_link = find_ancestor(handle, "a", "media-link");
+    let link = media_link.as_ref().and_then(|link| match &link.data {
+        NodeData::Element { attrs, .. } => get_attr_value(&attrs.borrow(), "href"),
+        _ => None,
+    });
+    let caption = media_link.and_then(|link| {
+        link.children
+            .borrow()
+            .iter()
+            .find_map(|child| match &child.data {
+                NodeData::Element { name, .. } if name.local.as_ref() == "p" => {
+                    Some(single_line(&get_text_content(child)))
+                        .filter(|caption| !caption.is_empty())
+                }
+                _ => None,
+            })
+    });
+
+    Some(Image {
+        src,
+        link,
+        alt,
+        caption,
+        album,
+        info: None,
+        variants: Vec::new(),
+    })
+}
+
 /// Extract comment data from a comment node
 fn extract_comment(handle: &Handle) -> Option<Comment> {
-    let mut author = String::new();
-    let mut date = String::new();
-    let mut content = String::new();
-
-    fn extract_comment_parts(
-        node: &Handle,
-        author: &mut String,
-        date: &mut String,
-        content: &mut String,
-    ) {
+    let mut comment = Comment::default();
+
+    fn extract_comment_parts(node: &Handle, comment: &mut Comment) {
         if let NodeData::Element {
             ref name,
             ref attrs,
@@ -156,67 +266,168 @@ fn extract_comment(handle: &Handle) -> Option<Comment> {
             let attrs = attrs.borrow();
             let tag_name = name.local.as_ref();
 
-            if tag_name == "a" && has_class(&attrs, "author") && author.is_empty() {
-                *author = get_text_content(node);
-            } else if has_class(&attrs, "time") && date.is_empty() {
+            if tag_name == "a" && has_class(&attrs, "author") && comment.author.is_empty() {
+                comment.author = get_text_content(node);
+                comment.author_url = get_attr_value(&attrs, "href").unwrap_or_default();
+            } else if has_class(&attrs, "time") && comment.date.is_empty() {
                 let date_text = get_text_content(node);
                 // Comment dates have "- " prefix in the HTML, strip it
                 let date_text = date_text.trim_start_matches("- ").trim();
-                *date = convert_to_utc(date_text);
-            } else if has_class(&attrs, "comment-content") && content.is_empty() {
-                *content = get_text_content_formatted(node);
+                comment.date = convert_to_utc(date_text);
+            } else if has_class(&attrs, "comment-content") && comment.body.is_empty() {
+                comment.body = extract_rich_text(node);
+                collect_tags(&comment.body, &mut comment.hashtags, &mut comment.mentions);
+            } else if tag_name == "img" && has_class(&attrs, "media") {
+                // Photos attached to the comment
+                if let Some(src) = get_attr_value(&attrs, "src") {
+                    comment.images.push(src);
+                }
+            } else if tag_name == "a" && has_class(&attrs, "inline-link-embed") {
+                // Link previews attached to the comment
+                if let Some(href) = get_attr_value(&attrs, "href") {
+                    let title = get_text_content(node);
+                    comment.links.push((href, title));
+                }
             }
         }
 
         for child in node.children.borrow().iter() {
-            extract_comment_parts(child, author, date, content);
+            extract_comment_parts(child, comment);
         }
     }
 
-    extract_comment_parts(handle, &mut author, &mut date, &mut content);
+    extract_comment_parts(handle, &mut comment);
 
-    if !author.is_empty() && !content.is_empty() {
-        Some(Comment {
-            author,
-            date,
-            content,
-        })
+    // Keep comments that are only an attachment, drop empty ones
+    let has_body =
+        !comment.body.is_empty() || !comment.images.is_empty() || !comment.links.is_empty();
+    if !comment.author.is_empty() && has_body {
+        Some(comment)
     } else {
         None
     }
 }
 
-/// Extract reshare content from the parent div of a reshare-attribution element
-fn extract_reshare_content(reshare_attr_handle: &Handle) -> Option<String> {
-    // Get parent element
-    let parent_weak_opt = reshare_attr_handle.parent.take();
-    let result = if let Some(ref weak) = parent_weak_opt {
-        if let Some(parent_strong) = weak.upgrade() {
-            // Extract text content from parent, excluding certain elements
-            let content = extract_reshare_text(&parent_strong);
-            let cleaned = content.trim().to_string();
-            if cleaned.is_empty() {
-                None
-            } else {
-                Some(cleaned)
+/// Parse the "Shared with" line under a post
+///
+/// The line starts with the audience, like `Public` or `Your circles`,
+/// followed by linked people.  Posts shared with named circles and people
+/// only are `Specific`.  A link to a community makes it the audience, with
+/// a link to one of its streams as the category, and a link to a
+/// collection is the collection the post was made in.
+fn parse_visibility(handle: &Handle, post_data: &mut PostData) {
+    let mut visibility = None;
+    let mut shared_with = Vec::new();
+
+    for child in handle.children.borrow().iter() {
+        match &child.data {
+            NodeData::Text { contents } => {
+                let text = contents.borrow();
+                let text = text.trim_start().trim_start_matches("Shared with:");
+                // Skip separators like the `>` between a community and its
+                // category
+                let entries = text
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|entry| entry.chars().any(char::is_alphanumeric));
+                for entry in entries {
+                    match Visibility::from_label(entry) {
+                        Some(audience) if visibility.is_none() => visibility = Some(audience),
+                        _ => shared_with.push(Recipient {
+                            name: entry.to_string(),
+                            url: None,
+                        }),
+                    }
+                }
+            }
+            NodeData::Element { name, attrs, .. } if name.local.as_ref() == "a" => {
+                let href = get_attr_value(&attrs.borrow(), "href").unwrap_or_default();
+                let link_text = get_text_content(child).trim().to_string();
+                if href.contains("/communities/") {
+                    match &mut visibility {
+                        None => {
+                            visibility = Some(Visibility::Community {
+                                name: link_text,
+                                url: href,
+                                category: None,
+                            })
+                        }
+                        Some(Visibility::Community { category, .. })
+                            if href.contains("/stream/") && !link_text.is_empty() =>
+                        {
+                            *category = Some(link_text)
+                        }
+                        Some(_) => {}
+                    }
+                } else if href.contains("/collection/") {
+                    post_data.collection = Some(Collection {
+                        name: link_text,
+                        url: href,
+                    });
+                } else if !link_text.is_empty() {
+                    shared_with.push(Recipient {
+                        name: link_text,
+                        url: Some(href).filter(|href| !href.is_empty()),
+                    });
+                }
             }
-        } else {
-            None
+            _ => {}
         }
+    }
+
+    post_data.visibility = visibility.unwrap_or(if shared_with.is_empty() {
+        Visibility::Unknown
     } else {
-        None
-    };
-    // Restore parent reference
-    reshare_attr_handle.parent.set(parent_weak_opt);
-    result
+        Visibility::Specific
+    });
+    post_data.shared_with = shared_with;
 }
 
-/// Extract reshare text, excluding reshare-attribution and link-embed elements
-fn extract_reshare_text(handle: &Handle) -> String {
-    let mut text = String::new();
+/// Collect #hashtags and +mentions from parsed text
+///
+/// Hashtags are stored without the leading '#', mentions as
+/// (profile url, name).  Repeats are only recorded once.
+fn collect_tags(
+    content: &RichText,
+    hashtags: &mut Vec<String>,
+    mentions: &mut Vec<(String, String)>,
+) {
+    for inline in content.inlines() {
+        match inline {
+            Inline::Hashtag { tag, .. } if !tag.is_empty() && !hashtags.contains(tag) => {
+                hashtags.push(tag.clone());
+            }
+            Inline::Mention { href, name } => {
+                let mention = (href.clone(), name.clone());
+                if !mentions.contains(&mention) {
+                    mentions.push(mention);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Parse the text of a post body, reshare or comment
+///
+/// Reshare attributions and link previews are extracted separately, so
+/// they are left out of the text.  `<p>` elements and blank lines start new
+/// blocks, see `split_blocks()`.
+pub fn extract_rich_text(handle: &Handle) -> RichText {
+    let mut rich_text = RichText::default();
+    let mut inlines = Vec::new();
+
+    fn end_paragraph(rich_text: &mut RichText, inlines: &mut Vec<Inline>) {
+        rich_text
+            .blocks
+            .extend(split_blocks(std::mem::take(inlines)));
+    }
 
-    fn collect_reshare_text(node: &Handle, text: &mut String) {
+    fn collect_inlines(node: &Handle, rich_text: &mut RichText, inlines: &mut Vec<Inline>) {
         match &node.data {
+            NodeData::Text { ref contents } => {
+                push_text(inlines, &contents.borrow());
+            }
             NodeData::Element {
                 ref name,
                 ref attrs,
@@ -225,43 +436,230 @@ fn extract_reshare_text(handle: &Handle) -> String {
                 let attrs = attrs.borrow();
                 let tag_name = name.local.as_ref();
 
-                // Skip reshare-attribution and link-embed elements
                 if has_class(&attrs, "reshare-attribution") || has_class(&attrs, "link-embed") {
                     return;
                 }
 
-                // Handle br tags as newlines
-                if tag_name == "br" {
-                    text.push('\n');
-                } else if tag_name == "a" {
-                    // Handle links within reshared content - convert to Markdown
-                    if let Some(href) = get_attr_value(&attrs, "href") {
-                        let link_text = get_text_content(node);
-                        format_markdown_link(text, &href, &link_text);
-                    } else {
-                        // No href attribute, just extract text
-                        for child in node.children.borrow().iter() {
-                            collect_reshare_text(child, text);
-                        }
+                match tag_name {
+                    "br" => inlines.push(Inline::LineBreak),
+                    "p" => {
+                        end_paragraph(rich_text, inlines);
+                        collect_children(node, rich_text, inlines);
+                        end_paragraph(rich_text, inlines);
                     }
-                } else {
-                    // Recurse into other elements
-                    for child in node.children.borrow().iter() {
-                        collect_reshare_text(child, text);
+                    "b" | "strong" | "i" | "em" | "del" | "s" | "strike" => {
+                        let mut children = Vec::new();
+                        collect_children(node, rich_text, &mut children);
+                        inlines.push(match tag_name {
+                            "b" | "strong" => Inline::Bold { inlines: children },
+                            "i" | "em" => Inline::Italic { inlines: children },
+                            _ => Inline::Strike { inlines: children },
+                        });
                     }
+                    "a" if has_class_name(&attrs, "ot-hashtag") => {
+                        let tag = get_text_content(node).trim_start_matches('#').to_string();
+                        let href = get_attr_value(&attrs, "href").unwrap_or_default();
+                        inlines.push(Inline::Hashtag { href, tag });
+                    }
+                    "a" if has_class_name(&attrs, "proflink") => {
+                        let name = get_text_content(node);
+                        let href = get_attr_value(&attrs, "href").unwrap_or_default();
+                        inlines.push(Inline::Mention { href, name });
+                    }
+                    "a" => match get_attr_value(&attrs, "href") {
+                        // Keep links whole so they can be rendered per format
+                        Some(href) => {
+                            let text = get_text_content(node);
+                            inlines.push(Inline::Link { href, text });
+                        }
+                        // No href attribute, just extract text
+                        None => collect_children(node, rich_text, inlines),
+                    },
+                    // The "+" in front of a mention is part of the mention
+                    _ if has_class_name(&attrs, "proflinkPrefix") => {}
+                    _ => collect_children(node, rich_text, inlines),
                 }
             }
-            NodeData::Text { ref contents } => {
-                text.push_str(&contents.borrow());
-            }
-            _ => {
-                for child in node.children.borrow().iter() {
-                    collect_reshare_text(child, text);
-                }
-            }
+            _ => collect_children(node, rich_text, inlines),
+        }
+    }
+
+    fn collect_children(node: &Handle, rich_text: &mut RichText, inlines: &mut Vec<Inline>) {
+        for child in node.children.borrow().iter() {
+            collect_inlines(child, rich_text, inlines);
         }
     }
 
-    collect_reshare_text(handle, &mut text);
-    text.trim().to_string()
+    collect_children(handle, &mut rich_text, &mut inlines);
+    end_paragraph(&mut rich_text, &mut inlines);
+    rich_text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use html5ever::tendril::TendrilSink;
+
+    fn parse(html: &str) -> PostData {
+        let dom = html5ever::parse_document(rcdom::RcDom::default(), Default::default()).one(html);
+        extract_post_data(&dom.document)
+    }
+
+    #[test]
+    fn test_album_images() {
+        let post = parse(
+            r#"<div class="album"><a href="../Photos/a.jpg" class="media-link"><img src="../Photos/a.jpg" alt="Image" class="media"></a><a href="https://lh3.googleusercontent.com/b/s0/b.jpg" class="media-link"><img src="https://lh3.googleusercontent.com/b/w288-h288/b.jpg" alt="Tulips" class="media"><p>Tulips at the
+            show</p></a></div>
+            <a href="../Photos/c.jpg" class="media-link"><img src="../Photos/c.jpg" alt="Image" class="media"></a>"#,
+        );
+
+        assert_eq!(
+            post.images,
+            vec![
+                Image {
+                    link: Some(String::from("../Photos/a.jpg")),
+                    album: Some(0),
+                    ..Image::new("../Photos/a.jpg")
+                },
+                Image {
+                    link: Some(String::from("https://lh3.googleusercontent.com/b/s0/b.jpg")),
+                    alt: String::from("Tulips"),
+                    caption: Some(String::from("Tulips at the show")),
+                    album: Some(0),
+                    ..Image::new("https://lh3.googleusercontent.com/b/w288-h288/b.jpg")
+                },
+                Image {
+                    link: Some(String::from("../Photos/c.jpg")),
+                    ..Image::new("../Photos/c.jpg")
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_reshare() {
+        let post = parse(
+            r#"<div class="main-content">Ha</div><div><a href="https://plus.google.com/+JakeGross/posts/HgJTPoeCorQ" class="reshare-attribution">Originally shared by Jake Gross</a>For the noobs.</div>"#,
+        );
+        assert_eq!(post.reshare_author.as_deref(), Some("Jake Gross"));
+        assert_eq!(
+            post.reshare_url.as_deref(),
+            Some("https://plus.google.com/+JakeGross/posts/HgJTPoeCorQ")
+        );
+        assert_eq!(post.reshare_content().as_deref(), Some("For the noobs."));
+    }
+
+    #[test]
+    fn test_albums_numbered_and_comment_images_kept_apart() {
+        let post = parse(
+            r#"<div class="album"><img src="a.jpg" class="media"></div>
+            <div class="album"><img src="b.jpg" class="media"></div>
+            <div class="comment"><a class="author" href="https://plus.google.com/1">Paul</a>
+            <img src="c.jpg" class="media"></div>"#,
+        );
+
+        let albums: Vec<Option<usize>> = post.images.iter().map(|image| image.album).collect();
+        assert_eq!(albums, vec![Some(0), Some(1)]);
+        assert_eq!(post.comments[0].images, vec![String::from("c.jpg")]);
+    }
+
+    #[test]
+    fn test_video_from_its_media_link() {
+        let post = parse(
+            r#"<a href="http://www.youtube.com/watch?v=u6XAPnuFjJc" class="media-link"><div class="video-placeholder" title="RSA Animate"><span class="play-icon"></span></div><p>RSA Animate</p></a>"#,
+        );
+        assert_eq!(
+            post.video_url.as_deref(),
+            Some("http://www.youtube.com/watch?v=u6XAPnuFjJc")
+        );
+        assert!(post.images.is_empty());
+    }
+
+    #[test]
+    fn test_link_preview() {
+        let post = parse(
+            r#"<a href="http://thereifixedit.com/lego/" class="link-embed"><h3>Jan Vormann’s Best Lego Installations</h3><img src="http://thereifixedit.files.wordpress.com/2011/08/31ea.jpg?w=500" alt=""></a>"#,
+        );
+        assert_eq!(
+            post.link_preview,
+            Some(Image {
+                alt: String::from("Jan Vormann’s Best Lego Installations"),
+                ..Image::new("http://thereifixedit.files.wordpress.com/2011/08/31ea.jpg?w=500")
+            })
+        );
+        assert!(post.images.is_empty());
+    }
+
+    #[test]
+    fn test_author_photo() {
+        let post = parse(
+            r#"<img class="author-photo" src="https://lh3.googleusercontent.com/a-/AAuE7m=s64-c" alt="Christopher Hicks">
+            <div class="comment"><img class="author-photo" src="https://lh3.googleusercontent.com/a-/other=s64-c"></div>"#,
+        );
+        assert_eq!(
+            post.author_photo.as_deref(),
+            Some("https://lh3.googleusercontent.com/a-/AAuE7m=s64-c")
+        );
+    }
+
+    #[test]
+    fn test_visibility() {
+        let post = parse(
+            r#"<div class="visibility">Shared with: Public, <a href="https://plus.google.com/102691505480521008290">Matt Asay</a>, <a href="https://plus.google.com/106932238751778070993"></a></div>"#,
+        );
+        assert_eq!(post.visibility, Visibility::Public);
+        assert_eq!(
+            post.shared_with,
+            vec![Recipient {
+                name: String::from("Matt Asay"),
+                url: Some(String::from(
+                    "https://plus.google.com/102691505480521008290"
+                )),
+            }]
+        );
+        assert_eq!(post.audience(), "Public, Matt Asay");
+
+        let post = parse(r#"<div class="visibility">Shared with: Extended circles</div>"#);
+        assert_eq!(post.visibility, Visibility::ExtendedCircles);
+
+        let post = parse(
+            r#"<div class="visibility">Shared with: Friends, <a href="https://plus.google.com/+PaulFernandez">Paul Fernandez</a></div>"#,
+        );
+        assert_eq!(post.visibility, Visibility::Specific);
+        assert_eq!(post.shared_with[0].url, None);
+        assert_eq!(post.audience(), "Friends, Paul Fernandez");
+
+        let post = parse(
+            r#"<div class="visibility">Shared with: <a href="https://plus.google.com/communities/113390432655174294208">Rust Programming</a></div>"#,
+        );
+        assert_eq!(
+            post.visibility,
+            Visibility::Community {
+                name: String::from("Rust Programming"),
+                url: String::from("https://plus.google.com/communities/113390432655174294208"),
+                category: None,
+            }
+        );
+
+        let post = parse(
+            r#"<div class="visibility">Shared with: <a href="https://plus.google.com/communities/113390432655174294208">Rust Programming</a> &gt; <a href="https://plus.google.com/communities/113390432655174294208/stream/b6c2ac1a-0ed5-4ab4-9e6b-a5e8ab1c5bdb">Show and Tell</a></div>"#,
+        );
+        assert_eq!(post.visibility.community(), Some("Rust Programming"));
+        assert_eq!(post.audience(), "Rust Programming (Show and Tell)");
+
+        let post = parse(
+            r#"<div class="visibility">Shared with: Public, <a href="https://plus.google.com/collection/AsA6OE">Retro Computing</a></div>"#,
+        );
+        assert_eq!(post.visibility, Visibility::Public);
+        assert!(post.shared_with.is_empty());
+        assert_eq!(
+            post.collection,
+            Some(Collection {
+                name: String::from("Retro Computing"),
+                url: String::from("https://plus.google.com/collection/AsA6OE"),
+            })
+        );
+
+        assert_eq!(parse("<div></div>").visibility, Visibility::Unknown);
+    }
 }
@@ -0,0 +1,237 @@
+//! Plain-text generation from post data
+//!
+//! Text is wrapped to a fixed width and links become numbered references
+//! listed at the end of the post, like a footnoted email.
+
+use crate::markdown::{post_title, published_image_path, transform_image_path};
+use crate::models::PostData;
+use crate::utils::wrap_text;
+use crate::video::Video;
+
+/// Line width plain-text output is wrapped to
+pub const WRAP_WIDTH: usize = 72;
+
+/// Indent for comment text under its author line
+const COMMENT_INDENT: &str = "    ";
+
+/// Generate plain text from post data
+///
+/// # Arguments
+/// * `post_data` - The post data to generate text from
+/// * `date_prefix` - The date prefix (YYYY-MM-DD) for image paths
+pub fn generate_plain_text(post_data: &PostData, date_prefix: &str) -> String {
+    let mut text = String::new();
+    let mut references = Vec::new();
+
+    let title = post_title(post_data)
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ");
+    text.push_str(&format!(
+        "{}\n{}\n\n",
+        title,
+        "=".repeat(title.chars().count())
+    ));
+
+    // Post metadata
+    if !post_data.date.is_empty() {
+        text.push_str(&format!("Date: {}\n", post_data.date));
+    }
+    if !post_data.author.is_empty() {
+        text.push_str(&format!("Author: {}\n", post_data.author));
+    }
+    if let Some(location) = post_data.location_label() {
+        text.push_str(&format!("Location: {}\n", location));
+    }
+    text.push('\n');
+
+    // Main content
+    let body = post_data.body.to_plain_text(&mut references);
+    if !body.is_empty() {
+        text.push_str(&format!("{}\n\n", wrap_text(&body, WRAP_WIDTH)));
+    }
+
+    // Reshare information
+    if let Some(reshare_author) = &post_data.reshare_author {
+        let author = match &post_data.reshare_url {
+            Some(reshare_url) => link_text(reshare_url, reshare_author),
+            None => reshare_author.clone(),
+        };
+        text.push_str(&format!("Originally shared by {}:\n\n", author));
+        let reshare = post_data.reshare_body.to_plain_text(&mut references);
+        if !reshare.is_empty() {
+            text.push_str(&format!("{}\n\n", wrap_text(&reshare, WRAP_WIDTH)));
+        }
+    }
+
+    // Images
+    if !post_data.images.is_empty() {
+        text.push_str("Images:\n");
+        for image in &post_data.images {
+            let path = transform_image_path(&image.src, date_prefix);
+            match &image.caption {
+                Some(caption) => text.push_str(&format!("  {} - {}\n", path, caption)),
+                None => text.push_str(&format!("  {}\n", path)),
+            }
+        }
+        text.push('\n');
+    }
+
+    // Video
+    if let Some(video_url) = &post_data.video_url {
+        let watch_url = Video::detect(video_url).watch_url(date_prefix);
+        text.push_str(&format!("Video: {}\n\n", watch_url));
+    }
+
+    // Links
+    if !post_data.links.is_empty() {
+        text.push_str("Links:\n");
+        for (url, title) in &post_data.links {
+            text.push_str(&format!("  {}\n", link_text(url, title)));
+        }
+        text.push('\n');
+    }
+
+    let audience = post_data.audience();
+    if !audience.is_empty() {
+        text.push_str(&format!("Shared with: {}\n", audience));
+    }
+    if let Some(collection) = &post_data.collection {
+        text.push_str(&format!("Collection: {}\n", collection.name));
+    }
+    if !post_data.plus_ones.is_empty() {
+        text.push_str(&format!("+1'd by: {}\n", post_data.plus_ones.join(", ")));
+    }
+    text.push('\n');
+
+    // Comments
+    if !post_data.comments.is_empty() {
+        text.push_str("Comments\n--------\n\n");
+        for comment in &post_data.comments {
+            text.push_str(&comment.author);
+            if !comment.date.is_empty() {
+                text.push_str(&format!(" - {}", comment.date));
+            }
+            text.push('\n');
+
+            let content = comment.body.to_plain_text(&mut references);
+            let width = WRAP_WIDTH - COMMENT_INDENT.len();
+            for line in wrap_text(&content, width).lines() {
+                if !line.is_empty() {
+                    text.push_str(COMMENT_INDENT);
+                    text.push_str(line);
+                }
+                text.push('\n');
+            }
+            for image_url in &comment.images {
+                text.push_str(&format!(
+                    "{}Image: {}\n",
+                    COMMENT_INDENT,
+                    published_image_path(image_url, date_prefix)
+                ));
+            }
+            for (url, title) in &comment.links {
+                text.push_str(&format!(
+                    "{}Link: {}\n",
+                    COMMENT_INDENT,
+                    link_text(url, title)
+                ));
+            }
+            text.push('\n');
+        }
+    }
+
+    // Numbered references for links in the text
+    if !references.is_empty() {
+        text.push_str("References:\n");
+        for (index, url) in references.iter().enumerate() {
+            text.push_str(&format!("[{}] {}\n", index + 1, url));
+        }
+    }
+
+    format!("{}\n", text.trim_end())
+}
+
+/// Format a link as `title <url>`, or the bare URL when there is no title
+fn link_text(url: &str, title: &str) -> String {
+    if title.is_empty() || title == url {
+        url.to_string()
+    } else {
+        format!("{} <{}>", title, url)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{Comment, Image, Visibility};
+    use crate::test_helpers::{link, paragraph, text};
+
+    #[test]
+    fn test_generate_plain_text() {
+        let post = PostData {
+            author: String::from("Christopher Hicks"),
+            date: String::from("2013-09-05T21:03:42Z"),
+            title: String::from("Vatra Inc"),
+            body: paragraph(vec![
+                text("Dinner at"),
+                link("http://vatra.example/", "Vatra"),
+            ]),
+            images: vec![Image::new("../Photos/a.jpg")],
+            visibility: Visibility::Public,
+            comments: vec![Comment {
+                author: String::from("Paul"),
+                date: String::from("2013-09-06T01:00:00Z"),
+                body: paragraph(vec![text("Yum, "), link("http://yum.example/", "agreed")]),
+                images: vec![
+                    String::from("../Photos/b.jpg"),
+                    String::from("https://lh3.googleusercontent.com/-x/AAuE7m=s64-c"),
+                ],
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+
+        assert_eq!(
+            generate_plain_text(&post, "2013-09-05"),
+            "Vatra Inc\n=========\n\n\
+             Date: 2013-09-05T21:03:42Z\nAuthor: Christopher Hicks\n\n\
+             Dinner at Vatra [1]\n\n\
+             Images:\n  /posts/2013-09-05-a.jpg\n\n\
+             Shared with: Public\n\n\
+             Comments\n--------\n\n\
+             Paul - 2013-09-06T01:00:00Z\n    Yum, agreed [2]\n\
+             \x20   Image: /posts/2013-09-05-b.jpg\n\
+             \x20   Image: https://lh3.googleusercontent.com/-x/AAuE7m=s64-c\n\n\
+             References:\n[1] http://vatra.example/\n[2] http://yum.example/\n"
+        );
+    }
+
+    #[test]
+    fn test_generate_plain_text_wraps_body() {
+        let post = PostData {
+            body: paragraph(vec![text(&"word ".repeat(30))]),
+            ..Default::default()
+        };
+        let output = generate_plain_text(&post, "");
+        assert!(output
+            .lines()
+            .all(|line| line.chars().count() <= WRAP_WIDTH));
+    }
+
+    #[test]
+    fn test_generate_plain_text_reshare() {
+        let post = PostData {
+            reshare_author: Some(String::from("Jake Gross")),
+            reshare_url: Some(String::from(
+                "https://plus.google.com/+JakeGross/posts/HgJTPoeCorQ",
+            )),
+            reshare_body: paragraph(vec![text("For the noobs.")]),
+            ..Default::default()
+        };
+        assert!(generate_plain_text(&post, "").contains(
+            "Originally shared by Jake Gross <https://plus.google.com/+JakeGross/posts/HgJTPoeCorQ>:\n\n\
+             For the noobs.\n"
+        ));
+    }
+}
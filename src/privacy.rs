@@ -0,0 +1,455 @@
+//! Keeping private posts and other people out of the output
+//!
+//! Posts shared only with circles, and the names of the people who
+//! commented, +1'd or were mentioned, were never meant for the whole web.
+//! With a `[privacy]` section in the settings file, a `Redactor` leaves out
+//! or redacts posts that weren't public and renames everyone except the
+//! author and the people listed as having agreed to be named.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use crate::config::{NameMode, NonPublicMode, PrivacyConfig};
+use crate::content::{Block, Inline, RichText};
+use crate::models::{PostData, Visibility};
+
+/// Shown in place of the content of redacted posts
+const REDACTED_NOTE: &str = "This post was shared with a limited audience and is not republished.";
+
+/// What became of a post
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Treatment {
+    /// Published, with other people renamed
+    Published,
+    /// Published without its content, see `redacted_file_name()`
+    Redacted,
+    /// Left out
+    Skipped,
+}
+
+/// Applies the privacy settings to posts
+pub struct Redactor {
+    non_public: NonPublicMode,
+    names: NameMode,
+    /// Lowercased names and profile URLs of people who may be named
+    allow: Vec<String>,
+    /// Numbers of the pseudonyms given out so far, by lowercased name
+    pseudonyms: HashMap<String, usize>,
+    /// Posts left out and redacted so far
+    pub skipped: usize,
+    pub redacted: usize,
+}
+
+impl Redactor {
+    pub fn new(config: &PrivacyConfig) -> Self {
+        Redactor {
+            non_public: config.non_public,
+            names: config.names,
+            allow: config
+                .allow
+                .iter()
+                .map(|person| person.trim().to_lowercase())
+                .collect(),
+            pseudonyms: HashMap::new(),
+            skipped: 0,
+            redacted: 0,
+        }
+    }
+
+    /// Redact a post, or tell it to be left out
+    pub fn apply(&mut self, post_data: &mut PostData) -> Treatment {
+        let mut treatment = Treatment::Published;
+        if !is_public(&post_data.visibility) {
+            match self.non_public {
+                NonPublicMode::Keep => {}
+                NonPublicMode::Skip => {
+                    self.skipped += 1;
+                    return Treatment::Skipped;
+                }
+                NonPublicMode::Redact => {
+                    redact_post(post_data);
+                    self.redacted += 1;
+                    treatment = Treatment::Redacted;
+                }
+            }
+        }
+
+        if self.names != NameMode::Keep {
+            self.rename_people(post_data);
+        }
+        treatment
+    }
+
+    /// Rename commenters, +1ers, reshared authors, mentioned people and
+    /// people the post was shared with and drop their profile links and
+    /// comment photos, except for the author and allowed people
+    fn rename_people(&mut self, post_data: &mut PostData) {
+        let owner = [
+            post_data.author.trim().to_lowercase(),
+            post_data.author_url.trim().to_lowercase(),
+        ];
+        let is_named = |allow: &[String], name: &str, url: &str| {
+            [name, url]
+                .iter()
+                .map(|person| person.trim().to_lowercase())
+                .any(|person| {
+                    !person.is_empty() && (owner.contains(&person) || allow.contains(&person))
+                })
+        };
+
+        // Photos in comments may show the people who posted them
+        for comment in &mut post_data.comments {
+            if !is_named(&self.allow, &comment.author, &comment.author_url) {
+                comment.author = self.rename(&comment.author);
+                comment.author_url.clear();
+                comment.images.clear();
+            }
+            self.rename_mentions(&mut comment.body, &owner);
+            comment
+                .mentions
+                .retain(|(url, name)| is_named(&self.allow, name, url));
+        }
+
+        if let Some(reshare_author) = &mut post_data.reshare_author {
+            if !is_named(&self.allow, reshare_author, "") {
+                *reshare_author = self.rename(reshare_author);
+                // The post URL names the author's profile
+                post_data.reshare_url = None;
+            }
+        }
+
+        let plus_ones = std::mem::take(&mut post_data.plus_ones);
+        post_data.plus_ones = plus_ones
+            .into_iter()
+            .map(|name| {
+                if is_named(&self.allow, &name, "") {
+                    name
+                } else {
+                    self.rename(&name)
+                }
+            })
+            .collect();
+
+        self.rename_mentions(&mut post_data.body, &owner);
+        self.rename_mentions(&mut post_data.reshare_body, &owner);
+        post_data
+            .mentions
+            .retain(|(url, name)| is_named(&self.allow, name, url));
+
+        // Circles have no profile, people the post was shared with do
+        for recipient in &mut post_data.shared_with {
+            let Some(url) = &recipient.url else {
+                continue;
+            };
+            if !is_named(&self.allow, &recipient.name, url) {
+                recipient.name = self.rename(&recipient.name);
+                recipient.url = None;
+            }
+        }
+    }
+
+    /// Turn +mentions of people who may not be named into plain text
+    fn rename_mentions(&mut self, body: &mut RichText, owner: &[String; 2]) {
+        body.replace_mentions(&mut |href, name| {
+            let named = [name, href]
+                .iter()
+                .map(|person| person.trim().to_lowercase())
+                .any(|person| owner.contains(&person) || self.allow.contains(&person));
+            (!named).then(|| {
+                vec![Inline::Text {
+                    text: format!("+{}", self.rename(name)),
+                }]
+            })
+        });
+    }
+
+    /// What `name` is shown as
+    fn rename(&mut self, name: &str) -> String {
+        match self.names {
+            NameMode::Keep => name.to_string(),
+            NameMode::Initials => initials(name),
+            NameMode::Pseudonyms => {
+                let next = self.pseudonyms.len() + 1;
+                let number = *self
+                    .pseudonyms
+                    .entry(name.trim().to_lowercase())
+                    .or_insert(next);
+                format!("Person {}", number)
+            }
+        }
+    }
+}
+
+/// Whether a post was shared with `Public`, possibly among others
+///
+/// Posts whose audience is unknown count as not public.
+pub fn is_public(visibility: &Visibility) -> bool {
+    *visibility == Visibility::Public
+}
+
+/// `Paul Fernandez` as `P. F.`, `Someone` for names without letters
+pub fn initials(name: &str) -> String {
+    let initials: Vec<String> = name
+        .split_whitespace()
+        .filter_map(|word| word.chars().find(|c| c.is_alphanumeric()))
+        .map(|initial| format!("{}.", initial.to_uppercase()))
+        .collect();
+    if initials.is_empty() {
+        String::from("Someone")
+    } else {
+        initials.join(" ")
+    }
+}
+
+/// Replace everything but the author, date, address and audience of a post
+/// with a note saying it isn't republished
+///
+/// Circles and people the post was shared with are dropped.
+fn redact_post(post_data: &mut PostData) {
+    *post_data = PostData {
+        title: String::from("Private post"),
+        visibility: std::mem::take(&mut post_data.visibility),
+        author: std::mem::take(&mut post_data.author),
+        author_url: std::mem::take(&mut post_data.author_url),
+        author_photo: post_data.author_photo.take(),
+        date: std::mem::take(&mut post_data.date),
+        canonical_url: std::mem::take(&mut post_data.canonical_url),
+        body: RichText {
+            blocks: vec![Block::Paragraph {
+                inlines: vec![Inline::Text {
+                    text: String::from(REDACTED_NOTE),
+                }],
+            }],
+        },
+        ..Default::default()
+    };
+}
+
+/// A file name for a redacted post that doesn't give its content away
+///
+/// Takeout names post files after the start of their text, like
+/// `20130228 - Unlocked Tux by playing TF2 in.html`.  The redacted post
+/// keeps the date and gets the ID from its address instead, so posts from
+/// the same day don't overwrite each other.
+pub fn redacted_file_name(path: &Path, canonical_url: &str) -> PathBuf {
+    let stem = path
+        .file_stem()
+        .and_then(|stem| stem.to_str())
+        .unwrap_or("");
+    let date = stem.split(" - ").next().unwrap_or(stem);
+    let id = canonical_url
+        .trim_end_matches('/')
+        .rsplit('/')
+        .next()
+        .filter(|id| !id.is_empty() && !id.contains(':'))
+        .unwrap_or("post");
+    path.with_file_name(format!("{} - Private {}.html", date, id))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{Comment, Image, Recipient};
+    use crate::test_helpers::{mention, paragraph, text};
+
+    fn post(visibility: Visibility) -> PostData {
+        PostData {
+            author: String::from("Christopher Hicks"),
+            author_url: String::from("https://plus.google.com/+ChristopherHicksFINI"),
+            date: String::from("2017-03-31T16:17:06Z"),
+            canonical_url: String::from(
+                "https://plus.google.com/+ChristopherHicksFINI/posts/YvptFVXgTct",
+            ),
+            visibility,
+            body: paragraph(vec![
+                text("Thanks "),
+                mention(
+                    "https://plus.google.com/100809291576677149722",
+                    "Anonymous Pro",
+                ),
+            ]),
+            images: vec![Image::new("../Photos/a.jpg")],
+            mentions: vec![(
+                String::from("https://plus.google.com/100809291576677149722"),
+                String::from("Anonymous Pro"),
+            )],
+            plus_ones: vec![String::from("David Swift"), String::from("Wil Schultz")],
+            comments: vec![
+                Comment {
+                    author: String::from("Anonymous Pro"),
+                    author_url: String::from("https://plus.google.com/100809291576677149722"),
+                    body: paragraph(vec![text("What is this nonsense?")]),
+                    ..Default::default()
+                },
+                Comment {
+                    author: String::from("Christopher Hicks"),
+                    author_url: String::from("https://plus.google.com/+ChristopherHicksFINI"),
+                    body: paragraph(vec![
+                        mention(
+                            "https://plus.google.com/100809291576677149722",
+                            "Anonymous Pro",
+                        ),
+                        text(" I replied on ServerFault."),
+                    ]),
+                    ..Default::default()
+                },
+            ],
+            ..Default::default()
+        }
+    }
+
+    fn anonymous_pro() -> Recipient {
+        Recipient {
+            name: String::from("Anonymous Pro"),
+            url: Some(String::from(
+                "https://plus.google.com/100809291576677149722",
+            )),
+        }
+    }
+
+    fn redactor(non_public: NonPublicMode, names: NameMode, allow: &[&str]) -> Redactor {
+        Redactor::new(&PrivacyConfig {
+            non_public,
+            names,
+            allow: allow.iter().map(|person| person.to_string()).collect(),
+        })
+    }
+
+    #[test]
+    fn test_is_public() {
+        assert!(is_public(&Visibility::Public));
+        assert!(!is_public(&Visibility::Circles));
+        assert!(!is_public(&Visibility::ExtendedCircles));
+        assert!(!is_public(&Visibility::Specific));
+        assert!(!is_public(&Visibility::Unknown));
+    }
+
+    #[test]
+    fn test_initials() {
+        assert_eq!(initials("Paul Fernandez"), "P. F.");
+        assert_eq!(initials("  wil  schultz "), "W. S.");
+        assert_eq!(initials("Ævar (Arnfjörð)"), "Æ. A.");
+        assert_eq!(initials("-- ?"), "Someone");
+    }
+
+    #[test]
+    fn test_non_public_posts() {
+        let mut skip = redactor(NonPublicMode::Skip, NameMode::Keep, &[]);
+        let mut private = post(Visibility::Circles);
+        assert_eq!(skip.apply(&mut private), Treatment::Skipped);
+        assert_eq!(
+            skip.apply(&mut post(Visibility::Public)),
+            Treatment::Published
+        );
+        assert_eq!(skip.skipped, 1);
+
+        let mut keep = redactor(NonPublicMode::Keep, NameMode::Keep, &[]);
+        assert_eq!(keep.apply(&mut private), Treatment::Published);
+        assert_eq!(private.comments.len(), 2);
+
+        let mut redact = redactor(NonPublicMode::Redact, NameMode::Keep, &[]);
+        private.shared_with = vec![anonymous_pro()];
+        assert_eq!(redact.apply(&mut private), Treatment::Redacted);
+        assert_eq!(private.title, "Private post");
+        assert_eq!(private.content(), REDACTED_NOTE);
+        assert_eq!(private.visibility, Visibility::Circles);
+        assert!(private.shared_with.is_empty());
+        assert_eq!(private.date, "2017-03-31T16:17:06Z");
+        assert!(private.images.is_empty());
+        assert!(private.comments.is_empty());
+        assert!(private.plus_ones.is_empty());
+    }
+
+    #[test]
+    fn test_initials_for_other_people() {
+        let mut redactor = redactor(NonPublicMode::Skip, NameMode::Initials, &[]);
+        let mut post = post(Visibility::Public);
+        post.shared_with = vec![
+            Recipient {
+                name: String::from("Friends"),
+                url: None,
+            },
+            anonymous_pro(),
+        ];
+        post.reshare_author = Some(String::from("Jake Gross"));
+        post.reshare_url = Some(String::from(
+            "https://plus.google.com/+JakeGross/posts/HgJTPoeCorQ",
+        ));
+        for comment in &mut post.comments {
+            comment.images = vec![String::from("../Photos/selfie.jpg")];
+        }
+        redactor.apply(&mut post);
+
+        assert_eq!(post.content(), "Thanks +A. P.");
+        assert!(post.mentions.is_empty());
+        assert_eq!(post.audience(), "Public, Friends, A. P.");
+        assert_eq!(post.shared_with[1].url, None);
+        assert_eq!(post.plus_ones, vec!["D. S.", "W. S."]);
+        assert_eq!(post.reshare_author.as_deref(), Some("J. G."));
+        assert_eq!(post.reshare_url, None);
+
+        let comment = &post.comments[0];
+        assert_eq!(comment.author, "A. P.");
+        assert_eq!(comment.author_url, "");
+        assert_eq!(comment.content(), "What is this nonsense?");
+        assert!(comment.images.is_empty());
+        // The author keeps their name and photos
+        let reply = &post.comments[1];
+        assert_eq!(reply.author, "Christopher Hicks");
+        assert_eq!(reply.images.len(), 1);
+        assert_eq!(reply.content(), "+A. P. I replied on ServerFault.");
+    }
+
+    #[test]
+    fn test_pseudonyms_are_stable() {
+        let mut redactor = redactor(NonPublicMode::Skip, NameMode::Pseudonyms, &[]);
+        let mut first = post(Visibility::Public);
+        redactor.apply(&mut first);
+        assert_eq!(first.comments[0].author, "Person 1");
+        assert_eq!(first.plus_ones, vec!["Person 2", "Person 3"]);
+
+        let mut second = post(Visibility::Public);
+        second.plus_ones = vec![String::from("Wil Schultz"), String::from("Paul Fernandez")];
+        redactor.apply(&mut second);
+        assert_eq!(second.plus_ones, vec!["Person 3", "Person 4"]);
+        assert_eq!(second.content(), "Thanks +Person 1");
+    }
+
+    #[test]
+    fn test_allowed_people() {
+        let mut redactor = redactor(
+            NonPublicMode::Skip,
+            NameMode::Initials,
+            &[
+                "https://plus.google.com/100809291576677149722",
+                "wil schultz",
+            ],
+        );
+        let mut post = post(Visibility::Public);
+        redactor.apply(&mut post);
+
+        assert_eq!(post.comments[0].author, "Anonymous Pro");
+        assert_eq!(
+            post.comments[0].author_url,
+            "https://plus.google.com/100809291576677149722"
+        );
+        assert_eq!(post.mentions.len(), 1);
+        assert!(post.content().starts_with("Thanks +[Anonymous Pro]("));
+        assert_eq!(post.plus_ones, vec!["D. S.", "Wil Schultz"]);
+    }
+
+    #[test]
+    fn test_redacted_file_name() {
+        assert_eq!(
+            redacted_file_name(
+                Path::new("Posts/20170331 - This is the future of computer security.html"),
+                "https://plus.google.com/+ChristopherHicksFINI/posts/YvptFVXgTct",
+            ),
+            PathBuf::from("Posts/20170331 - Private YvptFVXgTct.html")
+        );
+        assert_eq!(
+            redacted_file_name(Path::new("20170331 - Hi.html"), ""),
+            PathBuf::from("20170331 - Private post.html")
+        );
+    }
+}
@@ -0,0 +1,339 @@
+//! Offline expansion of short links
+//!
+//! Shorteners like goo.gl are shutting down, taking the targets of old
+//! links with them.  Rather than asking them, links are expanded from a
+//! map of short URL → long URL kept next to the archive, built once from
+//! the list of short links the archive contains.
+
+use std::collections::{BTreeSet, HashMap};
+use std::path::Path;
+
+use url::Url;
+
+use crate::links::unwrap_redirects;
+use crate::models::PostData;
+
+/// Hosts whose links are short links
+pub const SHORTENER_HOSTS: &[&str] = &[
+    "goo.gl",
+    "bit.ly",
+    "t.co",
+    "ow.ly",
+    "tinyurl.com",
+    "buff.ly",
+    "j.mp",
+    "is.gd",
+    "wapo.st",
+    "fb.me",
+    "lnkd.in",
+    "amzn.to",
+    "dlvr.it",
+    "tiny.cc",
+    "shar.es",
+];
+
+/// Map of short URLs to the long URLs they redirect to
+#[derive(Debug, Default)]
+pub struct ShortLinkMap {
+    /// Long URLs by `short_link_key()`
+    links: HashMap<String, String>,
+}
+
+impl ShortLinkMap {
+    /// Load a map from a `.json` or `.csv` file
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let source = std::fs::read_to_string(path).map_err(|why| why.to_string())?;
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some("json") => Self::from_json(&source),
+            _ => Self::from_csv(&source),
+        }
+    }
+
+    /// Parse a JSON object of `"short": "long"` pairs
+    pub fn from_json(source: &str) -> Result<Self, String> {
+        let pairs: HashMap<String, String> =
+            serde_json::from_str(source).map_err(|why| why.to_string())?;
+        Ok(Self::from_pairs(pairs))
+    }
+
+    /// Parse `short,long` lines
+    ///
+    /// Blank lines, `#` comments, the `short_url,long_url` header written
+    /// by `short_links_to_csv()` and rows without a long URL are skipped.
+    /// Fields may be double-quoted.
+    pub fn from_csv(source: &str) -> Result<Self, String> {
+        let mut pairs = Vec::new();
+        for (index, line) in source.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let fields = split_csv_line(line)
+                .ok_or_else(|| format!("line {}: unterminated quote", index + 1))?;
+            if let [short, long, ..] = fields.as_slice() {
+                if short != "short_url" {
+                    pairs.push((short.clone(), long.clone()));
+                }
+            }
+        }
+        Ok(Self::from_pairs(pairs))
+    }
+
+    fn from_pairs(pairs: impl IntoIterator<Item = (String, String)>) -> Self {
+        let links = pairs
+            .into_iter()
+            .filter(|(_, long)| !long.trim().is_empty())
+            .map(|(short, long)| (short_link_key(&short), long.trim().to_string()))
+            .collect();
+        ShortLinkMap { links }
+    }
+
+    /// Number of short links in the map
+    pub fn len(&self) -> usize {
+        self.links.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.links.is_empty()
+    }
+
+    /// The long URL for a short link
+    ///
+    /// Matching ignores the scheme, a `www.` prefix and a trailing slash,
+    /// but not the case of the path, which shorteners care about.
+    pub fn expand(&self, href: &str) -> Option<&str> {
+        self.links.get(&short_link_key(href)).map(String::as_str)
+    }
+}
+
+/// Is this a link on a known URL shortener?
+pub fn is_short_link(href: &str) -> bool {
+    match Url::parse(href) {
+        Ok(url) => {
+            let host = url.host_str().unwrap_or_default();
+            let host = host.strip_prefix("www.").unwrap_or(host);
+            SHORTENER_HOSTS.contains(&host) && url.path() != "/"
+        }
+        Err(_) => false,
+    }
+}
+
+/// Every short link in a post and its comments, in the order they appear
+pub fn post_short_links(post_data: &PostData) -> Vec<String> {
+    let mut hrefs: Vec<&str> = Vec::new();
+    hrefs.push(&post_data.author_url);
+    hrefs.extend(post_data.body.hrefs());
+    hrefs.extend(post_data.reshare_body.hrefs());
+    hrefs.extend(post_data.video_url.as_deref());
+    hrefs.extend(post_data.links.iter().map(|(url, _)| url.as_str()));
+    for comment in &post_data.comments {
+        hrefs.extend(comment.body.hrefs());
+        hrefs.extend(comment.links.iter().map(|(url, _)| url.as_str()));
+    }
+
+    // Short links are often wrapped in a Google redirector
+    let mut short_links: Vec<String> = Vec::new();
+    for href in hrefs.into_iter().map(unwrap_redirects) {
+        if is_short_link(&href) && !short_links.contains(&href) {
+            short_links.push(href);
+        }
+    }
+    short_links
+}
+
+/// List short links as a CSV map to fill in
+///
+/// Links already in `map` get their long URL, the rest are left blank.
+pub fn short_links_to_csv(short_links: &BTreeSet<String>, map: &ShortLinkMap) -> String {
+    let mut csv = String::from("short_url,long_url\n");
+    for short in short_links {
+        csv.push_str(&csv_field(short));
+        csv.push(',');
+        csv.push_str(&csv_field(map.expand(short).unwrap_or_default()));
+        csv.push('\n');
+    }
+    csv
+}
+
+/// Key short links are matched on: host without `www.`, path and query
+fn short_link_key(href: &str) -> String {
+    let href = href.trim();
+    match Url::parse(href) {
+        Ok(url) => {
+            let host = url.host_str().unwrap_or_default();
+            let host = host.strip_prefix("www.").unwrap_or(host);
+            let mut key = format!("{}{}", host, url.path().trim_end_matches('/'));
+            if let Some(query) = url.query() {
+                key.push('?');
+                key.push_str(query);
+            }
+            key
+        }
+        Err(_) => href.to_string(),
+    }
+}
+
+/// Split a CSV line into fields, `None` if a quote is left open
+fn split_csv_line(line: &str) -> Option<Vec<String>> {
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let mut chars = line.chars().peekable();
+    let mut quoted = false;
+
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                field.push('"');
+                chars.next();
+            }
+            '"' if quoted => quoted = false,
+            '"' if field.trim().is_empty() => {
+                field.clear();
+                quoted = true;
+            }
+            ',' if !quoted => fields.push(std::mem::take(&mut field).trim().to_string()),
+            _ => field.push(c),
+        }
+    }
+    if quoted {
+        return None;
+    }
+    fields.push(field.trim().to_string());
+    Some(fields)
+}
+
+/// Quote a CSV field when it needs it
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::content::Inline;
+    use crate::models::Comment;
+    use crate::test_helpers::paragraph;
+
+    #[test]
+    fn test_is_short_link() {
+        assert!(is_short_link("http://goo.gl/maps/luJXU"));
+        assert!(is_short_link("https://bit.ly/abc"));
+        assert!(is_short_link("https://t.co/xyz"));
+        assert!(is_short_link("http://www.tinyurl.com/abc"));
+        assert!(!is_short_link("http://goo.gl/"));
+        assert!(!is_short_link("http://example.com/goo.gl"));
+        assert!(!is_short_link("not a url"));
+    }
+
+    #[test]
+    fn test_from_csv() {
+        let map = ShortLinkMap::from_csv(
+            "short_url,long_url\n\
+             # filled in by hand\n\
+             http://goo.gl/maps/luJXU,https://www.google.com/maps/place/Vatra\n\
+             \n\
+             \"https://bit.ly/a\",\"https://example.com/?a=1,2\"\n\
+             https://t.co/unknown,\n",
+        )
+        .unwrap();
+        assert_eq!(map.len(), 2);
+        assert_eq!(
+            map.expand("http://goo.gl/maps/luJXU"),
+            Some("https://www.google.com/maps/place/Vatra")
+        );
+        assert_eq!(
+            map.expand("https://bit.ly/a"),
+            Some("https://example.com/?a=1,2")
+        );
+        assert_eq!(map.expand("https://t.co/unknown"), None);
+    }
+
+    #[test]
+    fn test_from_csv_unterminated_quote() {
+        assert_eq!(
+            ShortLinkMap::from_csv("http://goo.gl/a,\"http://example.com\n").unwrap_err(),
+            "line 1: unterminated quote"
+        );
+    }
+
+    #[test]
+    fn test_from_json() {
+        let map =
+            ShortLinkMap::from_json(r#"{"http://goo.gl/news/Tb5W2": "https://example.com/news"}"#)
+                .unwrap();
+        assert_eq!(
+            map.expand("http://goo.gl/news/Tb5W2"),
+            Some("https://example.com/news")
+        );
+        assert!(ShortLinkMap::from_json("[1, 2]").is_err());
+    }
+
+    #[test]
+    fn test_expand_ignores_scheme_www_and_trailing_slash() {
+        let map = ShortLinkMap::from_csv("http://goo.gl/AbC,https://example.com/\n").unwrap();
+        assert_eq!(
+            map.expand("https://goo.gl/AbC/"),
+            Some("https://example.com/")
+        );
+        assert_eq!(
+            map.expand("https://www.goo.gl/AbC"),
+            Some("https://example.com/")
+        );
+        assert_eq!(map.expand("https://goo.gl/abc"), None);
+    }
+
+    #[test]
+    fn test_post_short_links() {
+        let link = |href: &str| Inline::Link {
+            href: href.to_string(),
+            text: href.to_string(),
+        };
+        let post = PostData {
+            body: paragraph(vec![
+                link("http://goo.gl/maps/luJXU"),
+                link("http://example.com/"),
+                link("https://www.google.com/url?q=http%3A%2F%2Fbit.ly%2F2mtZzzw&sa=D"),
+            ]),
+            links: vec![(String::from("http://goo.gl/maps/luJXU"), String::new())],
+            comments: vec![Comment {
+                links: vec![(String::from("https://t.co/x"), String::new())],
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        assert_eq!(
+            post_short_links(&post),
+            vec![
+                "http://goo.gl/maps/luJXU",
+                "http://bit.ly/2mtZzzw",
+                "https://t.co/x"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_short_links_to_csv() {
+        let map = ShortLinkMap::from_csv("https://t.co/x,\"https://example.com/a,b\"\n").unwrap();
+        let short_links = BTreeSet::from([
+            String::from("https://t.co/x"),
+            String::from("http://goo.gl/maps/luJXU"),
+        ]);
+        assert_eq!(
+            short_links_to_csv(&short_links, &map),
+            "short_url,long_url\n\
+             http://goo.gl/maps/luJXU,\n\
+             https://t.co/x,\"https://example.com/a,b\"\n"
+        );
+
+        // The listing loads back as a map
+        let reloaded = ShortLinkMap::from_csv(&short_links_to_csv(&short_links, &map)).unwrap();
+        assert_eq!(
+            reloaded.expand("https://t.co/x"),
+            Some("https://example.com/a,b")
+        );
+    }
+}
@@ -0,0 +1,113 @@
+//! Local HTTP server for tests
+//!
+//! Answers every request from a handler function, so code that talks to
+//! the network can be tested without it.
+
+use std::io::{BufRead, BufReader, Write};
+use std::net::TcpListener;
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+/// Response sent back for a request
+pub struct StubResponse {
+    pub status: u16,
+    pub headers: Vec<(&'static str, String)>,
+    pub body: String,
+}
+
+impl StubResponse {
+    /// `200 OK` with a body
+    pub fn ok(body: &str) -> Self {
+        StubResponse {
+            status: 200,
+            headers: Vec::new(),
+            body: body.to_string(),
+        }
+    }
+
+    /// An empty response with a status code
+    pub fn status(status: u16) -> Self {
+        StubResponse {
+            status,
+            headers: Vec::new(),
+            body: String::new(),
+        }
+    }
+
+    /// `301 Moved Permanently` to `location`
+    pub fn redirect(location: &str) -> Self {
+        StubResponse {
+            status: 301,
+            headers: vec![("Location", location.to_string())],
+            body: String::new(),
+        }
+    }
+}
+
+/// A running stub server
+pub struct StubServer {
+    /// `http://127.0.0.1:<port>` without a trailing slash
+    pub url: String,
+    requests: Arc<Mutex<Vec<String>>>,
+}
+
+impl StubServer {
+    /// Start a server on a free port, answering requests with `handler`
+    ///
+    /// The handler gets the request target, e.g. `/path?query`.  The server
+    /// runs until the test process exits.
+    pub fn start(handler: impl Fn(&str) -> StubResponse + Send + Sync + 'static) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("bind stub server");
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let handler = Arc::new(handler);
+
+        let seen = Arc::clone(&requests);
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let handler = Arc::clone(&handler);
+                let seen = Arc::clone(&seen);
+                thread::spawn(move || {
+                    let mut reader = BufReader::new(&stream);
+                    let mut request_line = String::new();
+                    if reader.read_line(&mut request_line).is_err() {
+                        return;
+                    }
+                    // Skip the headers, requests have no body
+                    let mut line = String::new();
+                    while reader.read_line(&mut line).is_ok_and(|read| read > 2) {
+                        line.clear();
+                    }
+
+                    let target = request_line
+                        .split_whitespace()
+                        .nth(1)
+                        .unwrap_or("/")
+                        .to_string();
+                    seen.lock().unwrap().push(target.clone());
+                    let response = handler(&target);
+
+                    let mut head = format!(
+                        "HTTP/1.1 {} Stub\r\nContent-Length: {}\r\nConnection: close\r\n",
+                        response.status,
+                        response.body.len()
+                    );
+                    for (name, value) in &response.headers {
+                        head.push_str(&format!("{}: {}\r\n", name, value));
+                    }
+                    head.push_str("\r\n");
+                    let mut stream = &stream;
+                    let _ = stream.write_all(head.as_bytes());
+                    let _ = stream.write_all(response.body.as_bytes());
+                });
+            }
+        });
+
+        StubServer { url, requests }
+    }
+
+    /// Request targets received so far, in order
+    pub fn requests(&self) -> Vec<String> {
+        self.requests.lock().unwrap().clone()
+    }
+}
@@ -0,0 +1,351 @@
+//! User-defined output templates
+//!
+//! Templates use [MiniJinja](https://docs.rs/minijinja) syntax and receive the
+//! whole `PostData` as `post`, so layouts can be changed without touching
+//! `markdown.rs`.  See `examples/default.md` for a template that reproduces
+//! the built-in layout.
+//!
+//! Variables available to a template:
+//!
+//! - `post` - every `PostData` field (`post.comments`, `post.images`, ...)
+//! - `title` - cleaned title, falling back to the start of the content
+//! - `description` - first 150 characters of the content
+//! - `date_prefix` - the YYYY-MM-DD prefix of the output filename
+//! - `video` - the post's video: `provider`, `id`, `url`, `watch_url` and
+//!   the Hugo `shortcode` when there is one
+//! - `cover` - the cover `image` path and its `alt` text, both none for
+//!   posts without images, and whether it is `hidden`
+//! - `audience` - the "Shared with" line, empty when unknown; the kind of
+//!   audience alone is `post.visibility.kind`, like `extended_circles`
+//!
+//! Extra filters:
+//!
+//! - `toml` - escape a value for use inside a TOML basic string
+//! - `image_path` - rewrite an image source to its `/posts/YYYY-MM-DD-name`
+//!   path, keeping the URL of a remote image
+//! - `figure` - a Hugo `figure` shortcode for one of `post.images`
+//! - `picture` - `<picture>` HTML for one of `post.images` with resized
+//!   `variants`, taking an optional `sizes` attribute value
+
+use minijinja::value::ViaDeserialize;
+use minijinja::{context, Environment, Error, State};
+
+use crate::config::{CoverConfig, DEFAULT_SIZES};
+use crate::cover::post_cover;
+use crate::markdown::{
+    figure_shortcode, picture_html, post_description, post_title, published_image_path,
+};
+use crate::models::{Image, PostData};
+use crate::utils::escape_toml_string;
+use crate::video::Video;
+
+const TEMPLATE_NAME: &str = "post";
+
+/// A compiled output template
+pub struct PostTemplate {
+    env: Environment<'static>,
+    cover: CoverConfig,
+}
+
+impl PostTemplate {
+    /// Compile a template from its source text
+    ///
+    /// Syntax errors are reported here rather than once per post.
+    pub fn new(source: String) -> Result<Self, Error> {
+        let mut env = Environment::new();
+        env.set_trim_blocks(true);
+        env.set_lstrip_blocks(true);
+        env.set_keep_trailing_newline(true);
+        env.add_filter("toml", toml_filter);
+        env.add_filter("image_path", image_path_filter);
+        env.add_filter("figure", figure_filter);
+        env.add_filter("picture", picture_filter);
+        env.add_template_owned(TEMPLATE_NAME, source)?;
+
+        Ok(PostTemplate {
+            env,
+            cover: CoverConfig::default(),
+        })
+    }
+
+    /// Pick and show cover images as `cover` says instead of the defaults
+    pub fn with_cover(mut self, cover: CoverConfig) -> Self {
+        self.cover = cover;
+        self
+    }
+
+    /// Render a post through the template
+    ///
+    /// Trailing whitespace is collapsed to a single newline, matching
+    /// `generate_markdown`.
+    ///
+    /// # Arguments
+    /// * `post_data` - The post to expose as `post`
+    /// * `date_prefix` - The date prefix (YYYY-MM-DD) for image paths
+    pub fn render(&self, post_data: &PostData, date_prefix: &str) -> Result<String, Error> {
+        let template = self.env.get_template(TEMPLATE_NAME)?;
+        let video = post_data.video_url.as_deref().map(|video_url| {
+            let video = Video::detect(video_url);
+            context! {
+                watch_url => video.watch_url(date_prefix),
+                shortcode => video.hugo_shortcode(),
+                provider => video.provider,
+                id => video.id,
+                url => video.url,
+            }
+        });
+        let cover = post_cover(post_data, date_prefix, self.cover.pick);
+        let rendered = template.render(context! {
+            post => post_data,
+            video => video,
+            cover => context! {
+                image => cover.as_ref().map(|cover| cover.image.as_str()),
+                alt => cover.as_ref().map(|cover| cover.alt.as_str()),
+                hidden => self.cover.hidden,
+            },
+            title => post_title(post_data),
+            description => post_description(post_data),
+            date_prefix => date_prefix,
+            audience => post_data.audience(),
+        })?;
+
+        Ok(format!("{}\n", rendered.trim_end()))
+    }
+}
+
+fn toml_filter(value: String) -> String {
+    escape_toml_string(&value)
+}
+
+fn image_path_filter(state: &State, image_path: String) -> String {
+    published_image_path(&image_path, &date_prefix(state))
+}
+
+fn figure_filter(state: &State, image: ViaDeserialize<Image>) -> String {
+    figure_shortcode(&image, &date_prefix(state))
+}
+
+fn picture_filter(state: &State, image: ViaDeserialize<Image>, sizes: Option<String>) -> String {
+    picture_html(
+        &image,
+        &date_prefix(state),
+        sizes.as_deref().unwrap_or(DEFAULT_SIZES),
+    )
+}
+
+/// The `date_prefix` the template was rendered with
+fn date_prefix(state: &State) -> String {
+    state
+        .lookup("date_prefix")
+        .and_then(|value| value.as_str().map(str::to_string))
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{Config, CoverPick};
+    use crate::markdown::generate_markdown;
+    use crate::models::{Comment, ImageInfo, ImageVariant, VariantFormat};
+    use crate::test_helpers::text_body;
+
+    fn sample_post() -> PostData {
+        PostData {
+            author: String::from("Christopher Hicks"),
+            date: String::from("2012-11-01T22:04:01Z"),
+            title: String::from("Penn &amp; Teller rock!"),
+            body: text_body("Penn & Teller rock!"),
+            images: vec![Image::new("../Photos/Photos%20from%20posts/x/a.jpg")],
+            links: vec![(String::from("http://example.com"), String::new())],
+            comments: vec![Comment {
+                author: String::from("Paul"),
+                author_url: String::from("https://plus.google.com/+PaulFernandez"),
+                date: String::from("2012-11-02T01:00:00Z"),
+                body: text_body("Nice"),
+                images: vec![
+                    String::from("../Photos/c.jpg"),
+                    String::from("https://lh3.googleusercontent.com/-x/AAuE7m=s64-c"),
+                ],
+                links: vec![(
+                    String::from("http://example.com/recipe"),
+                    String::from("Recipe"),
+                )],
+                ..Default::default()
+            }],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_render_placeholders() {
+        let template = PostTemplate::new(String::from(
+            "{{ title }}|{{ post.date }}|{{ post.content }}",
+        ))
+        .unwrap();
+        assert_eq!(
+            template.render(&sample_post(), "2012-11-01").unwrap(),
+            "Penn & Teller rock!|2012-11-01T22:04:01Z|Penn & Teller rock!\n"
+        );
+    }
+
+    #[test]
+    fn test_render_loops() {
+        let source = "{% for comment in post.comments %}{{ comment.author }}: {{ comment.content }}\n{% endfor %}\
+                      {% for url, title in post.links %}{{ title or url }}\n{% endfor %}";
+        let template = PostTemplate::new(String::from(source)).unwrap();
+        assert_eq!(
+            template.render(&sample_post(), "2012-11-01").unwrap(),
+            "Paul: Nice\nhttp://example.com\n"
+        );
+    }
+
+    #[test]
+    fn test_render_conditionals() {
+        let source = "{% if post.location %}at {{ post.location }}{% else %}nowhere{% endif %}";
+        let template = PostTemplate::new(String::from(source)).unwrap();
+        let mut post = sample_post();
+        assert_eq!(template.render(&post, "").unwrap(), "nowhere\n");
+        post.location = Some(String::from("Oakland"));
+        assert_eq!(template.render(&post, "").unwrap(), "at Oakland\n");
+    }
+
+    #[test]
+    fn test_image_path_filter() {
+        let source = "{% for image in post.images %}{{ image.src | image_path }}{% endfor %}";
+        let template = PostTemplate::new(String::from(source)).unwrap();
+        assert_eq!(
+            template.render(&sample_post(), "2012-11-01").unwrap(),
+            "/posts/2012-11-01-a.jpg\n"
+        );
+    }
+
+    #[test]
+    fn test_render_video() {
+        let source = "{% if video %}{{ video.provider }} {{ video.shortcode or video.watch_url }}{% endif %}";
+        let template = PostTemplate::new(String::from(source)).unwrap();
+        let mut post = sample_post();
+        assert_eq!(template.render(&post, "2012-11-01").unwrap(), "\n");
+        post.video_url = Some(String::from("http://youtu.be/u6XAPnuFjJc"));
+        assert_eq!(
+            template.render(&post, "2012-11-01").unwrap(),
+            "you_tube {{< youtube u6XAPnuFjJc >}}\n"
+        );
+        post.video_url = Some(String::from("../Photos/Photos%20from%20posts/x/b.mp4"));
+        assert_eq!(
+            template.render(&post, "2012-11-01").unwrap(),
+            "local /posts/2012-11-01-b.mp4\n"
+        );
+    }
+
+    #[test]
+    fn test_figure_filter() {
+        let source = "{% for image in post.images %}{{ image | figure }}{% endfor %}";
+        let template = PostTemplate::new(String::from(source)).unwrap();
+        let mut post = sample_post();
+        post.images[0].caption = Some(String::from("Penn & \"Teller\""));
+        assert_eq!(
+            template.render(&post, "2012-11-01").unwrap(),
+            "{{< figure src=\"/posts/2012-11-01-a.jpg\" alt=\"Penn & \\\"Teller\\\"\" caption=\"Penn & \\\"Teller\\\"\" >}}\n"
+        );
+
+        post.images[0].info = Some(ImageInfo {
+            width: Some(640),
+            height: Some(480),
+            ..Default::default()
+        });
+        assert!(template
+            .render(&post, "2012-11-01")
+            .unwrap()
+            .contains(" width=\"640\" height=\"480\" caption="));
+    }
+
+    #[test]
+    fn test_picture_filter() {
+        let source = "{% for image in post.images %}{{ image | picture('50vw') }}{% endfor %}";
+        let template = PostTemplate::new(String::from(source)).unwrap();
+        let mut post = sample_post();
+        post.images[0].variants = vec![ImageVariant {
+            src: String::from("/posts/2012-11-01-a-480w.webp"),
+            width: 480,
+            height: 320,
+            format: VariantFormat::Webp,
+        }];
+        assert_eq!(
+            template.render(&post, "2012-11-01").unwrap(),
+            "<figure>\n<picture>\n\
+             <source type=\"image/webp\" srcset=\"/posts/2012-11-01-a-480w.webp 480w\" sizes=\"50vw\">\n\
+             <img src=\"/posts/2012-11-01-a.jpg\" alt=\"\" width=\"480\" height=\"320\" loading=\"lazy\">\n\
+             </picture>\n</figure>\n"
+        );
+    }
+
+    #[test]
+    fn test_toml_filter() {
+        let template = PostTemplate::new(String::from("\"{{ post.content | toml }}\"")).unwrap();
+        let post = PostData {
+            body: text_body("He said \"hi\"\nbye"),
+            ..Default::default()
+        };
+        assert_eq!(
+            template.render(&post, "").unwrap(),
+            "\"He said \\\"hi\\\" bye\"\n"
+        );
+    }
+
+    #[test]
+    fn test_syntax_error_reported_on_compile() {
+        assert!(PostTemplate::new(String::from("{% if %}")).is_err());
+    }
+
+    #[test]
+    fn test_default_template_matches_built_in() {
+        let template = PostTemplate::new(include_str!("../examples/default.md").to_string())
+            .unwrap()
+            .with_cover(CoverConfig {
+                pick: CoverPick::Largest,
+                hidden: false,
+            });
+        let mut post = sample_post();
+        post.images.push(Image {
+            alt: String::from("Tulips"),
+            caption: Some(String::from("Tulips at the show")),
+            info: Some(ImageInfo {
+                width: Some(2048),
+                height: Some(1536),
+                ..Default::default()
+            }),
+            album: Some(0),
+            ..Image::new("../Photos/b.jpg")
+        });
+        post.images.push(Image {
+            caption: Some(String::from("Pier")),
+            variants: vec![ImageVariant {
+                src: String::from("/posts/2012-11-01-c-480w.jpg"),
+                width: 480,
+                height: 360,
+                format: VariantFormat::Jpeg,
+            }],
+            album: Some(1),
+            ..Image::new("../Photos/c.jpg")
+        });
+        post.link_preview = Some(Image::new("http://example.com/preview.jpg"));
+        post.reshare_author = Some(String::from("Jake Gross"));
+        post.reshare_url = Some(String::from(
+            "https://plus.google.com/+JakeGross/posts/HgJTPoeCorQ",
+        ));
+        post.reshare_body = text_body("For the noobs.");
+        post.location = Some(String::from("34.00940, -118.49730"));
+        post.location_inferred = true;
+
+        let config = Config {
+            cover: CoverConfig {
+                pick: CoverPick::Largest,
+                hidden: false,
+            },
+            ..Default::default()
+        };
+        let rendered = template.render(&post, "2012-11-01").unwrap();
+        assert!(rendered.contains("### Album 2\n\n<figure>\n<picture>"));
+        assert_eq!(rendered, generate_markdown(&post, "2012-11-01", &config));
+    }
+}
@@ -0,0 +1,59 @@
+//! Fixtures shared by the unit tests
+
+use std::path::PathBuf;
+
+use crate::content::{Block, Inline, RichText};
+
+/// An empty scratch directory for one test, named after `name`
+///
+/// The process id keeps parallel test runs apart; whatever a previous run
+/// left behind is removed first.
+pub fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("gpd-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// Plain text inline
+pub fn text(s: &str) -> Inline {
+    Inline::Text {
+        text: s.to_string(),
+    }
+}
+
+/// Link inline
+pub fn link(href: &str, s: &str) -> Inline {
+    Inline::Link {
+        href: href.to_string(),
+        text: s.to_string(),
+    }
+}
+
+/// Mention inline for a Google+ profile
+pub fn mention(href: &str, name: &str) -> Inline {
+    Inline::Mention {
+        href: href.to_string(),
+        name: name.to_string(),
+    }
+}
+
+/// A body of one paragraph
+pub fn paragraph(inlines: Vec<Inline>) -> RichText {
+    paragraphs(vec![inlines])
+}
+
+/// A body of one paragraph per entry
+pub fn paragraphs(paragraphs: Vec<Vec<Inline>>) -> RichText {
+    RichText {
+        blocks: paragraphs
+            .into_iter()
+            .map(|inlines| Block::Paragraph { inlines })
+            .collect(),
+    }
+}
+
+/// A body of one paragraph of plain text
+pub fn text_body(s: &str) -> RichText {
+    paragraph(vec![text(s)])
+}
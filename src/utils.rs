@@ -2,6 +2,7 @@
 
 use chrono::{DateTime, Utc};
 use html_escape::decode_html_entities;
+use serde::Serialize;
 
 /// Escape double quotes and backslashes for TOML basic string values
 /// Also replaces newlines with spaces to ensure single-line TOML strings
@@ -11,6 +12,115 @@ pub fn escape_toml_string(s: &str) -> String {
         .replace(['\n', '\r'], " ")
 }
 
+/// Serialize data written to a JSON file, pretty-printed and ending in a
+/// newline
+///
+/// Everything written this way is plain strings, numbers and string-keyed
+/// maps, which always serialize.
+pub fn to_pretty_json<T: Serialize + ?Sized>(value: &T) -> String {
+    let mut json =
+        serde_json::to_string_pretty(value).expect("Output data is plain and always serializes");
+    json.push('\n');
+    json
+}
+
+/// Escape text so Markdown renders it literally
+/// Inline syntax (emphasis, code, links, HTML, entities) is escaped everywhere;
+/// block syntax (headings, quotes, lists, rules) only at the start of a line.
+/// `at_line_start` says whether the text begins a line of the output.
+pub fn escape_markdown(text: &str, at_line_start: bool) -> String {
+    text.split('\n')
+        .enumerate()
+        .map(|(index, line)| {
+            let escaped = escape_markdown_inline(line);
+            if index > 0 || at_line_start {
+                escape_markdown_block_start(&escaped)
+            } else {
+                escaped
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Escape inline Markdown syntax, for text that never starts a line such as
+/// link text
+pub fn escape_markdown_inline(text: &str) -> String {
+    let chars: Vec<char> = text.chars().collect();
+    let mut escaped = String::with_capacity(text.len());
+
+    for (index, &c) in chars.iter().enumerate() {
+        let needs_escape = match c {
+            '\\' | '`' | '*' | '~' | '[' | ']' | '<' => true,
+            // snake_case words cannot open or close emphasis
+            '_' => {
+                let before = index.checked_sub(1).map(|i| chars[i]);
+                let after = chars.get(index + 1).copied();
+                !(before.is_some_and(char::is_alphanumeric)
+                    && after.is_some_and(char::is_alphanumeric))
+            }
+            // Only something shaped like an entity reference gets decoded
+            '&' => {
+                let rest: String = chars[index + 1..].iter().take(33).collect();
+                rest.split_once(';').is_some_and(|(name, _)| {
+                    !name.is_empty() && name.chars().all(|c| c.is_alphanumeric() || c == '#')
+                })
+            }
+            _ => false,
+        };
+        if needs_escape {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+
+    escaped
+}
+
+/// Escape a line's leading characters that would make it a heading, quote,
+/// list item or thematic break rather than paragraph text
+pub fn escape_markdown_block_start(line: &str) -> String {
+    let content = line.trim_start_matches([' ', '\t']);
+    let indent = &line[..line.len() - content.len()];
+    let first = match content.chars().next() {
+        Some(first) => first,
+        None => return line.to_string(),
+    };
+    let rest = &content[first.len_utf8()..];
+    let ends_marker = |s: &str| s.is_empty() || s.starts_with([' ', '\t']);
+
+    let escape_first = match first {
+        '#' => {
+            ends_marker(content.trim_start_matches('#'))
+                && content.len() - content.trim_start_matches('#').len() <= 6
+        }
+        '>' => true,
+        '+' => ends_marker(rest),
+        // Setext underlines and thematic breaks as well as list items
+        '-' | '=' => {
+            ends_marker(rest) || content.chars().all(|c| c == first || c == ' ' || c == '\t')
+        }
+        _ => false,
+    };
+    if escape_first {
+        return format!("{}\\{}", indent, content);
+    }
+
+    // Ordered list items: up to nine digits then '.' or ')'
+    let digits = content.len()
+        - content
+            .trim_start_matches(|c: char| c.is_ascii_digit())
+            .len();
+    if (1..=9).contains(&digits) {
+        let after_digits = &content[digits..];
+        if after_digits.starts_with(['.', ')']) && ends_marker(&after_digits[1..]) {
+            return format!("{}{}\\{}", indent, &content[..digits], after_digits);
+        }
+    }
+
+    line.to_string()
+}
+
 /// Clean up title text by decoding HTML entities and stripping HTML tags
 /// This handles double-encoded entities from Google+ Takeout HTML
 pub fn clean_title(title: &str) -> String {
@@ -107,9 +217,68 @@ pub fn format_filename_date(filename: &str) -> String {
     }
 }
 
+/// Turn a name into a lowercase directory name, like `rust-programming`
+/// for `Rust Programming!`
+/// Runs of anything but letters and digits become a single dash
+pub fn slugify(name: &str) -> String {
+    let mut slug = String::new();
+    for c in name.chars().flat_map(char::to_lowercase) {
+        if c.is_alphanumeric() {
+            slug.push(c);
+        } else if !slug.is_empty() && !slug.ends_with('-') {
+            slug.push('-');
+        }
+    }
+    slug.trim_end_matches('-').to_string()
+}
+
+/// Turn a file name into the path Hugo serves it from, like Hugo's `urlize`
+/// Letters, digits and `._-~#+@/\` are kept and lowercased, runs of
+/// whitespace become a single dash and anything else is dropped
+pub fn urlize(name: &str) -> String {
+    let mut path = String::new();
+    let mut was_space = false;
+    for c in name.chars() {
+        if c.is_alphanumeric() || "._-~#+@/\\".contains(c) {
+            if was_space {
+                path.push('-');
+                was_space = false;
+            }
+            path.extend(c.to_lowercase());
+        } else if c.is_whitespace() {
+            was_space = true;
+        }
+    }
+    path
+}
+
+/// Word-wrap text to a maximum line width in characters
+/// Existing line breaks are kept, runs of spaces collapse to one,
+/// and words longer than the width get a line of their own
+pub fn wrap_text(text: &str, width: usize) -> String {
+    let mut wrapped = Vec::new();
+
+    for line in text.lines() {
+        let mut current = String::new();
+        for word in line.split_whitespace() {
+            if !current.is_empty() && current.chars().count() + 1 + word.chars().count() > width {
+                wrapped.push(std::mem::take(&mut current));
+            }
+            if !current.is_empty() {
+                current.push(' ');
+            }
+            current.push_str(word);
+        }
+        wrapped.push(current);
+    }
+
+    wrapped.join("\n")
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use pulldown_cmark::{Event, Options, Parser, Tag, TagEnd};
 
     // Tests for escape_toml_string()
     #[test]
@@ -360,6 +529,33 @@ mod tests {
         );
     }
 
+    // Tests for slugify()
+    #[test]
+    fn test_slugify() {
+        assert_eq!(slugify("Rust Programming!"), "rust-programming");
+        assert_eq!(slugify("  C++ & Qt -- Tips "), "c-qt-tips");
+        assert_eq!(slugify("Ærø Kayaking"), "ærø-kayaking");
+        assert_eq!(slugify("!!!"), "");
+    }
+
+    // Tests for to_pretty_json()
+    #[test]
+    fn test_to_pretty_json() {
+        assert_eq!(to_pretty_json(&["a", "b"]), "[\n  \"a\",\n  \"b\"\n]\n");
+    }
+
+    // Tests for urlize()
+    #[test]
+    fn test_urlize() {
+        assert_eq!(
+            urlize("2014-03-12-Penn _ Teller rock!"),
+            "2014-03-12-penn-_-teller-rock"
+        );
+        assert_eq!(urlize("Today_is_my_first_day"), "today_is_my_first_day");
+        assert_eq!(urlize("C++  &  Qt"), "c++-qt");
+        assert_eq!(urlize("rust-programming/Ærø"), "rust-programming/ærø");
+    }
+
     // Tests for clean_title()
     #[test]
     fn test_clean_title_simple() {
@@ -502,6 +698,43 @@ mod tests {
         assert_eq!(clean_title("Test&#3"), "Test&#3");
     }
 
+    // Tests for wrap_text()
+    #[test]
+    fn test_wrap_text_short_line() {
+        assert_eq!(wrap_text("Hello world", 72), "Hello world");
+    }
+
+    #[test]
+    fn test_wrap_text_wraps_at_width() {
+        assert_eq!(
+            wrap_text("one two three four five", 9),
+            "one two\nthree\nfour five"
+        );
+    }
+
+    #[test]
+    fn test_wrap_text_keeps_line_breaks() {
+        assert_eq!(wrap_text("one\n\ntwo", 72), "one\n\ntwo");
+    }
+
+    #[test]
+    fn test_wrap_text_collapses_spaces() {
+        assert_eq!(wrap_text("Props.  Enjoy.", 72), "Props. Enjoy.");
+    }
+
+    #[test]
+    fn test_wrap_text_long_word() {
+        assert_eq!(
+            wrap_text("see http://example.com/a/very/long/path ok", 10),
+            "see\nhttp://example.com/a/very/long/path\nok"
+        );
+    }
+
+    #[test]
+    fn test_wrap_text_unicode_width() {
+        assert_eq!(wrap_text("世界 世界 世界", 5), "世界 世界\n世界");
+    }
+
     // Tests for clean_location()
     #[test]
     fn test_clean_location_with_missing_space() {
@@ -545,4 +778,92 @@ mod tests {
             "Home Address: 123, WorkAddress: 456"
         );
     }
+
+    // Tests for escape_markdown()
+    /// Render Markdown and return its text, failing on any markup other
+    /// than paragraphs
+    fn rendered_text(markdown: &str) -> String {
+        let mut text = String::new();
+        for event in Parser::new_ext(markdown, Options::ENABLE_STRIKETHROUGH) {
+            match event {
+                Event::Text(t) => text.push_str(&t),
+                Event::SoftBreak => text.push('\n'),
+                Event::Start(Tag::Paragraph) | Event::End(TagEnd::Paragraph) => {}
+                other => panic!("{:?} in {:?}", other, markdown),
+            }
+        }
+        text
+    }
+
+    fn assert_round_trip(text: &str) {
+        let escaped = escape_markdown(text, true);
+        assert_eq!(rendered_text(&escaped), text, "escaped as {:?}", escaped);
+    }
+
+    #[test]
+    fn test_escape_markdown_round_trip_inline() {
+        for text in [
+            "2 * 3 * 4",
+            "*not emphasis*",
+            "_not emphasis_ either",
+            "__still not__",
+            "~~not struck~~",
+            "`not code`",
+            "[not a link](http://example.com)",
+            "![not an image](a.jpg)",
+            "<b>not html</b>",
+            "<http://example.com> is not an autolink",
+            "&copy; &#169; &amp; stay literal",
+            r"back\slash \* and \\",
+            "snake_case_words are fine",
+        ] {
+            assert_round_trip(text);
+        }
+    }
+
+    #[test]
+    fn test_escape_markdown_round_trip_block_start() {
+        for text in [
+            "# not a heading",
+            "###### not a heading",
+            "> not a quote",
+            "- not a list",
+            "+ not a list",
+            "* not a list",
+            "1. not a list",
+            "2) not a list",
+            "not a heading\n===",
+            "not a heading\n---",
+            "***",
+            "_ _ _",
+            "```\nnot code\n```",
+            "~~~",
+            "first line\n# second line",
+        ] {
+            assert_round_trip(text);
+        }
+    }
+
+    #[test]
+    fn test_escape_markdown_leaves_plain_text() {
+        let text = "Hello, world! It's 5 o'clock (really) - #hashtag 1.5 + 2 = 3.5";
+        assert_eq!(escape_markdown(text, true), text);
+        assert_eq!(escape_markdown("snake_case", true), "snake_case");
+    }
+
+    #[test]
+    fn test_escape_markdown_block_start_only_at_line_start() {
+        assert_eq!(escape_markdown("# one", false), "# one");
+        assert_eq!(escape_markdown("# one", true), r"\# one");
+        assert_eq!(escape_markdown("a\n1. b", false), "a\n1\\. b");
+    }
+
+    #[test]
+    fn test_escape_markdown_inline_keeps_hashtags() {
+        assert_eq!(
+            escape_markdown_inline("#ignorance_is_bliss"),
+            "#ignorance_is_bliss"
+        );
+        assert_eq!(escape_markdown_inline("[x]"), r"\[x\]");
+    }
 }
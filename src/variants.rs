@@ -0,0 +1,435 @@
+//! Resized copies of photos
+//!
+//! Takeout photos are often several megabytes.  When the settings file has
+//! an `[images]` section, photos copied with `--copy-media` also get copies
+//! at a few smaller widths, so the output can offer them in a `srcset` and
+//! let browsers download the size they need.
+
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::Path;
+
+use image::codecs::jpeg::JpegEncoder;
+use image::codecs::webp::WebPEncoder;
+use image::imageops::FilterType;
+use image::metadata::Orientation;
+use image::{DynamicImage, ImageDecoder, ImageEncoder, ImageReader};
+
+use crate::config::{ExifMode, ImagesConfig};
+use crate::markdown::transform_image_path;
+use crate::models::{ImageVariant, VariantFormat};
+
+/// Extensions of photos that are resized, animated GIFs would lose their
+/// animation
+const RESIZED_EXTENSIONS: &[&str] = &["jpg", "jpeg", "png", "webp"];
+
+/// Make resized copies of the photo at `source` in `dest_dir`
+///
+/// Copies are named `<date_prefix>-<name>-<width>w.<extension>` next to
+/// the copy of the photo itself and returned smallest first with their
+/// site paths.  Photos narrower than a configured width get one copy at
+/// their own width instead, so EXIF data can still be stripped.  The
+/// photo is turned upright according to its EXIF orientation first.
+/// Files that aren't photos, like videos, return no copies.
+pub fn make_variants(
+    source: &Path,
+    date_prefix: &str,
+    dest_dir: &Path,
+    config: &ImagesConfig,
+) -> Result<Vec<ImageVariant>, String> {
+    let extension = source
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .map(str::to_ascii_lowercase);
+    let stem = source.file_stem().and_then(|stem| stem.to_str());
+    let (Some(extension), Some(stem)) = (extension, stem) else {
+        return Ok(Vec::new());
+    };
+    if !RESIZED_EXTENSIONS.contains(&extension.as_str()) {
+        return Ok(Vec::new());
+    }
+
+    let (photo, exif) = read_upright(source)
+        .map_err(|why| format!("couldn't read {}: {}", source.display(), why))?;
+    let exif = match config.exif {
+        ExifMode::Strip => None,
+        ExifMode::Preserve => exif,
+    };
+
+    let mut variants = Vec::new();
+    for width in variant_widths(photo.width(), &config.widths) {
+        let height = scaled_height(photo.width(), photo.height(), width);
+        let resized = photo.resize_exact(width, height, FilterType::Lanczos3);
+        for &format in &config.formats {
+            let file_name = format!("{}-{}w.{}", stem, width, format.extension());
+            let target = dest_dir.join(format!("{}-{}", date_prefix, file_name));
+            write_variant(&resized, &target, format, config.quality, exif.as_deref())
+                .map_err(|why| format!("couldn't write {}: {}", target.display(), why))?;
+            variants.push(ImageVariant {
+                src: transform_image_path(&file_name, date_prefix),
+                width,
+                height,
+                format,
+            });
+        }
+    }
+    Ok(variants)
+}
+
+/// Widths to make copies at for a photo `original` pixels wide
+///
+/// The configured widths, smallest first, with widths larger than the
+/// photo brought down to `original`.
+fn variant_widths(original: u32, widths: &[u32]) -> Vec<u32> {
+    let mut result: Vec<u32> = widths
+        .iter()
+        .copied()
+        .filter(|&width| width > 0)
+        .map(|width| width.min(original))
+        .collect();
+    result.sort_unstable();
+    result.dedup();
+    result
+}
+
+/// Height keeping the aspect ratio when scaling to `width`, at least 1
+fn scaled_height(original_width: u32, original_height: u32, width: u32) -> u32 {
+    let height = u64::from(original_height) * u64::from(width) / u64::from(original_width.max(1));
+    u32::try_from(height).unwrap_or(u32::MAX).max(1)
+}
+
+/// Decode a photo, turned upright, along with its EXIF data
+///
+/// The EXIF orientation is reset, since the pixels are already upright.
+fn read_upright(source: &Path) -> Result<(DynamicImage, Option<Vec<u8>>), image::ImageError> {
+    let mut decoder = ImageReader::open(source)?
+        .with_guessed_format()?
+        .into_decoder()?;
+    let mut exif = decoder.exif_metadata()?;
+    let orientation = decoder.orientation()?;
+    let mut photo = DynamicImage::from_decoder(decoder)?;
+
+    photo.apply_orientation(orientation);
+    if let Some(exif) = &mut exif {
+        let _ = Orientation::remove_from_exif_chunk(exif);
+    }
+    Ok((photo, exif))
+}
+
+/// Encode one copy, with `exif` attached if given
+fn write_variant(
+    photo: &DynamicImage,
+    target: &Path,
+    format: VariantFormat,
+    quality: u8,
+    exif: Option<&[u8]>,
+) -> Result<(), String> {
+    let writer = BufWriter::new(File::create(target).map_err(|why| why.to_string())?);
+    let quality = quality.clamp(1, 100);
+    let result = match format {
+        VariantFormat::Jpeg => {
+            let photo = DynamicImage::ImageRgb8(photo.to_rgb8());
+            let mut encoder = JpegEncoder::new_with_quality(writer, quality);
+            if let Some(exif) = exif {
+                encoder
+                    .set_exif_metadata(exif.to_vec())
+                    .map_err(|why| why.to_string())?;
+            }
+            photo.write_with_encoder(encoder)
+        }
+        VariantFormat::Webp => {
+            let photo = if photo.color().has_alpha() {
+                DynamicImage::ImageRgba8(photo.to_rgba8())
+            } else {
+                DynamicImage::ImageRgb8(photo.to_rgb8())
+            };
+            let mut encoder = WebPEncoder::new_lossless(writer);
+            if let Some(exif) = exif {
+                encoder
+                    .set_exif_metadata(exif.to_vec())
+                    .map_err(|why| why.to_string())?;
+            }
+            photo.write_with_encoder(encoder)
+        }
+    };
+    result.map_err(|why| why.to_string())
+}
+
+/// Remove EXIF and XMP data from a JPEG, PNG or WebP file in place
+///
+/// The image data is copied as it is, so nothing is re-encoded.  Returns
+/// whether anything was removed; other files are left alone.
+pub fn strip_exif(path: &Path) -> Result<bool, String> {
+    let data =
+        std::fs::read(path).map_err(|why| format!("couldn't read {}: {}", path.display(), why))?;
+    let stripped = if data.starts_with(&[0xff, 0xd8]) {
+        strip_jpeg_exif(&data)
+    } else if data.starts_with(b"\x89PNG\r\n\x1a\n") {
+        strip_png_exif(&data)
+    } else if data.len() >= 12 && data.starts_with(b"RIFF") && &data[8..12] == b"WEBP" {
+        strip_webp_exif(&data)
+    } else {
+        None
+    };
+    match stripped {
+        Some(stripped) if stripped.len() != data.len() => {
+            std::fs::write(path, stripped)
+                .map_err(|why| format!("couldn't write {}: {}", path.display(), why))?;
+            Ok(true)
+        }
+        _ => Ok(false),
+    }
+}
+
+/// A JPEG without its APP1 segments, which hold EXIF and XMP data
+fn strip_jpeg_exif(data: &[u8]) -> Option<Vec<u8>> {
+    let mut stripped = data[..2].to_vec();
+    let mut at = 2;
+    while at + 4 <= data.len() {
+        if data[at] != 0xff {
+            return None;
+        }
+        let marker = data[at + 1];
+        // Entropy-coded data follows the start of scan, copy the rest as is
+        if marker == 0xda {
+            break;
+        }
+        if marker == 0xff {
+            at += 1;
+            continue;
+        }
+        if matches!(marker, 0x01 | 0xd0..=0xd7) {
+            stripped.extend_from_slice(&data[at..at + 2]);
+            at += 2;
+            continue;
+        }
+        let end = at + 2 + usize::from(u16::from_be_bytes([data[at + 2], data[at + 3]]));
+        if end > data.len() {
+            return None;
+        }
+        if marker != 0xe1 {
+            stripped.extend_from_slice(&data[at..end]);
+        }
+        at = end;
+    }
+    stripped.extend_from_slice(&data[at..]);
+    Some(stripped)
+}
+
+/// A PNG without its `eXIf` chunk and XMP text
+fn strip_png_exif(data: &[u8]) -> Option<Vec<u8>> {
+    let mut stripped = data[..8].to_vec();
+    let mut at = 8;
+    while at + 12 <= data.len() {
+        let length = u32::from_be_bytes(data[at..at + 4].try_into().ok()?) as usize;
+        let end = at
+            .checked_add(12 + length)
+            .filter(|&end| end <= data.len())?;
+        let kind = &data[at + 4..at + 8];
+        let is_xmp = kind == b"iTXt" && data[at + 8..end].starts_with(b"XML:com.adobe.xmp\0");
+        if kind != b"eXIf" && !is_xmp {
+            stripped.extend_from_slice(&data[at..end]);
+        }
+        at = end;
+    }
+    stripped.extend_from_slice(&data[at..]);
+    Some(stripped)
+}
+
+/// A WebP without its `EXIF` and `XMP ` chunks, and without the flags
+/// announcing them
+fn strip_webp_exif(data: &[u8]) -> Option<Vec<u8>> {
+    let mut stripped = data[..12].to_vec();
+    let mut at = 12;
+    while at + 8 <= data.len() {
+        let size = u32::from_le_bytes(data[at + 4..at + 8].try_into().ok()?) as usize;
+        if at + 8 + size > data.len() {
+            return None;
+        }
+        // Chunks are padded to an even size
+        let end = (at + 8 + size + size % 2).min(data.len());
+        match &data[at..at + 4] {
+            b"EXIF" | b"XMP " => {}
+            b"VP8X" if size > 0 => {
+                let flags = stripped.len() + 8;
+                stripped.extend_from_slice(&data[at..end]);
+                stripped[flags] &= !0x0c;
+            }
+            _ => stripped.extend_from_slice(&data[at..end]),
+        }
+        at = end;
+    }
+    let riff_size = u32::try_from(stripped.len() - 8).ok()?;
+    stripped[4..8].copy_from_slice(&riff_size.to_le_bytes());
+    Some(stripped)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_helpers::temp_dir;
+    use image::codecs::png::PngEncoder;
+
+    /// A JPEG of `width` x `height` pixels with `exif` attached
+    fn write_jpeg(path: &Path, width: u32, height: u32, exif: Option<Vec<u8>>) {
+        let photo = DynamicImage::new_rgb8(width, height);
+        let mut encoder = JpegEncoder::new(File::create(path).unwrap());
+        if let Some(exif) = exif {
+            encoder.set_exif_metadata(exif).unwrap();
+        }
+        photo.write_with_encoder(encoder).unwrap();
+    }
+
+    /// Big-endian TIFF EXIF data with just an orientation tag
+    fn orientation_exif(orientation: u8) -> Vec<u8> {
+        // Header with the first IFD at offset 8
+        let mut exif = b"MM\x00\x2a\x00\x00\x00\x08".to_vec();
+        // One entry: Orientation, a SHORT
+        exif.extend([0, 1, 0x01, 0x12, 0, 3, 0, 0, 0, 1, 0, orientation, 0, 0]);
+        // No next IFD
+        exif.extend([0, 0, 0, 0]);
+        exif
+    }
+
+    fn exif_of(path: &Path) -> Option<Vec<u8>> {
+        let mut decoder = ImageReader::open(path)
+            .unwrap()
+            .with_guessed_format()
+            .unwrap()
+            .into_decoder()
+            .unwrap();
+        decoder.exif_metadata().unwrap()
+    }
+
+    #[test]
+    fn test_variant_widths() {
+        assert_eq!(
+            variant_widths(2048, &[1600, 480, 960]),
+            vec![480, 960, 1600]
+        );
+        assert_eq!(variant_widths(800, &[480, 960, 1600]), vec![480, 800]);
+        assert_eq!(variant_widths(300, &[480, 960]), vec![300]);
+        assert_eq!(variant_widths(300, &[0]), Vec::<u32>::new());
+    }
+
+    #[test]
+    fn test_scaled_height() {
+        assert_eq!(scaled_height(2048, 1536, 480), 360);
+        assert_eq!(scaled_height(4000, 1, 480), 1);
+    }
+
+    #[test]
+    fn test_make_variants() {
+        let dir = temp_dir("variants-make");
+        let source = dir.join("pier.jpg");
+        write_jpeg(&source, 64, 48, None);
+        let config = ImagesConfig {
+            widths: vec![32, 100],
+            formats: vec![VariantFormat::Webp, VariantFormat::Jpeg],
+            ..Default::default()
+        };
+
+        let variants = make_variants(&source, "2012-05-26", &dir, &config).unwrap();
+
+        let described: Vec<(&str, u32, u32)> = variants
+            .iter()
+            .map(|variant| (variant.src.as_str(), variant.width, variant.height))
+            .collect();
+        assert_eq!(
+            described,
+            vec![
+                ("/posts/2012-05-26-pier-32w.webp", 32, 24),
+                ("/posts/2012-05-26-pier-32w.jpg", 32, 24),
+                ("/posts/2012-05-26-pier-64w.webp", 64, 48),
+                ("/posts/2012-05-26-pier-64w.jpg", 64, 48),
+            ]
+        );
+        assert_eq!(
+            image::image_dimensions(dir.join("2012-05-26-pier-32w.webp")).unwrap(),
+            (32, 24)
+        );
+        assert_eq!(
+            image::image_dimensions(dir.join("2012-05-26-pier-64w.jpg")).unwrap(),
+            (64, 48)
+        );
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_make_variants_exif() {
+        let dir = temp_dir("variants-exif");
+        let source = dir.join("side.jpg");
+        // Orientation 6: stored sideways, shown rotated 90° clockwise
+        write_jpeg(&source, 40, 20, Some(orientation_exif(6)));
+
+        let stripped = ImagesConfig {
+            widths: vec![10],
+            ..Default::default()
+        };
+        let variants = make_variants(&source, "2012-05-26", &dir, &stripped).unwrap();
+        assert_eq!((variants[0].width, variants[0].height), (10, 20));
+        assert_eq!(exif_of(&dir.join("2012-05-26-side-10w.jpg")), None);
+
+        let preserved = ImagesConfig {
+            widths: vec![10],
+            exif: ExifMode::Preserve,
+            ..Default::default()
+        };
+        make_variants(&source, "2012-05-26", &dir, &preserved).unwrap();
+        let exif = exif_of(&dir.join("2012-05-26-side-10w.jpg")).unwrap();
+        assert_eq!(
+            Orientation::from_exif_chunk(&exif),
+            Some(Orientation::NoTransforms)
+        );
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_strip_exif() {
+        let dir = temp_dir("variants-strip");
+        let photo = DynamicImage::new_rgb8(8, 6);
+
+        let jpeg = dir.join("gps.jpg");
+        write_jpeg(&jpeg, 8, 6, Some(orientation_exif(1)));
+        let png = dir.join("gps.png");
+        let mut encoder = PngEncoder::new(File::create(&png).unwrap());
+        encoder.set_exif_metadata(orientation_exif(1)).unwrap();
+        photo.write_with_encoder(encoder).unwrap();
+        let webp = dir.join("gps.webp");
+        let mut encoder = WebPEncoder::new_lossless(File::create(&webp).unwrap());
+        encoder.set_exif_metadata(orientation_exif(1)).unwrap();
+        photo.write_with_encoder(encoder).unwrap();
+
+        for path in [&jpeg, &png, &webp] {
+            assert!(exif_of(path).is_some(), "{}", path.display());
+            assert_eq!(strip_exif(path), Ok(true), "{}", path.display());
+            assert_eq!(exif_of(path), None, "{}", path.display());
+            assert_eq!(image::image_dimensions(path).unwrap(), (8, 6));
+            image::open(path).unwrap();
+            assert_eq!(strip_exif(path), Ok(false), "{}", path.display());
+        }
+
+        let clip = dir.join("clip.mp4");
+        std::fs::write(&clip, b"not a photo").unwrap();
+        assert_eq!(strip_exif(&clip), Ok(false));
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_make_variants_skips_other_files() {
+        let dir = temp_dir("variants-skip");
+        let config = ImagesConfig::default();
+        assert_eq!(
+            make_variants(&dir.join("clip.mp4"), "", &dir, &config),
+            Ok(Vec::new())
+        );
+        assert!(make_variants(&dir.join("missing.jpg"), "", &dir, &config)
+            .unwrap_err()
+            .contains("missing.jpg"));
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,251 @@
+//! Video provider detection
+//!
+//! Posts link their video either on a hosting site or, for videos uploaded
+//! to Google+, as a file in the Takeout.  Knowing the provider and video ID
+//! lets YouTube and Vimeo videos be embedded with Hugo's built-in
+//! shortcodes instead of a bare link.
+
+use serde::Serialize;
+
+use crate::links::parse_link;
+use crate::markdown::transform_image_path;
+
+/// Where a video is hosted
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum VideoProvider {
+    YouTube,
+    Vimeo,
+    GooglePhotos,
+    /// A video file in the Takeout, next to the photos
+    Local,
+    /// Any other site, linked as it is
+    Other,
+}
+
+/// A post's video
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Video {
+    pub provider: VideoProvider,
+    /// Video ID for the provider, empty for `Other`, the file name for
+    /// `Local`
+    pub id: String,
+    /// The link as found in the post
+    pub url: String,
+}
+
+impl Video {
+    /// Work out the provider and video ID of a video link
+    pub fn detect(url: &str) -> Self {
+        let (provider, id) = match parse_link(url) {
+            Some(parsed) => {
+                let host = parsed.host_str().unwrap_or_default();
+                let host = host.strip_prefix("www.").unwrap_or(host);
+                if let Some(id) = youtube_id(&parsed) {
+                    (VideoProvider::YouTube, id)
+                } else if host == "vimeo.com" || host.ends_with(".vimeo.com") {
+                    match last_segment(&parsed).filter(|id| id.chars().all(|c| c.is_ascii_digit()))
+                    {
+                        Some(id) => (VideoProvider::Vimeo, id),
+                        None => (VideoProvider::Other, String::new()),
+                    }
+                } else if host == "photos.google.com" || host == "photos.app.goo.gl" {
+                    (
+                        VideoProvider::GooglePhotos,
+                        last_segment(&parsed).unwrap_or_default(),
+                    )
+                } else {
+                    (VideoProvider::Other, String::new())
+                }
+            }
+            None => {
+                let file_name = url.rsplit(['/', '\\']).next().unwrap_or(url);
+                (VideoProvider::Local, file_name.to_string())
+            }
+        };
+
+        Video {
+            provider,
+            id,
+            url: url.to_string(),
+        }
+    }
+
+    /// Where to watch the video
+    ///
+    /// YouTube and Vimeo links are rebuilt from the ID, which also drops
+    /// `attribution_link` wrappers, and local files get their
+    /// `/posts/YYYY-MM-DD-name` path like photos.
+    pub fn watch_url(&self, date_prefix: &str) -> String {
+        match self.provider {
+            VideoProvider::YouTube => format!("https://www.youtube.com/watch?v={}", self.id),
+            VideoProvider::Vimeo => format!("https://vimeo.com/{}", self.id),
+            VideoProvider::Local => transform_image_path(&self.url, date_prefix),
+            VideoProvider::GooglePhotos | VideoProvider::Other => self.url.clone(),
+        }
+    }
+
+    /// The Hugo shortcode embedding the video, for providers Hugo has one for
+    pub fn hugo_shortcode(&self) -> Option<String> {
+        match self.provider {
+            VideoProvider::YouTube => Some(format!("{{{{< youtube {} >}}}}", self.id)),
+            VideoProvider::Vimeo => Some(format!("{{{{< vimeo {} >}}}}", self.id)),
+            _ => None,
+        }
+    }
+}
+
+/// The ID of a YouTube video link
+///
+/// Handles `watch?v=`, `youtu.be/`, `/embed/`, `/v/` and `/shorts/` links
+/// and the `attribution_link?u=/watch?v=...` form Google+ shares used.
+fn youtube_id(url: &url::Url) -> Option<String> {
+    let host = url.host_str()?;
+    let host = host.strip_prefix("www.").unwrap_or(host);
+    let id = if host == "youtu.be" {
+        last_segment(url)?
+    } else if host == "youtube.com" || host.ends_with(".youtube.com") {
+        let query = |name: &str| {
+            url.query_pairs()
+                .find(|(key, _)| key == name)
+                .map(|(_, value)| value.into_owned())
+        };
+        let segments: Vec<&str> = url.path_segments()?.filter(|s| !s.is_empty()).collect();
+        match segments.as_slice() {
+            ["watch"] => query("v")?,
+            ["embed" | "v" | "shorts", id] => id.to_string(),
+            ["attribution_link"] => {
+                let target = url.join(&query("u")?).ok()?;
+                return youtube_id(&target);
+            }
+            _ => return None,
+        }
+    } else {
+        return None;
+    };
+
+    let is_id = id.len() == 11
+        && id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+    is_id.then_some(id)
+}
+
+/// Last non-empty path segment of a URL
+fn last_segment(url: &url::Url) -> Option<String> {
+    url.path_segments()?
+        .rfind(|segment| !segment.is_empty())
+        .map(str::to_string)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn detect(url: &str) -> (VideoProvider, String) {
+        let video = Video::detect(url);
+        (video.provider, video.id)
+    }
+
+    #[test]
+    fn test_detect_youtube() {
+        for url in [
+            "http://www.youtube.com/watch?v=u6XAPnuFjJc",
+            "https://m.youtube.com/watch?feature=share&v=u6XAPnuFjJc",
+            "http://youtu.be/u6XAPnuFjJc",
+            "https://www.youtube.com/embed/u6XAPnuFjJc?rel=0",
+            "//www.youtube.com/v/u6XAPnuFjJc",
+        ] {
+            assert_eq!(
+                detect(url),
+                (VideoProvider::YouTube, String::from("u6XAPnuFjJc")),
+                "{}",
+                url
+            );
+        }
+    }
+
+    #[test]
+    fn test_detect_youtube_attribution_link() {
+        assert_eq!(
+            detect("http://www.youtube.com/attribution_link?a=SkONkkL9vW0DggmHN0A_VA&u=/watch?v%3DdcrY59nGxBg%26feature%3Dshare"),
+            (VideoProvider::YouTube, String::from("dcrY59nGxBg"))
+        );
+    }
+
+    #[test]
+    fn test_detect_youtube_needs_video_id() {
+        assert_eq!(
+            detect("http://www.youtube.com/TheYouTubeCollection").0,
+            VideoProvider::Other
+        );
+        assert_eq!(
+            detect("http://www.youtube.com/watch?v=short").0,
+            VideoProvider::Other
+        );
+    }
+
+    #[test]
+    fn test_detect_vimeo() {
+        assert_eq!(
+            detect("http://www.vimeo.com/18539129"),
+            (VideoProvider::Vimeo, String::from("18539129"))
+        );
+        assert_eq!(
+            detect("https://player.vimeo.com/video/9581676"),
+            (VideoProvider::Vimeo, String::from("9581676"))
+        );
+        assert_eq!(
+            detect("http://vimeo.com/keith/flights"),
+            (VideoProvider::Other, String::new())
+        );
+    }
+
+    #[test]
+    fn test_detect_google_photos() {
+        assert_eq!(
+            detect("https://photos.google.com/share/AF1QipN/photo/AF1QipM?key=abc"),
+            (VideoProvider::GooglePhotos, String::from("AF1QipM"))
+        );
+        assert_eq!(
+            detect("https://photos.app.goo.gl/Xy12ab"),
+            (VideoProvider::GooglePhotos, String::from("Xy12ab"))
+        );
+    }
+
+    #[test]
+    fn test_detect_local() {
+        let video = Video::detect("../Photos/Photos%20from%20posts/2013-03-10/18h0nir4n94ya.mp4");
+        assert_eq!(video.provider, VideoProvider::Local);
+        assert_eq!(video.id, "18h0nir4n94ya.mp4");
+        assert_eq!(
+            video.watch_url("2013-03-09"),
+            "/posts/2013-03-09-18h0nir4n94ya.mp4"
+        );
+        assert_eq!(video.hugo_shortcode(), None);
+    }
+
+    #[test]
+    fn test_watch_url_and_shortcode() {
+        let youtube = Video::detect("https://www.youtube.com/attribution_link?a=y67c50Nr_Ik&u=/watch?v%3Dcv8msHbqaFE%26feature%3Dshare");
+        assert_eq!(
+            youtube.watch_url(""),
+            "https://www.youtube.com/watch?v=cv8msHbqaFE"
+        );
+        assert_eq!(
+            youtube.hugo_shortcode().as_deref(),
+            Some("{{< youtube cv8msHbqaFE >}}")
+        );
+
+        let vimeo = Video::detect("http://www.vimeo.com/18539129");
+        assert_eq!(vimeo.watch_url(""), "https://vimeo.com/18539129");
+        assert_eq!(
+            vimeo.hugo_shortcode().as_deref(),
+            Some("{{< vimeo 18539129 >}}")
+        );
+
+        let other = Video::detect("http://vimeo.com/keith/flights");
+        assert_eq!(other.watch_url(""), "http://vimeo.com/keith/flights");
+        assert_eq!(other.hugo_shortcode(), None);
+    }
+}
@@ -0,0 +1,412 @@
+//! Wayback Machine fallback links
+//!
+//! Much of what old posts link to is gone.  Each external link can get an
+//! archived copy at `web.archive.org/web/<YYYYMMDD>/<url>`, dated by the
+//! post or comment so the Wayback Machine picks the snapshot closest to
+//! when the link was shared.  Building those URLs needs no network; the
+//! optional availability check asks an endpoint compatible with
+//! <https://archive.org/wayback/available> whether a snapshot exists.
+
+use std::collections::HashMap;
+use std::time::Duration;
+
+use serde::Deserialize;
+
+use crate::content::Inline;
+use crate::models::PostData;
+
+/// Where archived copies are served from
+pub const WAYBACK_PREFIX: &str = "https://web.archive.org/web/";
+
+/// The Wayback Machine availability API
+pub const DEFAULT_AVAILABILITY_ENDPOINT: &str = "https://archive.org/wayback/available";
+
+/// Text of the companion link added after the original
+const COMPANION_TEXT: &str = "archived";
+
+/// How archived copies are added to a post
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WaybackMode {
+    /// Keep the original link and add an archived link after it
+    Companion,
+    /// Point the link at the archived copy, keeping its text
+    Replace,
+}
+
+/// Adds Wayback Machine links to posts
+pub struct Wayback {
+    mode: WaybackMode,
+    /// Availability API to check snapshots against, if checking
+    endpoint: Option<String>,
+    agent: ureq::Agent,
+    /// Archived URLs already looked up, by `(url, timestamp)`
+    checked: HashMap<(String, String), Option<String>>,
+}
+
+/// Availability API response
+#[derive(Deserialize)]
+struct Availability {
+    #[serde(default)]
+    archived_snapshots: ArchivedSnapshots,
+}
+
+#[derive(Default, Deserialize)]
+struct ArchivedSnapshots {
+    closest: Option<Snapshot>,
+}
+
+#[derive(Deserialize)]
+struct Snapshot {
+    #[serde(default)]
+    available: bool,
+    url: String,
+}
+
+impl Wayback {
+    /// Generate archive links offline, without checking them
+    pub fn new(mode: WaybackMode) -> Self {
+        Wayback {
+            mode,
+            endpoint: None,
+            agent: ureq::AgentBuilder::new()
+                .timeout(Duration::from_secs(30))
+                .build(),
+            checked: HashMap::new(),
+        }
+    }
+
+    /// Only add links to snapshots the availability API knows about
+    pub fn with_availability_check(mut self, endpoint: &str) -> Self {
+        self.endpoint = Some(endpoint.to_string());
+        self
+    }
+
+    /// The archived copy of `url` closest to `date`
+    ///
+    /// Without an availability check this is always the generated
+    /// `wayback_url()`.  With one it is the snapshot the API returns, or
+    /// `None` when there is no snapshot.  If the API can't be reached the
+    /// generated URL is used and a warning printed.
+    pub fn archived_url(&mut self, url: &str, date: &str) -> Option<String> {
+        let endpoint = match &self.endpoint {
+            Some(endpoint) => endpoint.clone(),
+            None => return Some(wayback_url(url, date)),
+        };
+
+        let timestamp = wayback_timestamp(date).unwrap_or_default();
+        let key = (url.to_string(), timestamp.clone());
+        if let Some(archived) = self.checked.get(&key) {
+            return archived.clone();
+        }
+
+        let archived = match self.check(&endpoint, url, &timestamp) {
+            Ok(archived) => archived,
+            Err(why) => {
+                eprintln!("Warning: couldn't check archive for {}: {}", url, why);
+                Some(wayback_url(url, date))
+            }
+        };
+        self.checked.insert(key, archived.clone());
+        archived
+    }
+
+    /// Ask the availability API for the closest snapshot
+    fn check(&self, endpoint: &str, url: &str, timestamp: &str) -> Result<Option<String>, String> {
+        let mut request = self.agent.get(endpoint).query("url", url);
+        if !timestamp.is_empty() {
+            request = request.query("timestamp", timestamp);
+        }
+        let availability: Availability = request
+            .call()
+            .map_err(|why| why.to_string())?
+            .into_json()
+            .map_err(|why| why.to_string())?;
+
+        Ok(availability
+            .archived_snapshots
+            .closest
+            .filter(|snapshot| snapshot.available)
+            .map(|snapshot| snapshot.url))
+    }
+
+    /// Add archived links to every external link in a post
+    ///
+    /// Covers links in the post, reshare and comment bodies and the
+    /// embedded links lists.  Comment links are dated by the comment.
+    pub fn add_archive_links(&mut self, post_data: &mut PostData) {
+        let post_date = post_data.date.clone();
+
+        let mut bodies = vec![(&mut post_data.body, post_date.clone())];
+        bodies.push((&mut post_data.reshare_body, post_date.clone()));
+        for comment in &mut post_data.comments {
+            let date = if comment.date.is_empty() {
+                post_date.clone()
+            } else {
+                comment.date.clone()
+            };
+            bodies.push((&mut comment.body, date));
+        }
+        for (body, date) in bodies {
+            body.replace_links(&mut |href, text| {
+                if !is_external_link(href) {
+                    return None;
+                }
+                let archived = self.archived_url(href, &date)?;
+                Some(self.link_inlines(href, text, archived))
+            });
+        }
+
+        post_data.links = self.archive_link_list(std::mem::take(&mut post_data.links), &post_date);
+        for comment in &mut post_data.comments {
+            let date = if comment.date.is_empty() {
+                &post_date
+            } else {
+                &comment.date
+            };
+            comment.links = self.archive_link_list(std::mem::take(&mut comment.links), date);
+        }
+    }
+
+    /// Inline elements replacing a link
+    fn link_inlines(&self, href: &str, text: &str, archived: String) -> Vec<Inline> {
+        match self.mode {
+            WaybackMode::Companion => vec![
+                Inline::Link {
+                    href: href.to_string(),
+                    text: text.to_string(),
+                },
+                Inline::Text {
+                    text: String::from(" ("),
+                },
+                Inline::Link {
+                    href: archived,
+                    text: String::from(COMPANION_TEXT),
+                },
+                Inline::Text {
+                    text: String::from(")"),
+                },
+            ],
+            WaybackMode::Replace => vec![Inline::Link {
+                href: archived,
+                text: text.to_string(),
+            }],
+        }
+    }
+
+    /// Add archived copies to a `(url, title)` links list
+    fn archive_link_list(
+        &mut self,
+        links: Vec<(String, String)>,
+        date: &str,
+    ) -> Vec<(String, String)> {
+        let mut archived_links = Vec::new();
+        for (url, title) in links {
+            let archived = if is_external_link(&url) {
+                self.archived_url(&url, date)
+            } else {
+                None
+            };
+            match (archived, self.mode) {
+                (Some(archived), WaybackMode::Companion) => {
+                    let label = if title.is_empty() { &url } else { &title };
+                    let archived_title = format!("{} ({})", label, COMPANION_TEXT);
+                    archived_links.push((url, title));
+                    archived_links.push((archived, archived_title));
+                }
+                (Some(archived), WaybackMode::Replace) => {
+                    let title = if title.is_empty() { url } else { title };
+                    archived_links.push((archived, title));
+                }
+                (None, _) => archived_links.push((url, title)),
+            }
+        }
+        archived_links
+    }
+}
+
+/// Wayback Machine URL for `url` as of `date`
+///
+/// `date` is an ISO 8601 date or timestamp.  Without a usable date the URL
+/// leads to the latest snapshot.
+pub fn wayback_url(url: &str, date: &str) -> String {
+    match wayback_timestamp(date) {
+        Some(timestamp) => format!("{}{}/{}", WAYBACK_PREFIX, timestamp, url),
+        None => format!("{}{}", WAYBACK_PREFIX, url),
+    }
+}
+
+/// `YYYYMMDD` from the start of an ISO 8601 date
+fn wayback_timestamp(date: &str) -> Option<String> {
+    let day = date.get(..10)?;
+    let bytes = day.as_bytes();
+    let is_date = bytes[4] == b'-'
+        && bytes[7] == b'-'
+        && day
+            .chars()
+            .enumerate()
+            .all(|(index, c)| index == 4 || index == 7 || c.is_ascii_digit());
+    is_date.then(|| day.replace('-', ""))
+}
+
+/// Is this an http(s) link to somewhere other than the Wayback Machine?
+pub fn is_external_link(href: &str) -> bool {
+    let rest = match href
+        .strip_prefix("http://")
+        .or_else(|| href.strip_prefix("https://"))
+    {
+        Some(rest) => rest,
+        None => return false,
+    };
+    let host = rest.split(['/', '?', '#']).next().unwrap_or_default();
+    !host.is_empty() && host != "web.archive.org" && host != "archive.org"
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::Comment;
+    use crate::stub_server::{StubResponse, StubServer};
+    use crate::test_helpers::{link, paragraph, text};
+
+    fn post() -> PostData {
+        PostData {
+            date: String::from("2013-09-05T21:03:42Z"),
+            body: paragraph(vec![text("See "), link("http://example.com/a", "this")]),
+            links: vec![(String::from("http://example.com/a"), String::from("A"))],
+            comments: vec![Comment {
+                date: String::from("2014-01-02T03:04:05Z"),
+                body: paragraph(vec![
+                    text("See "),
+                    link("http://example.com/b", "http://example.com/b"),
+                ]),
+                ..Default::default()
+            }],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_wayback_url() {
+        assert_eq!(
+            wayback_url("http://example.com/a?b=1", "2013-09-05T21:03:42Z"),
+            "https://web.archive.org/web/20130905/http://example.com/a?b=1"
+        );
+        assert_eq!(
+            wayback_url("http://example.com/", ""),
+            "https://web.archive.org/web/http://example.com/"
+        );
+        assert_eq!(
+            wayback_url("http://example.com/", "Sep 5, 2013"),
+            "https://web.archive.org/web/http://example.com/"
+        );
+    }
+
+    #[test]
+    fn test_is_external_link() {
+        assert!(is_external_link("http://example.com/"));
+        assert!(is_external_link("https://plus.google.com/+WingWong"));
+        assert!(!is_external_link("/posts/2013-09-05-a/"));
+        assert!(!is_external_link("mailto:me@example.com"));
+        assert!(!is_external_link(
+            "https://web.archive.org/web/2013/http://example.com/"
+        ));
+    }
+
+    #[test]
+    fn test_add_archive_links_companion() {
+        let mut post = post();
+        Wayback::new(WaybackMode::Companion).add_archive_links(&mut post);
+
+        assert_eq!(
+            post.content(),
+            "See [this](http://example.com/a) \
+             ([archived](https://web.archive.org/web/20130905/http://example.com/a))"
+        );
+        assert_eq!(
+            post.links,
+            vec![
+                (String::from("http://example.com/a"), String::from("A")),
+                (
+                    String::from("https://web.archive.org/web/20130905/http://example.com/a"),
+                    String::from("A (archived)")
+                ),
+            ]
+        );
+        // Comments use their own date
+        assert_eq!(
+            post.comments[0].content(),
+            "See <http://example.com/b> \
+             ([archived](https://web.archive.org/web/20140102/http://example.com/b))"
+        );
+    }
+
+    #[test]
+    fn test_add_archive_links_replace() {
+        let mut post = post();
+        Wayback::new(WaybackMode::Replace).add_archive_links(&mut post);
+
+        assert_eq!(
+            post.content(),
+            "See [this](https://web.archive.org/web/20130905/http://example.com/a)"
+        );
+        assert_eq!(
+            post.links,
+            vec![(
+                String::from("https://web.archive.org/web/20130905/http://example.com/a"),
+                String::from("A")
+            )]
+        );
+        assert_eq!(
+            post.comments[0].content(),
+            "See [http://example.com/b](https://web.archive.org/web/20140102/http://example.com/b)"
+        );
+    }
+
+    #[test]
+    fn test_availability_check() {
+        let server = StubServer::start(|target| {
+            if target.contains("example.com%2Fa") {
+                StubResponse::ok(
+                    r#"{"url": "http://example.com/a", "archived_snapshots": {"closest":
+                        {"status": "200", "available": true,
+                         "url": "http://web.archive.org/web/20130901000000/http://example.com/a",
+                         "timestamp": "20130901000000"}}}"#,
+                )
+            } else {
+                StubResponse::ok(r#"{"url": "http://example.com/b", "archived_snapshots": {}}"#)
+            }
+        });
+        let endpoint = format!("{}/wayback/available", server.url);
+        let mut wayback = Wayback::new(WaybackMode::Replace).with_availability_check(&endpoint);
+        let mut post = post();
+        wayback.add_archive_links(&mut post);
+
+        assert_eq!(
+            post.content(),
+            "See [this](http://web.archive.org/web/20130901000000/http://example.com/a)"
+        );
+        // No snapshot, no archive link
+        assert_eq!(post.comments[0].content(), "See <http://example.com/b>");
+
+        // The body and links list share one lookup
+        let requests = server.requests();
+        assert_eq!(requests.len(), 2);
+        assert_eq!(
+            requests[0],
+            "/wayback/available?url=http%3A%2F%2Fexample.com%2Fa&timestamp=20130905"
+        );
+    }
+
+    #[test]
+    fn test_availability_check_unreachable() {
+        // Nothing listens on the discard port
+        let mut wayback = Wayback::new(WaybackMode::Replace)
+            .with_availability_check("http://127.0.0.1:9/wayback/available");
+        assert_eq!(
+            wayback.archived_url("http://example.com/a", "2013-09-05"),
+            Some(String::from(
+                "https://web.archive.org/web/20130905/http://example.com/a"
+            ))
+        );
+    }
+}